@@ -9,7 +9,9 @@ fn main() {
         //
         // If you don't specify a PID, a system-wide counter is allocated.
         .attach_to(vec![0])
-        .allocate("inst_retired.any")
+        //
+        // Generic events are resolved to the native event for the running CPU.
+        .allocate_generic(GenericEvent::Instructions)
         .expect("failed to allocate PMC");
 
     // Start the counter.
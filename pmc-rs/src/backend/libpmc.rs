@@ -0,0 +1,386 @@
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
+
+use std::os::unix::io::RawFd;
+
+use pmc_sys::{
+    pmc_allocate, pmc_attach, pmc_close_logfile, pmc_configure_logfile, pmc_cpuinfo, pmc_detach,
+    pmc_event_names_of_class, pmc_flush_logfile, pmc_init, pmc_mode_PMC_MODE_SC,
+    pmc_mode_PMC_MODE_SS, pmc_mode_PMC_MODE_TC, pmc_mode_PMC_MODE_TS, pmc_name_of_class, pmc_read,
+    pmc_release, pmc_rw, pmc_start, pmc_stop, PMC_F_DESCENDANTS,
+};
+
+use super::{AllocSpec, Backend, EventInfo, Mode, PmcId, SharedLog};
+use crate::cpuid::CpuId;
+use crate::event::{GenericEvent, RawEvent};
+use crate::spec::{EventSpec, Qualifier};
+
+static PMC_INIT: Once = Once::new();
+static PMC_INIT_ERRNO: AtomicI32 = AtomicI32::new(0);
+
+// The lower-cased names of the events libpmc supports, listed on first use
+// rather than on every raw allocation.
+lazy_static! {
+    static ref PMC_EVENT_NAMES: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+}
+
+// pmc_allocate and pmc_release update tables in libpmc that are not thread
+// safe, so calls to them are serialised.
+lazy_static! {
+    static ref PMC_ALLOC_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Take the allocation lock.
+///
+/// The lock guards no data, so a panic while it was held cannot leave
+/// anything inconsistent, and a poisoned lock is used as normal.
+fn alloc_lock() -> MutexGuard<'static, ()> {
+    PMC_ALLOC_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// hwpmc has a single log file per process, shared by every LibPmc backend.
+lazy_static! {
+    static ref PMC_LOG: Mutex<SharedLog> = Mutex::new(SharedLog::default());
+}
+
+fn log() -> MutexGuard<'static, SharedLog> {
+    PMC_LOG.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A [`Backend`] using [`libpmc`] and the [`hwpmc`] kernel module on FreeBSD.
+///
+/// [`libpmc`]: https://www.freebsd.org/cgi/man.cgi?query=pmc
+/// [`hwpmc`]: https://www.freebsd.org/cgi/man.cgi?query=hwpmc
+#[derive(Debug, Default)]
+pub struct LibPmc {
+    _private: (),
+}
+
+impl LibPmc {
+    /// Returns true if libpmc has an event called `name`.
+    fn has_event(&self, name: &str) -> bool {
+        let mut names = PMC_EVENT_NAMES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if names.is_none() {
+            // Listing fails before hwpmc is initialised, so is retried.
+            let events = match self.events() {
+                Ok(v) => v,
+                Err(_) => return false,
+            };
+            *names = Some(
+                events
+                    .into_iter()
+                    .map(|e| e.name.to_ascii_lowercase())
+                    .collect(),
+            );
+        }
+
+        names
+            .as_ref()
+            .map(|names| names.contains(&name.to_ascii_lowercase()))
+            .unwrap_or(false)
+    }
+}
+
+/// Returns the qualifiers supported by the events of a PMC class.
+fn class_qualifiers(class: &str) -> Vec<String> {
+    let qualifiers: &[&str] = match class {
+        // Intel programmable counters
+        "IAP" => &["usr", "os", "edge", "inv", "anythread", "cmask", "umask"],
+        // Intel fixed-function counters
+        "IAF" => &["usr", "os", "anythread"],
+        _ => &["usr", "os"],
+    };
+    qualifiers.iter().map(|q| q.to_string()).collect()
+}
+
+/// Rewrite a raw `rNNNN` event spec into the libpmc `event=0xNN,umask=0xNN`
+/// syntax, leaving named events unchanged.
+///
+/// The `event=` and `umask=` terms are those of the perfmon event
+/// descriptions libpmc is built with, parsed by `pmu_parse_event` in
+/// [`libpmc_pmu_util.c`], and the remaining qualifiers are those of the Intel
+/// programmable counters described in [`pmc.core(3)`].
+///
+/// Only names in the form a `RawEvent` converts to are rewritten, and only if
+/// libpmc has no event of the same name.
+///
+/// [`libpmc_pmu_util.c`]: https://cgit.freebsd.org/src/tree/lib/libpmc/libpmc_pmu_util.c
+/// [`pmc.core(3)`]: https://www.freebsd.org/cgi/man.cgi?query=pmc.core
+fn raw_event_spec(backend: &LibPmc, event: &str) -> String {
+    let spec = match EventSpec::parse(event) {
+        Ok(v) => v,
+        Err(_) => return event.to_string(),
+    };
+
+    let raw = match RawEvent::from_spec(&spec) {
+        Some(v) => v,
+        None => return event.to_string(),
+    };
+
+    if backend.has_event(spec.name()) {
+        return event.to_string();
+    }
+
+    let mut out = format!(
+        "event={:#x},umask={:#x}",
+        raw.event_select(),
+        raw.unit_mask()
+    );
+    if raw.counter_mask() != 0 {
+        out.push_str(&format!(",cmask={}", raw.counter_mask()));
+    }
+    for (set, qualifier) in &[
+        (raw.is_inverted(), "inv"),
+        (raw.is_edge(), "edge"),
+        (raw.is_any_thread(), "anythread"),
+    ] {
+        if *set {
+            out.push(',');
+            out.push_str(qualifier);
+        }
+    }
+    for q in spec.qualifiers() {
+        out.push_str(&format!(",{}", q));
+    }
+    out
+}
+
+/// Returns the IDs of the online CPUs.
+///
+/// CPU IDs are not necessarily contiguous, so rather than assuming
+/// `0..ncpus` the IDs are read from the root group of the scheduler topology.
+pub(crate) fn online_cpus() -> io::Result<Vec<i32>> {
+    let spec = sysctl_string("kern.sched.topology_spec")?;
+    parse_topology_spec(&spec)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid CPU topology"))
+}
+
+/// Parse the CPU list of the root group of a `kern.sched.topology_spec`
+/// document, such as `<cpu count="4" mask="f,0,0,0">0, 1, 2, 3</cpu>`.
+fn parse_topology_spec(spec: &str) -> Option<Vec<i32>> {
+    let cpu = &spec[spec.find("<cpu")?..];
+    let list = &cpu[cpu.find('>')? + 1..cpu.find("</cpu>")?];
+    list.split(',').map(|id| id.trim().parse().ok()).collect()
+}
+
+fn sysctl_string(name: &str) -> io::Result<String> {
+    let name = CString::new(name).unwrap();
+
+    let mut len = 0;
+    let ret = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr(),
+            std::ptr::null_mut(),
+            &mut len,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut buf = vec![0u8; len];
+    let ret = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The length includes the NUL terminator.
+    buf.truncate(buf[..len].iter().position(|&b| b == 0).unwrap_or(len));
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn check(ret: i32) -> io::Result<()> {
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Backend for LibPmc {
+    fn init(&self) -> io::Result<()> {
+        PMC_INIT.call_once(|| {
+            if unsafe { pmc_init() } != 0 {
+                let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+                PMC_INIT_ERRNO.store(errno, Ordering::SeqCst);
+            }
+        });
+
+        match PMC_INIT_ERRNO.load(Ordering::SeqCst) {
+            0 => Ok(()),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+
+    fn allocate(&self, spec: &AllocSpec) -> io::Result<PmcId> {
+        // Translate the software event names hwpmc supports, and raw events,
+        // into its own naming scheme.
+        let event = match spec.event.as_str() {
+            "page-faults" => "PAGE_FAULT.ALL".to_string(),
+            v => raw_event_spec(self, v),
+        };
+
+        // hwpmc cannot exclude hypervisor or idle time.
+        if spec.exclude_hv || spec.exclude_idle {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+
+        // Restrict counting to user or kernel mode with the libpmc qualifiers,
+        // unless the spec already has them.
+        let parsed =
+            EventSpec::parse(&spec.event).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+        let qualified = parsed
+            .qualifiers()
+            .iter()
+            .any(|q| matches!(q, Qualifier::Usr | Qualifier::Os));
+        let event = match spec.mode_exclusions(&parsed)? {
+            (true, true) => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            _ if qualified => event,
+            (false, false) => event,
+            (false, true) => format!("{},usr", event),
+            (true, false) => format!("{},os", event),
+        };
+
+        let c_spec = CString::new(event)
+            .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        let mode = match spec.mode {
+            Mode::SystemCounting => pmc_mode_PMC_MODE_SC,
+            Mode::ProcessCounting => pmc_mode_PMC_MODE_TC,
+            Mode::SystemSampling => pmc_mode_PMC_MODE_SS,
+            Mode::ProcessSampling => pmc_mode_PMC_MODE_TS,
+        };
+
+        // hwpmc only reports descendant exits through the log file, which is
+        // not parsed.
+        if spec.track_exits {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+
+        let flags = if spec.descendants {
+            PMC_F_DESCENDANTS
+        } else {
+            0
+        };
+
+        let mut id = 0;
+        let _guard = alloc_lock();
+        check(unsafe {
+            pmc_allocate(
+                c_spec.as_ptr(),
+                mode,
+                flags,
+                spec.cpu,
+                &mut id,
+                spec.sample_rate,
+            )
+        })?;
+        Ok(id)
+    }
+
+    // The remaining calls are plain hwpmc system calls that touch no libpmc
+    // state, and the kernel module does its own locking, so they do not take
+    // the allocation lock.
+    fn attach(&self, id: PmcId, pid: i32) -> io::Result<()> {
+        check(unsafe { pmc_attach(id, pid) })
+    }
+
+    fn detach(&self, id: PmcId, pid: i32) -> io::Result<()> {
+        check(unsafe { pmc_detach(id, pid) })
+    }
+
+    fn start(&self, id: PmcId) -> io::Result<()> {
+        check(unsafe { pmc_start(id) })
+    }
+
+    fn stop(&self, id: PmcId) -> io::Result<()> {
+        check(unsafe { pmc_stop(id) })
+    }
+
+    fn read(&self, id: PmcId) -> io::Result<u64> {
+        let mut value: u64 = 0;
+        check(unsafe { pmc_read(id, &mut value) })?;
+        Ok(value)
+    }
+
+    fn write(&self, id: PmcId, value: u64) -> io::Result<u64> {
+        let mut old: u64 = 0;
+        check(unsafe { pmc_rw(id, value, &mut old) })?;
+        Ok(old)
+    }
+
+    fn release(&self, id: PmcId) -> io::Result<()> {
+        let _guard = alloc_lock();
+        check(unsafe { pmc_release(id) })
+    }
+
+    fn events(&self) -> io::Result<Vec<EventInfo>> {
+        let mut info: *const pmc_cpuinfo = std::ptr::null();
+        check(unsafe { pmc_cpuinfo(&mut info) })?;
+        let info = unsafe { &*info };
+
+        let mut events = vec![];
+        for class in &info.pm_classes[..info.pm_nclass as usize] {
+            let pmu = unsafe { CStr::from_ptr(pmc_name_of_class(class.pm_class)) }
+                .to_string_lossy()
+                .into_owned();
+
+            let mut names: *mut *const libc::c_char = std::ptr::null_mut();
+            let mut n: libc::c_int = 0;
+            check(unsafe { pmc_event_names_of_class(class.pm_class, &mut names, &mut n) })?;
+
+            // libpmc does not describe its events.
+            for i in 0..n as usize {
+                let name = unsafe { CStr::from_ptr(*names.add(i)) };
+                events.push(EventInfo {
+                    name: name.to_string_lossy().into_owned(),
+                    description: None,
+                    pmu: pmu.clone(),
+                    qualifiers: class_qualifiers(&pmu),
+                });
+            }
+
+            // The names array is allocated by libpmc, the names themselves
+            // are static.
+            unsafe { libc::free(names as *mut libc::c_void) };
+        }
+
+        Ok(events)
+    }
+
+    fn generic_event(&self, event: GenericEvent) -> io::Result<String> {
+        // libpmc uses the vendor event names, which are not all available on
+        // every model.
+        CpuId::current()
+            .and_then(|cpu| event.vendor_name(&cpu))
+            .filter(|name| self.has_event(name))
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    fn configure_log(&self, fd: RawFd) -> io::Result<()> {
+        log().open(fd, || check(unsafe { pmc_configure_logfile(fd) }))
+    }
+
+    fn flush_log(&self) -> io::Result<()> {
+        check(unsafe { pmc_flush_logfile() })
+    }
+
+    fn close_log(&self) -> io::Result<()> {
+        log().close(|| check(unsafe { pmc_close_logfile() }))
+    }
+}
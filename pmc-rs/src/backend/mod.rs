@@ -0,0 +1,497 @@
+//! Pluggable PMC backends.
+//!
+//! A [`Backend`] is the layer [`Counter`] uses to talk to the operating
+//! system's PMC interface. The default backend for the target platform is
+//! used unless one is explicitly configured with
+//! [`CounterBuilder::set_backend`].
+//!
+//! [`Counter`]: ../struct.Counter.html
+//! [`CounterBuilder::set_backend`]: ../struct.CounterBuilder.html#method.set_backend
+
+use std::fmt::Debug;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+
+use crate::event::GenericEvent;
+use crate::spec::{EventSpec, Qualifier};
+
+#[cfg(target_os = "freebsd")]
+mod libpmc;
+#[cfg(target_os = "freebsd")]
+pub use libpmc::*;
+
+mod simulated;
+pub use simulated::*;
+
+mod unsupported;
+pub use unsupported::*;
+
+#[cfg(target_os = "linux")]
+mod perf;
+#[cfg(target_os = "linux")]
+pub use perf::*;
+
+// The FreeBSD errno values libpmc reports without a Linux equivalent.
+#[cfg(target_os = "freebsd")]
+pub use libc::{EDOOFUS, EPROGMISMATCH};
+
+/// The FreeBSD `EDOOFUS` errno value ("Programming error").
+#[cfg(not(target_os = "freebsd"))]
+pub const EDOOFUS: i32 = 88;
+
+/// The FreeBSD `EPROGMISMATCH` errno value ("Program version wrong").
+#[cfg(not(target_os = "freebsd"))]
+pub const EPROGMISMATCH: i32 = 74;
+
+/// The identifier of a PMC allocated by a [`Backend`].
+pub type PmcId = u32;
+
+/// A counter value, with the time the PMC was enabled and actually counting.
+///
+/// When more PMCs are enabled than the hardware can count at once, the kernel
+/// may multiplex them - `time_running` is then less than `time_enabled`, and
+/// the value can be scaled to estimate the full count.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimedValue {
+    /// The counter value.
+    pub value: u64,
+
+    /// Nanoseconds the PMC has been enabled.
+    pub time_enabled: u64,
+
+    /// Nanoseconds the PMC has been counting events.
+    pub time_running: u64,
+}
+
+/// The final counter value of a child process or thread of an attached target,
+/// recorded when it exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildExit {
+    /// The process ID of the child.
+    pub pid: i32,
+
+    /// The thread ID of the child.
+    pub tid: i32,
+
+    /// The number of events counted for the child over its lifetime.
+    pub value: u64,
+}
+
+/// An event that can be counted, as listed by [`Backend::events`].
+///
+/// [`Backend::events`]: trait.Backend.html#method.events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventInfo {
+    /// The event name, as passed to [`CounterBuilder::allocate`].
+    ///
+    /// [`CounterBuilder::allocate`]: ../struct.CounterBuilder.html#method.allocate
+    pub name: String,
+
+    /// A description of what the event counts, if known.
+    pub description: Option<String>,
+
+    /// The class of PMU (or `libpmc` PMC class) providing the event.
+    pub pmu: String,
+
+    /// The [qualifiers] that can be applied to the event.
+    ///
+    /// [qualifiers]: ../enum.Qualifier.html
+    pub qualifiers: Vec<String>,
+}
+
+/// The operating mode of an allocated PMC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Count events across the whole system.
+    SystemCounting,
+
+    /// Count events for the attached processes only.
+    ProcessCounting,
+
+    /// Sample events across the whole system.
+    SystemSampling,
+
+    /// Sample events for the attached processes only.
+    ProcessSampling,
+}
+
+impl Mode {
+    /// Returns true if the mode is system-scoped.
+    pub fn is_system(self) -> bool {
+        matches!(self, Mode::SystemCounting | Mode::SystemSampling)
+    }
+
+    /// Returns true if the mode is a counting (rather than sampling) mode.
+    pub fn is_counting(self) -> bool {
+        matches!(self, Mode::SystemCounting | Mode::ProcessCounting)
+    }
+}
+
+/// The parameters of a PMC allocation request passed to
+/// [`Backend::allocate`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct AllocSpec {
+    /// The event specification to count.
+    pub event: String,
+
+    /// The mode the PMC is allocated in.
+    pub mode: Mode,
+
+    /// The CPU the PMC is allocated on, or [`CPU_ANY`].
+    ///
+    /// [`CPU_ANY`]: ../constant.CPU_ANY.html
+    pub cpu: i32,
+
+    /// The number of events between samples for sampling modes, or 0 for
+    /// counting modes.
+    pub sample_rate: u64,
+
+    /// The PMC leading the group this PMC is a member of, if any.
+    ///
+    /// Backends that support hardware event groups schedule all members of a
+    /// group onto the PMU together.
+    pub group: Option<PmcId>,
+
+    /// Count events for all descendants of the attached processes, including
+    /// those started after the PMC was attached.
+    pub descendants: bool,
+
+    /// Record the final value of each descendant when it exits, retrieved
+    /// with [`Backend::child_exits`].
+    ///
+    /// [`Backend::child_exits`]: trait.Backend.html#method.child_exits
+    pub track_exits: bool,
+
+    /// Do not count events while the CPU is executing in user mode.
+    pub exclude_user: bool,
+
+    /// Do not count events while the CPU is executing in kernel mode.
+    pub exclude_kernel: bool,
+
+    /// Do not count events while the CPU is executing in the hypervisor.
+    pub exclude_hv: bool,
+
+    /// Do not count events while the CPU is idle.
+    pub exclude_idle: bool,
+}
+
+impl AllocSpec {
+    pub(crate) fn new(event: String, mode: Mode, cpu: i32) -> Self {
+        Self {
+            event,
+            mode,
+            cpu,
+            sample_rate: 0,
+            group: None,
+            descendants: false,
+            track_exits: false,
+            exclude_user: false,
+            exclude_kernel: false,
+            exclude_hv: false,
+            exclude_idle: false,
+        }
+    }
+
+    /// Returns whether user and kernel mode are excluded, combining the
+    /// exclusions of the spec with the `usr` and `os` qualifiers of `event`.
+    ///
+    /// A `usr` or `os` qualifier restricts counting to that mode, unless both
+    /// are given. Excluding a mode the qualifiers ask to count is a conflict
+    /// (see [`mode_conflict`]), and returns `EINVAL`.
+    ///
+    /// [`mode_conflict`]: #method.mode_conflict
+    pub(crate) fn mode_exclusions(&self, event: &EventSpec) -> io::Result<(bool, bool)> {
+        if self.mode_conflict(event).is_some() {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        let usr = event.qualifiers().contains(&Qualifier::Usr);
+        let os = event.qualifiers().contains(&Qualifier::Os);
+
+        let (mut exclude_user, mut exclude_kernel) = (self.exclude_user, self.exclude_kernel);
+        if usr != os {
+            exclude_user |= os;
+            exclude_kernel |= usr;
+        }
+        Ok((exclude_user, exclude_kernel))
+    }
+
+    /// Returns the index of the first qualifier of `event` asking to count a
+    /// mode the spec excludes, if any.
+    pub(crate) fn mode_conflict(&self, event: &EventSpec) -> Option<usize> {
+        event.qualifiers().iter().position(|q| match q {
+            Qualifier::Usr => self.exclude_user,
+            Qualifier::Os => self.exclude_kernel,
+            _ => false,
+        })
+    }
+}
+
+/// A `Backend` provides access to the PMC facilities of the system.
+///
+/// All methods mirror their [`libpmc`] equivalents, and report failures as an
+/// [`io::Error`] carrying the `errno` value `libpmc` would have set - the
+/// [`Counter`] maps these to an [`ErrorKind`], so backends behave consistently
+/// regardless of the underlying implementation.
+///
+/// Methods are called concurrently from any thread - backends wrapping
+/// interfaces that are not thread safe must serialise the calls themselves.
+///
+/// [`libpmc`]: https://www.freebsd.org/cgi/man.cgi?query=pmc
+/// [`Counter`]: ../struct.Counter.html
+/// [`ErrorKind`]: ../enum.ErrorKind.html
+pub trait Backend: Debug + Send + Sync {
+    /// Initialise the backend.
+    ///
+    /// This is called before every allocation and must be idempotent.
+    fn init(&self) -> io::Result<()>;
+
+    /// Allocate a new PMC described by `spec`.
+    fn allocate(&self, spec: &AllocSpec) -> io::Result<PmcId>;
+
+    /// Attach the PMC to the process `pid`.
+    ///
+    /// PID 0 refers to the calling process.
+    fn attach(&self, id: PmcId, pid: i32) -> io::Result<()>;
+
+    /// Detach the PMC from the process `pid`.
+    fn detach(&self, id: PmcId, pid: i32) -> io::Result<()>;
+
+    /// Start counting events.
+    fn start(&self, id: PmcId) -> io::Result<()>;
+
+    /// Stop counting events.
+    fn stop(&self, id: PmcId) -> io::Result<()>;
+
+    /// Read the current counter value.
+    fn read(&self, id: PmcId) -> io::Result<u64>;
+
+    /// Set the counter value to `value`, returning the previous value.
+    fn write(&self, id: PmcId, value: u64) -> io::Result<u64>;
+
+    /// Read the current counter value, along with the time it has been enabled
+    /// and running.
+    ///
+    /// Backends that multiplex PMCs in the kernel implement this, the default
+    /// implementation returns `ENOSYS`.
+    fn read_timed(&self, _id: PmcId) -> io::Result<TimedValue> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+
+    /// Return (and forget) the final values of the descendants that exited
+    /// since the last call, for PMCs allocated with
+    /// [`AllocSpec::track_exits`].
+    ///
+    /// The default implementation returns `ENOSYS`.
+    ///
+    /// [`AllocSpec::track_exits`]: struct.AllocSpec.html#structfield.track_exits
+    fn child_exits(&self, _id: PmcId) -> io::Result<Vec<ChildExit>> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+
+    /// Returns true if the process `pid` the PMC is attached to has exited.
+    ///
+    /// A zombie process awaiting its parent is considered to have exited.
+    ///
+    /// The default implementation checks whether a process with the PID
+    /// still exists, so reports the process as running if the PID is reused
+    /// after it exits. The perf backend avoids this by polling a pidfd opened
+    /// when attaching, while hwpmc only reports process exits through its log
+    /// file, so the libpmc backend uses the default implementation.
+    fn exited(&self, _id: PmcId, pid: i32) -> io::Result<bool> {
+        process_exited(pid)
+    }
+
+    /// List the events this backend can count on the running CPU.
+    ///
+    /// The default implementation returns `ENOSYS`.
+    fn events(&self) -> io::Result<Vec<EventInfo>> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+
+    /// Returns the native event spec counting `event`, or `ENOENT` if there is
+    /// no equivalent event.
+    ///
+    /// The default implementation returns the `perf(1)` name of the event.
+    fn generic_event(&self, event: GenericEvent) -> io::Result<String> {
+        Ok(event.name().to_string())
+    }
+
+    /// Release the PMC, freeing any resources held by it.
+    fn release(&self, id: PmcId) -> io::Result<()>;
+
+    /// Start all the PMCs in `ids` together.
+    ///
+    /// `ids` starts with the group leader, followed by its members. The
+    /// default implementation starts each PMC in turn.
+    fn start_group(&self, ids: &[PmcId]) -> io::Result<()> {
+        ids.iter().try_for_each(|&id| self.start(id))
+    }
+
+    /// Stop all the PMCs in `ids` together.
+    ///
+    /// The default implementation stops each PMC in turn.
+    fn stop_group(&self, ids: &[PmcId]) -> io::Result<()> {
+        ids.iter().try_for_each(|&id| self.stop(id))
+    }
+
+    /// Read the values of all the PMCs in `ids` at the same point in time.
+    ///
+    /// Backends return `ENOSPC` for a group that was enabled, but never
+    /// scheduled on the PMU, rather than values that were never counted. The
+    /// default implementation reads each PMC in turn.
+    fn read_group(&self, ids: &[PmcId]) -> io::Result<Vec<u64>> {
+        ids.iter().map(|&id| self.read(id)).collect()
+    }
+
+    /// Configure the log file that sampling PMCs record to.
+    ///
+    /// A backend has a single log file (for [`hwpmc`], one per process) so
+    /// the log is reference counted: configuring the file already in use adds
+    /// a user, while configuring a different file fails with `EBUSY` until
+    /// every user has closed the log.
+    ///
+    /// The default implementation returns `ENOSYS` for backends that do not
+    /// support sampling.
+    ///
+    /// [`hwpmc`]: https://www.freebsd.org/cgi/man.cgi?query=hwpmc
+    fn configure_log(&self, _fd: RawFd) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+
+    /// Flush any buffered samples to the log file.
+    fn flush_log(&self) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+
+    /// Flush and close the log file, once every user that configured it has
+    /// closed it.
+    fn close_log(&self) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+}
+
+/// Tracks the users of a backend's log file, see [`Backend::configure_log`].
+///
+/// [`Backend::configure_log`]: trait.Backend.html#method.configure_log
+#[derive(Debug, Default)]
+pub(crate) struct SharedLog {
+    // The device and inode of the configured file.
+    file: Option<(u64, u64)>,
+    users: usize,
+}
+
+impl SharedLog {
+    /// Add a user of the log file `fd`, calling `configure` if the log is
+    /// not already configured.
+    pub(crate) fn open(
+        &mut self,
+        fd: RawFd,
+        configure: impl FnOnce() -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = (stat.st_dev as u64, stat.st_ino as u64);
+
+        match self.file {
+            Some(f) if f == file => (),
+            Some(_) => return Err(io::Error::from_raw_os_error(libc::EBUSY)),
+            None => {
+                configure()?;
+                self.file = Some(file);
+            }
+        }
+
+        self.users += 1;
+        Ok(())
+    }
+
+    /// Remove a user of the log file, calling `close` once it has no users.
+    pub(crate) fn close(&mut self, close: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+        match self.users {
+            0 => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            1 => close()?,
+            _ => (),
+        }
+
+        self.users -= 1;
+        if self.users == 0 {
+            self.file = None;
+        }
+        Ok(())
+    }
+
+    /// Returns true if a log file is configured.
+    pub(crate) fn is_configured(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+/// Returns true if the process `pid` no longer exists, or is a zombie.
+pub(crate) fn process_exited(pid: i32) -> io::Result<bool> {
+    // PID 0 is the calling process.
+    if pid == 0 {
+        return Ok(false);
+    }
+
+    if unsafe { libc::kill(pid, 0) } != 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ESRCH) => Ok(true),
+            // The process exists, but belongs to another user.
+            Some(libc::EPERM) => Ok(false),
+            _ => Err(err),
+        };
+    }
+
+    // The state follows the command name, which may itself contain spaces or
+    // parentheses.
+    #[cfg(target_os = "linux")]
+    {
+        if let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            let state = stat.rsplit(')').next().and_then(|s| s.trim_start().chars().next());
+            return Ok(matches!(state, Some('Z') | Some('X')));
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(target_os = "freebsd")]
+lazy_static! {
+    static ref DEFAULT_BACKEND: Arc<dyn Backend> = Arc::new(LibPmc::default());
+}
+
+#[cfg(target_os = "linux")]
+lazy_static! {
+    static ref DEFAULT_BACKEND: Arc<dyn Backend> = Arc::new(Perf::default());
+}
+
+#[cfg(not(any(target_os = "freebsd", target_os = "linux")))]
+lazy_static! {
+    static ref DEFAULT_BACKEND: Arc<dyn Backend> = Arc::new(Unsupported::default());
+}
+
+/// Returns the default [`Backend`] for the target platform.
+///
+/// This is [`LibPmc`] on FreeBSD, [`Perf`] on Linux, and [`Unsupported`] on
+/// all other platforms.
+pub fn default_backend() -> Arc<dyn Backend> {
+    Arc::clone(&DEFAULT_BACKEND)
+}
+
+/// Returns true if the default backend is able to allocate counters on this
+/// system.
+///
+/// This allows callers to degrade gracefully when PMCs are unavailable, such as
+/// when the [`hwpmc`] kernel module is not loaded, `perf_event_open(2)` is
+/// blocked, or the platform is not supported at all.
+///
+/// [`hwpmc`]: https://www.freebsd.org/cgi/man.cgi?query=hwpmc
+pub fn is_available() -> bool {
+    DEFAULT_BACKEND.init().is_ok()
+}
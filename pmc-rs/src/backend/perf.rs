@@ -0,0 +1,918 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
+
+use super::{process_exited, AllocSpec, Backend, ChildExit, EventInfo, PmcId, TimedValue};
+use crate::catalog::{
+    qualifier_term, read_sysfs_events, resolve_sysfs_event, resolve_sysfs_terms, SysfsEncoding,
+    SYSFS_EVENT_SOURCES,
+};
+use crate::{EventSpec, GenericEvent, CPU_ANY};
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_TYPE_HW_CACHE: u32 = 3;
+const PERF_TYPE_RAW: u32 = 4;
+
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_REFERENCES: u64 = 2;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+const PERF_COUNT_HW_BRANCH_INSTRUCTIONS: u64 = 4;
+const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+const PERF_COUNT_HW_BUS_CYCLES: u64 = 6;
+const PERF_COUNT_HW_STALLED_CYCLES_FRONTEND: u64 = 7;
+const PERF_COUNT_HW_STALLED_CYCLES_BACKEND: u64 = 8;
+const PERF_COUNT_HW_REF_CPU_CYCLES: u64 = 9;
+
+// PERF_TYPE_HW_CACHE configs are the cache id, operation << 8 and result << 16.
+const PERF_COUNT_HW_CACHE_L1D_READ_ACCESS: u64 = 0;
+const PERF_COUNT_HW_CACHE_L1D_READ_MISS: u64 = 1 << 16;
+const PERF_COUNT_HW_CACHE_LL_READ_ACCESS: u64 = 2;
+const PERF_COUNT_HW_CACHE_LL_READ_MISS: u64 = 2 | 1 << 16;
+const PERF_COUNT_HW_CACHE_DTLB_READ_MISS: u64 = 3 | 1 << 16;
+
+const PERF_COUNT_SW_CPU_CLOCK: u64 = 0;
+const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
+const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+const PERF_COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+const PERF_COUNT_SW_CPU_MIGRATIONS: u64 = 4;
+const PERF_COUNT_SW_PAGE_FAULTS_MIN: u64 = 5;
+const PERF_COUNT_SW_PAGE_FAULTS_MAJ: u64 = 6;
+
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
+const PERF_ATTR_FLAG_DISABLED: u64 = 1 << 0;
+const PERF_ATTR_FLAG_INHERIT: u64 = 1 << 1;
+const PERF_ATTR_FLAG_EXCLUDE_USER: u64 = 1 << 4;
+const PERF_ATTR_FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const PERF_ATTR_FLAG_EXCLUDE_HV: u64 = 1 << 6;
+const PERF_ATTR_FLAG_EXCLUDE_IDLE: u64 = 1 << 7;
+const PERF_ATTR_FLAG_INHERIT_STAT: u64 = 1 << 11;
+
+const PERF_RECORD_READ: u32 = 8;
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+const PERF_EVENT_IOC_RESET: libc::c_ulong = 0x2403;
+
+const PERF_IOC_FLAG_GROUP: libc::c_ulong = 1;
+
+/// The number of data pages in each ring buffer - must be a power of 2.
+const RING_BUFFER_PAGES: usize = 8;
+
+// Offsets of the data_head and data_tail fields in `struct
+// perf_event_mmap_page`.
+const RING_BUFFER_HEAD: usize = 1024;
+const RING_BUFFER_TAIL: usize = 1032;
+
+static PERF_INIT: Once = Once::new();
+static PERF_INIT_ERRNO: AtomicI32 = AtomicI32::new(0);
+
+/// `struct perf_event_attr`, as of `PERF_ATTR_SIZE_VER5`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved_2: u16,
+}
+
+/// The generic hardware and software events named by `perf(1)`.
+#[rustfmt::skip]
+const GENERIC_EVENTS: &[(&str, u32, u64, &str)] = &[
+    ("cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES, "CPU cycles"),
+    ("cpu-cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES, "CPU cycles"),
+    ("instructions", PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS, "Instructions retired"),
+    ("cache-references", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_REFERENCES, "Cache accesses, usually last level cache"),
+    ("cache-misses", PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_MISSES, "Cache misses, usually last level cache"),
+    ("branches", PERF_TYPE_HARDWARE, PERF_COUNT_HW_BRANCH_INSTRUCTIONS, "Branch instructions retired"),
+    ("branch-instructions", PERF_TYPE_HARDWARE, PERF_COUNT_HW_BRANCH_INSTRUCTIONS, "Branch instructions retired"),
+    ("branch-misses", PERF_TYPE_HARDWARE, PERF_COUNT_HW_BRANCH_MISSES, "Mispredicted branch instructions"),
+    ("bus-cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_BUS_CYCLES, "Bus cycles"),
+    ("stalled-cycles-frontend", PERF_TYPE_HARDWARE, PERF_COUNT_HW_STALLED_CYCLES_FRONTEND, "Stalled cycles during issue"),
+    ("stalled-cycles-backend", PERF_TYPE_HARDWARE, PERF_COUNT_HW_STALLED_CYCLES_BACKEND, "Stalled cycles during retirement"),
+    ("ref-cycles", PERF_TYPE_HARDWARE, PERF_COUNT_HW_REF_CPU_CYCLES, "CPU cycles, unaffected by frequency scaling"),
+    ("L1-dcache-loads", PERF_TYPE_HW_CACHE, PERF_COUNT_HW_CACHE_L1D_READ_ACCESS, "L1 data cache loads"),
+    ("L1-dcache-load-misses", PERF_TYPE_HW_CACHE, PERF_COUNT_HW_CACHE_L1D_READ_MISS, "L1 data cache load misses"),
+    ("LLC-loads", PERF_TYPE_HW_CACHE, PERF_COUNT_HW_CACHE_LL_READ_ACCESS, "Last level cache loads"),
+    ("LLC-load-misses", PERF_TYPE_HW_CACHE, PERF_COUNT_HW_CACHE_LL_READ_MISS, "Last level cache load misses"),
+    ("dTLB-load-misses", PERF_TYPE_HW_CACHE, PERF_COUNT_HW_CACHE_DTLB_READ_MISS, "Data TLB load misses"),
+    ("cpu-clock", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_CLOCK, "Nanoseconds elapsed on the per-CPU timer"),
+    ("task-clock", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_TASK_CLOCK, "Nanoseconds of CPU time consumed by the task"),
+    ("page-faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS, "Page faults"),
+    ("faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS, "Page faults"),
+    ("context-switches", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CONTEXT_SWITCHES, "Context switches"),
+    ("cs", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CONTEXT_SWITCHES, "Context switches"),
+    ("cpu-migrations", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_MIGRATIONS, "Migrations between CPUs"),
+    ("migrations", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_MIGRATIONS, "Migrations between CPUs"),
+    ("minor-faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS_MIN, "Page faults resolved without I/O"),
+    ("major-faults", PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS_MAJ, "Page faults requiring I/O"),
+];
+
+/// Resolve an event spec into its `perf_event_attr` type and config values.
+///
+/// The generic hardware and software event names used by `perf(1)` are
+/// supported, as are raw events in the `rNNNN` form, the `pmu/event` events
+/// listed in sysfs, and `pmu/term=value,.../` events built from the PMU's
+/// format terms.
+///
+/// Qualifiers other than `usr` and `os` are applied to PMU events through the
+/// PMU's format terms, and are rejected for all other events.
+fn resolve_event(spec: &EventSpec) -> io::Result<SysfsEncoding> {
+    let name = spec.name();
+    let dir = Path::new(SYSFS_EVENT_SOURCES);
+
+    let enc = if let Some((pmu, event)) = name.split_once('/') {
+        let event = event.strip_suffix('/').unwrap_or(event);
+        let mut enc = if event.contains(&['=', ','][..]) {
+            resolve_sysfs_terms(dir, pmu, event)?
+        } else {
+            resolve_sysfs_event(dir, pmu, event)?
+        };
+        for q in spec.qualifiers() {
+            if let Some((term, value)) = qualifier_term(q)? {
+                enc.set(dir, pmu, term, value)
+                    .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+            }
+        }
+        return Ok(enc);
+    } else if let Some(&(_, type_, config, _)) = GENERIC_EVENTS.iter().find(|e| e.0 == name) {
+        SysfsEncoding {
+            type_,
+            config: [config, 0, 0],
+        }
+    } else {
+        let raw = name
+            .strip_prefix('r')
+            .and_then(|raw| u64::from_str_radix(raw, 16).ok())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        SysfsEncoding {
+            type_: PERF_TYPE_RAW,
+            config: [raw, 0, 0],
+        }
+    };
+
+    if spec
+        .qualifiers()
+        .iter()
+        .any(|q| !matches!(qualifier_term(q), Ok(None)))
+    {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    Ok(enc)
+}
+
+/// Returns the list of online CPUs.
+pub(crate) fn online_cpus() -> io::Result<Vec<i32>> {
+    let list = fs::read_to_string("/sys/devices/system/cpu/online")?;
+    parse_cpu_list(list.trim())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid online CPU list"))
+}
+
+/// Open a pidfd referring to the process `pid`.
+fn pidfd_open(pid: i32) -> io::Result<File> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+/// Parse a kernel CPU list such as `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<i32>> {
+    let mut cpus = vec![];
+    for range in list.split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(start.parse::<i32>().ok()?..=end.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Map the errno values returned by `perf_event_open(2)` to those `libpmc`
+/// would return in the same situation.
+fn to_pmc_error(err: io::Error) -> io::Error {
+    match err.raw_os_error() {
+        Some(libc::ENOENT) | Some(libc::EOPNOTSUPP) => io::Error::from_raw_os_error(libc::EINVAL),
+        Some(libc::EACCES) => io::Error::from_raw_os_error(libc::EPERM),
+        _ => err,
+    }
+}
+
+/// A memory-mapped perf ring buffer, used to receive the `PERF_RECORD_READ`
+/// records emitted when an inherited event's task exits.
+#[derive(Debug)]
+struct RingBuffer {
+    base: *mut u8,
+    len: usize,
+    page_size: usize,
+}
+
+// The mapping is owned by the RingBuffer, and only accessed through &mut self
+// (or atomically).
+unsafe impl Send for RingBuffer {}
+
+impl RingBuffer {
+    fn map(file: &File) -> io::Result<Self> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = page_size * (RING_BUFFER_PAGES + 1);
+
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            base: base as *mut u8,
+            len,
+            page_size,
+        })
+    }
+
+    fn control(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.base.add(offset) as *const AtomicU64) }
+    }
+
+    /// Copy `buf.len()` bytes starting at data offset `offset`, wrapping
+    /// around the end of the buffer.
+    fn copy(&self, offset: u64, buf: &mut [u8]) {
+        let size = (self.len - self.page_size) as u64;
+        for (i, b) in buf.iter_mut().enumerate() {
+            let at = (offset + i as u64) % size;
+            *b = unsafe { *self.base.add(self.page_size + at as usize) };
+        }
+    }
+
+    fn u32_at(&self, offset: u64) -> u32 {
+        let mut b = [0u8; 4];
+        self.copy(offset, &mut b);
+        u32::from_ne_bytes(b)
+    }
+
+    fn u64_at(&self, offset: u64) -> u64 {
+        let mut b = [0u8; 8];
+        self.copy(offset, &mut b);
+        u64::from_ne_bytes(b)
+    }
+
+    /// Consume all records in the buffer, appending the child exits to
+    /// `exits`.
+    fn drain(&mut self, exits: &mut Vec<ChildExit>) {
+        let head = self.control(RING_BUFFER_HEAD).load(Ordering::Acquire);
+        let mut tail = self.control(RING_BUFFER_TAIL).load(Ordering::Relaxed);
+
+        while tail < head {
+            // struct perf_event_header { u32 type; u16 misc; u16 size; }
+            let header = self.u64_at(tail);
+            let type_ = header as u32;
+            let size = header >> 48;
+            if size == 0 {
+                break;
+            }
+
+            // struct { header; u32 pid, tid; u64 value, enabled, running; }
+            if type_ == PERF_RECORD_READ {
+                exits.push(ChildExit {
+                    pid: self.u32_at(tail + 8) as i32,
+                    tid: self.u32_at(tail + 12) as i32,
+                    value: self.u64_at(tail + 16),
+                });
+            }
+
+            tail += size;
+        }
+
+        self.control(RING_BUFFER_TAIL).store(head, Ordering::Release);
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.len) };
+    }
+}
+
+/// A single `perf_event_open(2)` file descriptor.
+#[derive(Debug)]
+struct EventFd {
+    file: File,
+    pid: i32,
+    cpu: i32,
+    ring: Option<RingBuffer>,
+}
+
+impl EventFd {
+    fn open(attr: &PerfEventAttr, pid: i32, cpu: i32, group_fd: RawFd) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                attr as *const PerfEventAttr,
+                pid,
+                cpu,
+                group_fd,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(to_pmc_error(io::Error::last_os_error()));
+        }
+
+        Ok(Self {
+            file: unsafe { File::from_raw_fd(fd as i32) },
+            pid,
+            cpu,
+            ring: None,
+        })
+    }
+
+    fn ioctl(&self, request: libc::c_ulong, flags: libc::c_ulong) -> io::Result<()> {
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Read the counter value, and the time enabled & running.
+    fn read(&self) -> io::Result<[u64; 3]> {
+        let mut buf = [0u8; 24];
+        (&self.file).read_exact(&mut buf)?;
+
+        let mut values = [0u64; 3];
+        for (v, chunk) in values.iter_mut().zip(buf.chunks_exact(8)) {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(chunk);
+            *v = u64::from_ne_bytes(b);
+        }
+        Ok(values)
+    }
+}
+
+/// The `(pid, cpu, fd)` tuples of a group leader's file descriptors.
+type GroupFds = Vec<(i32, i32, RawFd)>;
+
+#[derive(Debug)]
+struct Event {
+    attr: PerfEventAttr,
+    cpu: i32,
+    fds: Vec<EventFd>,
+    running: bool,
+    base: u64,
+    leader: Option<PmcId>,
+    track_exits: bool,
+    exits: Vec<ChildExit>,
+
+    // The pidfds of the attached processes, where the kernel supports them.
+    pidfds: HashMap<i32, File>,
+}
+
+impl Event {
+    /// Open the event for `pid` on each of `cpus`, as a member of the group
+    /// led by `group` (if any).
+    fn open(&mut self, pid: i32, cpus: &[i32], group: Option<&GroupFds>) -> io::Result<()> {
+        for &cpu in cpus {
+            // Group members must be opened against the leader's file
+            // descriptor for the same target.
+            let group_fd = match group {
+                Some(group) => group
+                    .iter()
+                    .find(|&&(p, c, _)| p == pid && c == cpu)
+                    .map(|&(_, _, fd)| fd)
+                    .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?,
+                None => -1,
+            };
+
+            let mut fd = EventFd::open(&self.attr, pid, cpu, group_fd)?;
+            if self.track_exits {
+                fd.ring = Some(RingBuffer::map(&fd.file)?);
+            }
+            if self.running {
+                fd.ioctl(PERF_EVENT_IOC_ENABLE, 0)?;
+            }
+            self.fds.push(fd);
+        }
+        Ok(())
+    }
+
+    fn ioctl(&self, request: libc::c_ulong) -> io::Result<()> {
+        self.fds.iter().try_for_each(|fd| fd.ioctl(request, 0))
+    }
+
+    /// Apply `request` to the whole group this event leads.
+    fn group_ioctl(&self, request: libc::c_ulong) -> io::Result<()> {
+        self.fds
+            .iter()
+            .try_for_each(|fd| fd.ioctl(request, PERF_IOC_FLAG_GROUP))
+    }
+
+    fn group_fds(&self) -> GroupFds {
+        self.fds
+            .iter()
+            .map(|fd| (fd.pid, fd.cpu, fd.file.as_raw_fd()))
+            .collect()
+    }
+
+    fn read(&self) -> io::Result<u64> {
+        self.read_timed().map(|v| v.value)
+    }
+
+    /// Returns true if the attached process `pid` has exited.
+    ///
+    /// Processes are polled through the pidfd opened when attaching, which
+    /// unlike checking the PID cannot be confused by the PID being reused.
+    /// Kernels without pidfds (before 5.3) fall back to checking the PID.
+    fn exited(&self, pid: i32) -> io::Result<bool> {
+        if !self.fds.iter().any(|fd| fd.pid == pid) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        let pidfd = match self.pidfds.get(&pid) {
+            Some(v) => v,
+            None => return process_exited(pid),
+        };
+
+        // A pidfd becomes readable once the process exits.
+        let mut poll = libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut poll, 1, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(poll.revents & libc::POLLIN != 0)
+    }
+
+    /// Collect the exit records from all ring buffers.
+    fn drain_exits(&mut self) {
+        for fd in &mut self.fds {
+            if let Some(ring) = fd.ring.as_mut() {
+                ring.drain(&mut self.exits);
+            }
+        }
+    }
+
+    /// Read the event, summing the values and times across all file
+    /// descriptors.
+    fn read_timed(&self) -> io::Result<TimedValue> {
+        let mut total = TimedValue {
+            value: self.base,
+            ..Default::default()
+        };
+        for fd in &self.fds {
+            let [value, enabled, running] = fd.read()?;
+            total.value = total.value.wrapping_add(value);
+            total.time_enabled += enabled;
+            total.time_running += running;
+        }
+        Ok(total)
+    }
+}
+
+/// A [`Backend`] using the Linux [`perf_event_open(2)`] interface.
+///
+/// System-scoped counters are opened as per-CPU events, with [`CPU_ANY`]
+/// opening an event on every online CPU and summing the results. Process-scoped
+/// counters are opened as per-task events as each PID is attached.
+///
+/// Attaching to PID 0 counts events for the calling thread.
+///
+/// When more events are enabled than the PMU can count at once, the kernel
+/// multiplexes them, reporting the time each was enabled and running through
+/// [`Backend::read_timed`].
+///
+/// Descendants are followed using `inherit`, with `inherit_stat` and a ring
+/// buffer per CPU used to record the value of each child as it exits.
+///
+/// Sampling PMCs are not supported by this backend.
+///
+/// [`perf_event_open(2)`]: https://man7.org/linux/man-pages/man2/perf_event_open.2.html
+/// [`CPU_ANY`]: ../constant.CPU_ANY.html
+/// [`Backend::read_timed`]: trait.Backend.html#method.read_timed
+#[derive(Debug, Default)]
+pub struct Perf {
+    events: Mutex<HashMap<PmcId, Event>>,
+    next_id: AtomicU32,
+}
+
+impl Perf {
+    /// Lock the event table.
+    ///
+    /// A panic while the table is locked cannot leave it inconsistent, so a
+    /// poisoned lock is used as normal.
+    fn events(&self) -> MutexGuard<'_, HashMap<PmcId, Event>> {
+        self.events.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn with_event<T>(&self, id: PmcId, f: impl FnOnce(&mut Event) -> io::Result<T>) -> io::Result<T> {
+        let mut events = self.events();
+        match events.get_mut(&id) {
+            Some(event) => f(event),
+            None => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+
+    /// Returns true if `ids` is a perf event group - a leader followed by all
+    /// of its members.
+    fn is_group(events: &HashMap<PmcId, Event>, ids: &[PmcId]) -> bool {
+        let (leader, members) = match ids.split_first() {
+            Some(v) => v,
+            None => return false,
+        };
+
+        events.get(leader).map(|e| e.leader.is_none()) == Some(true)
+            && members
+                .iter()
+                .all(|id| events.get(id).and_then(|e| e.leader) == Some(*leader))
+    }
+
+    fn lookup<'a>(events: &'a HashMap<PmcId, Event>, id: &PmcId) -> io::Result<&'a Event> {
+        events
+            .get(id)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+    }
+}
+
+impl Backend for Perf {
+    fn init(&self) -> io::Result<()> {
+        // Probe for perf_event_open(2) support by opening a software event for
+        // the calling thread - this fails if the kernel was built without perf
+        // support, or the syscall is blocked (e.g. by a seccomp policy).
+        PERF_INIT.call_once(|| {
+            let attr = PerfEventAttr {
+                type_: PERF_TYPE_SOFTWARE,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config: PERF_COUNT_SW_TASK_CLOCK,
+                flags: PERF_ATTR_FLAG_DISABLED,
+                ..Default::default()
+            };
+            if let Err(err) = EventFd::open(&attr, 0, CPU_ANY, -1) {
+                PERF_INIT_ERRNO.store(err.raw_os_error().unwrap_or(0), Ordering::SeqCst);
+            }
+        });
+
+        match PERF_INIT_ERRNO.load(Ordering::SeqCst) {
+            0 => Ok(()),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+
+    fn allocate(&self, spec: &AllocSpec) -> io::Result<PmcId> {
+        // Sampling PMCs are not supported.
+        if !spec.mode.is_counting() {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+
+        let event =
+            EventSpec::parse(&spec.event).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        let enc = resolve_event(&event)?;
+
+        // The usr and os qualifiers restrict counting to that mode, as they do
+        // for libpmc.
+        let (exclude_user, exclude_kernel) = spec.mode_exclusions(&event)?;
+
+        // Inherited events follow all children created after the event is
+        // opened, and with inherit_stat record the value of each as it exits.
+        let mut flags = PERF_ATTR_FLAG_DISABLED;
+        if spec.descendants || spec.track_exits {
+            flags |= PERF_ATTR_FLAG_INHERIT;
+        }
+        if spec.track_exits {
+            flags |= PERF_ATTR_FLAG_INHERIT_STAT;
+        }
+
+        let exclusions = [
+            (exclude_user, PERF_ATTR_FLAG_EXCLUDE_USER),
+            (exclude_kernel, PERF_ATTR_FLAG_EXCLUDE_KERNEL),
+            (spec.exclude_hv, PERF_ATTR_FLAG_EXCLUDE_HV),
+            (spec.exclude_idle, PERF_ATTR_FLAG_EXCLUDE_IDLE),
+        ];
+        for (exclude, flag) in exclusions.iter() {
+            if *exclude {
+                flags |= flag;
+            }
+        }
+
+        let attr = PerfEventAttr {
+            type_: enc.type_,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config: enc.config[0],
+            config1: enc.config[1],
+            config2: enc.config[2],
+            read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+            flags,
+            ..Default::default()
+        };
+
+        let mut event = Event {
+            attr,
+            cpu: spec.cpu,
+            fds: vec![],
+            running: false,
+            base: 0,
+            leader: spec.group,
+            track_exits: spec.track_exits,
+            exits: vec![],
+            pidfds: HashMap::new(),
+        };
+
+        // Group members are opened against the leader's file descriptors, so
+        // the lock is held while they are opened to keep the leader from being
+        // released. Other events are opened without the lock, so allocations
+        // on many threads do not serialise on the syscalls.
+        let mut held = None;
+        let group = match spec.group {
+            Some(leader) => {
+                let events = self.events();
+                let fds = Self::lookup(&events, &leader)?.group_fds();
+                held = Some(events);
+                Some(fds)
+            }
+            None => None,
+        };
+
+        // System-scoped counters are opened immediately, process-scoped
+        // counters are opened as they are attached.
+        //
+        // Opening (and immediately closing) the event for the calling thread
+        // reports unsupported events at allocation time, as libpmc does,
+        // rather than when attaching to a target.
+        if spec.mode.is_system() {
+            let cpus = if spec.cpu == CPU_ANY {
+                online_cpus()?
+            } else {
+                vec![spec.cpu]
+            };
+            event.open(-1, &cpus, group.as_ref())?;
+        } else {
+            EventFd::open(&event.attr, 0, spec.cpu, -1)?;
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        held.unwrap_or_else(|| self.events()).insert(id, event);
+        Ok(id)
+    }
+
+    fn attach(&self, id: PmcId, pid: i32) -> io::Result<()> {
+        let mut events = self.events();
+
+        let group = match Self::lookup(&events, &id)?.leader {
+            Some(leader) => Some(Self::lookup(&events, &leader)?.group_fds()),
+            None => None,
+        };
+
+        let event = events.get_mut(&id).unwrap();
+
+        // System-scoped events cannot be attached to a process.
+        if event.fds.iter().any(|fd| fd.pid == -1) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        if event.fds.iter().any(|fd| fd.pid == pid) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST));
+        }
+
+        // Inherited per-task events cannot be memory mapped, so events
+        // tracking exits are opened on each CPU.
+        let cpus = if event.track_exits && event.cpu == CPU_ANY {
+            online_cpus()?
+        } else {
+            vec![event.cpu]
+        };
+
+        // Open the pidfd first, so it refers to the process the event is
+        // opened for.
+        let pidfd = match pid {
+            0 => None,
+            pid => pidfd_open(pid).ok(),
+        };
+        event.open(pid, &cpus, group.as_ref())?;
+        event.pidfds.extend(pidfd.map(|f| (pid, f)));
+        Ok(())
+    }
+
+    fn detach(&self, id: PmcId, pid: i32) -> io::Result<()> {
+        self.with_event(id, |event| {
+            event.drain_exits();
+
+            // Keep the events counted for the process, which are lost when
+            // its file descriptors are closed.
+            let mut counted: u64 = 0;
+            for fd in event.fds.iter().filter(|fd| fd.pid == pid) {
+                counted = counted.wrapping_add(fd.read()?[0]);
+            }
+
+            let before = event.fds.len();
+            event.fds.retain(|fd| fd.pid != pid);
+            if event.fds.len() == before {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            event.base = event.base.wrapping_add(counted);
+            event.pidfds.remove(&pid);
+            Ok(())
+        })
+    }
+
+    fn start(&self, id: PmcId) -> io::Result<()> {
+        self.with_event(id, |event| {
+            event.ioctl(PERF_EVENT_IOC_ENABLE)?;
+            event.running = true;
+            Ok(())
+        })
+    }
+
+    fn stop(&self, id: PmcId) -> io::Result<()> {
+        self.with_event(id, |event| {
+            event.ioctl(PERF_EVENT_IOC_DISABLE)?;
+            event.running = false;
+            Ok(())
+        })
+    }
+
+    fn read(&self, id: PmcId) -> io::Result<u64> {
+        self.with_event(id, |event| event.read())
+    }
+
+    fn read_timed(&self, id: PmcId) -> io::Result<TimedValue> {
+        self.with_event(id, |event| event.read_timed())
+    }
+
+    fn exited(&self, id: PmcId, pid: i32) -> io::Result<bool> {
+        self.with_event(id, |event| event.exited(pid))
+    }
+
+    fn child_exits(&self, id: PmcId) -> io::Result<Vec<ChildExit>> {
+        self.with_event(id, |event| {
+            if !event.track_exits {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            event.drain_exits();
+
+            // A child records an exit for each CPU it was opened on - merge
+            // them into a single value.
+            let mut exits: Vec<ChildExit> = vec![];
+            for exit in event.exits.drain(..) {
+                match exits
+                    .iter_mut()
+                    .find(|e| e.pid == exit.pid && e.tid == exit.tid)
+                {
+                    Some(e) => e.value = e.value.wrapping_add(exit.value),
+                    None => exits.push(exit),
+                }
+            }
+            Ok(exits)
+        })
+    }
+
+    fn events(&self) -> io::Result<Vec<EventInfo>> {
+        let mut events: Vec<EventInfo> = GENERIC_EVENTS
+            .iter()
+            .map(|&(name, type_, _, description)| EventInfo {
+                name: name.to_string(),
+                description: Some(description.to_string()),
+                pmu: if type_ == PERF_TYPE_SOFTWARE {
+                    "software"
+                } else {
+                    "hardware"
+                }
+                .to_string(),
+                qualifiers: vec!["usr".to_string(), "os".to_string()],
+            })
+            .collect();
+
+        match read_sysfs_events(Path::new(SYSFS_EVENT_SOURCES)) {
+            Ok(v) => events.extend(v),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(events)
+    }
+
+    fn generic_event(&self, event: GenericEvent) -> io::Result<String> {
+        GENERIC_EVENTS
+            .iter()
+            .find(|e| e.0 == event.name())
+            .map(|e| e.0.to_string())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    fn write(&self, id: PmcId, value: u64) -> io::Result<u64> {
+        // perf counters cannot be written directly - instead the counter is
+        // reset, and the requested value recorded as an offset.
+        self.with_event(id, |event| {
+            let old = event.read()?;
+            event.ioctl(PERF_EVENT_IOC_RESET)?;
+            event.base = value;
+            Ok(old)
+        })
+    }
+
+    fn release(&self, id: PmcId) -> io::Result<()> {
+        // Close the file descriptors after releasing the lock.
+        let event = self.events().remove(&id);
+        match event {
+            Some(_) => Ok(()),
+            None => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+
+    fn start_group(&self, ids: &[PmcId]) -> io::Result<()> {
+        let mut events = self.events();
+        if !Self::is_group(&events, ids) {
+            drop(events);
+            return ids.iter().try_for_each(|&id| self.start(id));
+        }
+
+        // Enabling the leader with PERF_IOC_FLAG_GROUP starts every member at
+        // the same instant.
+        events[&ids[0]].group_ioctl(PERF_EVENT_IOC_ENABLE)?;
+        for id in ids {
+            events.get_mut(id).unwrap().running = true;
+        }
+        Ok(())
+    }
+
+    fn stop_group(&self, ids: &[PmcId]) -> io::Result<()> {
+        let mut events = self.events();
+        if !Self::is_group(&events, ids) {
+            drop(events);
+            return ids.iter().try_for_each(|&id| self.stop(id));
+        }
+
+        events[&ids[0]].group_ioctl(PERF_EVENT_IOC_DISABLE)?;
+        for id in ids {
+            events.get_mut(id).unwrap().running = false;
+        }
+        Ok(())
+    }
+
+    fn read_group(&self, ids: &[PmcId]) -> io::Result<Vec<u64>> {
+        let events = self.events();
+
+        // Pause a running group while it is read, so all values cover exactly
+        // the same window.
+        let leader = ids.first().and_then(|id| events.get(id));
+        let pause = Self::is_group(&events, ids) && leader.map(|e| e.running) == Some(true);
+        if pause {
+            leader.unwrap().group_ioctl(PERF_EVENT_IOC_DISABLE)?;
+        }
+
+        // A group the PMU could never schedule (as it needs more counters
+        // than are available) has not counted anything.
+        let unscheduled = match leader {
+            Some(leader) if Self::is_group(&events, ids) => leader
+                .read_timed()
+                .map(|t| t.time_enabled > 0 && t.time_running == 0),
+            _ => Ok(false),
+        };
+
+        let values = unscheduled.and_then(|unscheduled| {
+            if unscheduled {
+                return Err(io::Error::from_raw_os_error(libc::ENOSPC));
+            }
+            ids.iter()
+                .map(|id| Self::lookup(&events, id)?.read())
+                .collect::<io::Result<Vec<_>>>()
+        });
+
+        if pause {
+            leader.unwrap().group_ioctl(PERF_EVENT_IOC_ENABLE)?;
+        }
+
+        values
+    }
+}
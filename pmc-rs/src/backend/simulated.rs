@@ -0,0 +1,471 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{AllocSpec, Backend, EventInfo, PmcId, SharedLog};
+use crate::counter::CounterBuilder;
+use crate::event::GenericEvent;
+
+/// A backend operation, used to inject failures into a [`Simulated`] backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    /// [`Backend::init`](trait.Backend.html#tymethod.init)
+    Init,
+    /// [`Backend::allocate`](trait.Backend.html#tymethod.allocate)
+    Allocate,
+    /// [`Backend::attach`](trait.Backend.html#tymethod.attach)
+    Attach,
+    /// [`Backend::detach`](trait.Backend.html#tymethod.detach)
+    Detach,
+    /// [`Backend::start`](trait.Backend.html#tymethod.start)
+    Start,
+    /// [`Backend::stop`](trait.Backend.html#tymethod.stop)
+    Stop,
+    /// [`Backend::read`](trait.Backend.html#tymethod.read)
+    Read,
+    /// [`Backend::write`](trait.Backend.html#tymethod.write)
+    Write,
+    /// [`Backend::release`](trait.Backend.html#tymethod.release)
+    Release,
+    /// [`Backend::configure_log`](trait.Backend.html#method.configure_log)
+    ConfigureLog,
+    /// [`Backend::flush_log`](trait.Backend.html#method.flush_log)
+    FlushLog,
+    /// [`Backend::close_log`](trait.Backend.html#method.close_log)
+    CloseLog,
+    /// [`Backend::exited`](trait.Backend.html#method.exited)
+    Exited,
+    /// [`Backend::events`](trait.Backend.html#method.events)
+    Events,
+    /// [`Backend::generic_event`](trait.Backend.html#method.generic_event)
+    GenericEvent,
+}
+
+/// A call made to a [`Simulated`] backend, as returned by
+/// [`Simulated::calls`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    /// `init()` was called.
+    Init,
+    /// `allocate()` was called with the given spec.
+    Allocate(AllocSpec),
+    /// `attach()` was called.
+    Attach(PmcId, i32),
+    /// `detach()` was called.
+    Detach(PmcId, i32),
+    /// `start()` was called.
+    Start(PmcId),
+    /// `stop()` was called.
+    Stop(PmcId),
+    /// `read()` was called.
+    Read(PmcId),
+    /// `write()` was called with the given value.
+    Write(PmcId, u64),
+    /// `release()` was called.
+    Release(PmcId),
+    /// `configure_log()` was called with the given file descriptor.
+    ConfigureLog(RawFd),
+    /// `flush_log()` was called.
+    FlushLog,
+    /// `close_log()` was called.
+    CloseLog,
+    /// `exited()` was called.
+    Exited(PmcId, i32),
+    /// `events()` was called.
+    Events,
+    /// `generic_event()` was called with the given event.
+    GenericEvent(GenericEvent),
+}
+
+impl Call {
+    /// Returns the [`Op`] this call performed.
+    pub fn op(&self) -> Op {
+        match self {
+            Call::Init => Op::Init,
+            Call::Allocate(_) => Op::Allocate,
+            Call::Attach(..) => Op::Attach,
+            Call::Detach(..) => Op::Detach,
+            Call::Start(_) => Op::Start,
+            Call::Stop(_) => Op::Stop,
+            Call::Read(_) => Op::Read,
+            Call::Write(..) => Op::Write,
+            Call::Release(_) => Op::Release,
+            Call::ConfigureLog(_) => Op::ConfigureLog,
+            Call::FlushLog => Op::FlushLog,
+            Call::CloseLog => Op::CloseLog,
+            Call::Exited(..) => Op::Exited,
+            Call::Events => Op::Events,
+            Call::GenericEvent(_) => Op::GenericEvent,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SimCounter {
+    spec: AllocSpec,
+    value: u64,
+    running: bool,
+    attached: HashSet<i32>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: PmcId,
+    counters: HashMap<PmcId, SimCounter>,
+    reads: HashMap<String, VecDeque<u64>>,
+    failures: HashMap<Op, VecDeque<i32>>,
+    calls: Vec<Call>,
+    log: SharedLog,
+    events: Vec<EventInfo>,
+    exited: HashSet<i32>,
+    panics: HashSet<(Op, String)>,
+    max_counters: Option<usize>,
+}
+
+impl State {
+    fn counter(&mut self, id: PmcId) -> io::Result<&mut SimCounter> {
+        self.counters
+            .get_mut(&id)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+    }
+
+    // Returns the event `call` operates on, if any.
+    fn event<'a>(&'a self, call: &'a Call) -> Option<&'a str> {
+        let id = match call {
+            Call::Allocate(spec) => return Some(&spec.event),
+            Call::Attach(id, _)
+            | Call::Detach(id, _)
+            | Call::Exited(id, _)
+            | Call::Start(id)
+            | Call::Stop(id)
+            | Call::Read(id)
+            | Call::Write(id, _)
+            | Call::Release(id) => id,
+            _ => return None,
+        };
+        self.counters.get(id).map(|c| c.spec.event.as_str())
+    }
+}
+
+/// A scriptable, in-memory [`Backend`] for deterministic tests.
+///
+/// The values returned by each read can be scripted per event, failures can
+/// be injected for any operation, and every call made to the backend is
+/// recorded so the call sequence can be verified.
+///
+/// ```
+/// use pmc::*;
+/// use pmc::backend::{Call, Op, Simulated};
+///
+/// let sim = Simulated::default();
+/// sim.script_reads("inst_retired.any", vec![100, 200]);
+///
+/// let mut counter = sim
+///     .builder()
+///     .attach_to(vec![42])
+///     .allocate("inst_retired.any")?;
+///
+/// let handle = counter.start()?;
+/// assert_eq!(handle.read()?, 100);
+/// assert_eq!(handle.read()?, 200);
+/// handle.stop();
+///
+/// // Inject a failure into the next start call
+/// sim.fail_next(Op::Start, libc::ENXIO);
+/// assert_eq!(counter.start().unwrap_err().kind(), &ErrorKind::BadScope);
+///
+/// drop(counter);
+/// assert!(sim.calls().ends_with(&[Call::Detach(0, 42), Call::Release(0)]));
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// Once the scripted values for an event are exhausted, reads return the last
+/// value set (or 0).
+///
+/// Processes can be made to exit with [`exit`]. Counters keep their value once
+/// a target exits, while attaching to (or detaching from) an exited process
+/// fails with `ESRCH`.
+///
+/// Clones of a `Simulated` backend share the same state, so a test can keep a
+/// clone to script and inspect the backend used by its counters.
+///
+/// As with [`hwpmc`], starting a sampling PMC fails with [`EDOOFUS`] unless a
+/// log file has been configured.
+///
+/// [`hwpmc`]: https://www.freebsd.org/cgi/man.cgi?query=hwpmc
+/// [`EDOOFUS`]: constant.EDOOFUS.html
+/// [`exit`]: #method.exit
+#[derive(Debug, Default, Clone)]
+pub struct Simulated {
+    state: Arc<Mutex<State>>,
+}
+
+impl Simulated {
+    /// Returns a [`CounterBuilder`] allocating counters with this backend.
+    ///
+    /// [`CounterBuilder`]: ../struct.CounterBuilder.html
+    pub fn builder(&self) -> CounterBuilder {
+        CounterBuilder::default().set_backend(Arc::new(self.clone()))
+    }
+
+    /// Script the values returned by successive reads of any counter
+    /// allocated for `event`.
+    ///
+    /// Values are appended to any values already scripted for the event.
+    pub fn script_reads(&self, event: impl Into<String>, values: impl IntoIterator<Item = u64>) {
+        self.state()
+            .reads
+            .entry(event.into())
+            .or_default()
+            .extend(values)
+    }
+
+    /// Set the events listed by [`Backend::events`].
+    ///
+    /// [`Backend::events`]: trait.Backend.html#method.events
+    pub fn script_events(&self, events: impl IntoIterator<Item = EventInfo>) {
+        self.state().events = events.into_iter().collect();
+    }
+
+    /// Limit the number of counters allocated at once to `n`, as a CPU has a
+    /// fixed number of PMCs.
+    ///
+    /// As with [`hwpmc`], allocations fail with `EINVAL` once every PMC is in
+    /// use.
+    ///
+    /// [`hwpmc`]: https://www.freebsd.org/cgi/man.cgi?query=hwpmc
+    pub fn set_max_counters(&self, n: usize) {
+        self.state().max_counters = Some(n);
+    }
+
+    /// Simulate the exit of the process `pid`.
+    pub fn exit(&self, pid: i32) {
+        self.state().exited.insert(pid);
+    }
+
+    /// Fail the next call to `op` with the OS error `errno`.
+    ///
+    /// Multiple failures for the same operation are returned in the order
+    /// they were injected.
+    pub fn fail_next(&self, op: Op, errno: i32) {
+        self.state()
+            .failures
+            .entry(op)
+            .or_default()
+            .push_back(errno)
+    }
+
+    /// Panic whenever `op` is called for a counter of `event`, simulating a
+    /// misbehaving backend.
+    ///
+    /// Allocations panic before allocating the counter. Any other operation
+    /// is performed before panicking, so a panicking release still releases
+    /// the counter.
+    pub fn panic_on(&self, op: Op, event: impl Into<String>) {
+        self.state().panics.insert((op, event.into()));
+    }
+
+    /// Returns all calls made to the backend, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state().calls.clone()
+    }
+
+    /// Clear the recorded calls.
+    pub fn clear_calls(&self) {
+        self.state().calls.clear()
+    }
+
+    /// Returns the specs of every `allocate()` call, in order.
+    pub fn allocations(&self) -> Vec<AllocSpec> {
+        self.state()
+            .calls
+            .iter()
+            .filter_map(|c| match c {
+                Call::Allocate(spec) => Some(spec.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the number of allocated (and not yet released) counters.
+    pub fn allocated(&self) -> usize {
+        self.state().counters.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Record `call`, and return any failure injected for it.
+    fn call<T>(&self, call: Call, f: impl FnOnce(&mut State) -> io::Result<T>) -> io::Result<T> {
+        let mut state = self.state();
+
+        let op = call.op();
+        let panics = match state.event(&call) {
+            Some(event) => state.panics.contains(&(op, event.to_string())),
+            None => false,
+        };
+        state.calls.push(call);
+
+        if let Some(errno) = state.failures.get_mut(&op).and_then(|f| f.pop_front()) {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+
+        let res = match (panics, op) {
+            (true, Op::Allocate) => None,
+            _ => Some(f(&mut state)),
+        };
+
+        // Panic with the state unlocked, so it is not poisoned.
+        drop(state);
+        match res {
+            Some(res) if !panics => res,
+            _ => panic!("simulated panic in {:?}", op),
+        }
+    }
+}
+
+impl Backend for Simulated {
+    fn init(&self) -> io::Result<()> {
+        self.call(Call::Init, |_| Ok(()))
+    }
+
+    fn allocate(&self, spec: &AllocSpec) -> io::Result<PmcId> {
+        self.call(Call::Allocate(spec.clone()), |state| {
+            if matches!(state.max_counters, Some(max) if state.counters.len() >= max) {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+
+            let id = state.next_id;
+            state.next_id += 1;
+            state.counters.insert(
+                id,
+                SimCounter {
+                    spec: spec.clone(),
+                    value: 0,
+                    running: false,
+                    attached: HashSet::new(),
+                },
+            );
+            Ok(id)
+        })
+    }
+
+    fn attach(&self, id: PmcId, pid: i32) -> io::Result<()> {
+        self.call(Call::Attach(id, pid), |state| {
+            let exited = state.exited.contains(&pid);
+            let c = state.counter(id)?;
+            if c.spec.mode.is_system() {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            if exited {
+                return Err(io::Error::from_raw_os_error(libc::ESRCH));
+            }
+            if !c.attached.insert(pid) {
+                return Err(io::Error::from_raw_os_error(libc::EEXIST));
+            }
+            Ok(())
+        })
+    }
+
+    fn detach(&self, id: PmcId, pid: i32) -> io::Result<()> {
+        self.call(Call::Detach(id, pid), |state| {
+            let exited = state.exited.contains(&pid);
+            if !state.counter(id)?.attached.remove(&pid) {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            if exited {
+                return Err(io::Error::from_raw_os_error(libc::ESRCH));
+            }
+            Ok(())
+        })
+    }
+
+    fn exited(&self, id: PmcId, pid: i32) -> io::Result<bool> {
+        self.call(Call::Exited(id, pid), |state| {
+            if !state.counter(id)?.attached.contains(&pid) {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            Ok(state.exited.contains(&pid))
+        })
+    }
+
+    fn start(&self, id: PmcId) -> io::Result<()> {
+        self.call(Call::Start(id), |state| {
+            let has_log = state.log.is_configured();
+
+            // Sampling PMCs require a log file, as with hwpmc.
+            let c = state.counter(id)?;
+            if !c.spec.mode.is_counting() && !has_log {
+                return Err(io::Error::from_raw_os_error(super::EDOOFUS));
+            }
+
+            c.running = true;
+            Ok(())
+        })
+    }
+
+    fn stop(&self, id: PmcId) -> io::Result<()> {
+        self.call(Call::Stop(id), |state| {
+            state.counter(id)?.running = false;
+            Ok(())
+        })
+    }
+
+    fn read(&self, id: PmcId) -> io::Result<u64> {
+        self.call(Call::Read(id), |state| {
+            let event = state.counter(id)?.spec.event.clone();
+            let scripted = state.reads.get_mut(&event).and_then(|v| v.pop_front());
+
+            let c = state.counter(id)?;
+            if let Some(v) = scripted {
+                c.value = v;
+            }
+            Ok(c.value)
+        })
+    }
+
+    fn write(&self, id: PmcId, value: u64) -> io::Result<u64> {
+        self.call(Call::Write(id, value), |state| {
+            let c = state.counter(id)?;
+            Ok(std::mem::replace(&mut c.value, value))
+        })
+    }
+
+    fn release(&self, id: PmcId) -> io::Result<()> {
+        self.call(Call::Release(id), |state| {
+            state
+                .counters
+                .remove(&id)
+                .map(|_| ())
+                .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+        })
+    }
+
+    fn configure_log(&self, fd: RawFd) -> io::Result<()> {
+        self.call(Call::ConfigureLog(fd), |state| {
+            state.log.open(fd, || Ok(()))
+        })
+    }
+
+    fn flush_log(&self) -> io::Result<()> {
+        self.call(Call::FlushLog, |state| match state.log.is_configured() {
+            true => Ok(()),
+            false => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        })
+    }
+
+    fn close_log(&self) -> io::Result<()> {
+        self.call(Call::CloseLog, |state| state.log.close(|| Ok(())))
+    }
+
+    fn events(&self) -> io::Result<Vec<EventInfo>> {
+        self.call(Call::Events, |state| Ok(state.events.clone()))
+    }
+
+    fn generic_event(&self, event: GenericEvent) -> io::Result<String> {
+        self.call(Call::GenericEvent(event), |_| Ok(event.name().to_string()))
+    }
+}
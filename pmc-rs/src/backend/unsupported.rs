@@ -0,0 +1,58 @@
+use std::io;
+
+use super::{AllocSpec, Backend, PmcId};
+
+/// A [`Backend`] for platforms without PMC support.
+///
+/// Every operation fails with `ENOSYS`, causing the [`Counter`] API to return
+/// [`ErrorKind::Unsupported`] rather than panicking. This is the default
+/// backend on platforms other than FreeBSD and Linux.
+///
+/// [`Counter`]: ../struct.Counter.html
+/// [`ErrorKind::Unsupported`]: ../enum.ErrorKind.html#variant.Unsupported
+#[derive(Debug, Default)]
+pub struct Unsupported {
+    _private: (),
+}
+
+fn unsupported<T>() -> io::Result<T> {
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+impl Backend for Unsupported {
+    fn init(&self) -> io::Result<()> {
+        unsupported()
+    }
+
+    fn allocate(&self, _spec: &AllocSpec) -> io::Result<PmcId> {
+        unsupported()
+    }
+
+    fn attach(&self, _id: PmcId, _pid: i32) -> io::Result<()> {
+        unsupported()
+    }
+
+    fn detach(&self, _id: PmcId, _pid: i32) -> io::Result<()> {
+        unsupported()
+    }
+
+    fn start(&self, _id: PmcId) -> io::Result<()> {
+        unsupported()
+    }
+
+    fn stop(&self, _id: PmcId) -> io::Result<()> {
+        unsupported()
+    }
+
+    fn read(&self, _id: PmcId) -> io::Result<u64> {
+        unsupported()
+    }
+
+    fn write(&self, _id: PmcId, _value: u64) -> io::Result<u64> {
+        unsupported()
+    }
+
+    fn release(&self, _id: PmcId) -> io::Result<()> {
+        unsupported()
+    }
+}
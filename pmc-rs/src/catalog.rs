@@ -0,0 +1,279 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::backend::{default_backend, EventInfo};
+use crate::counter::init_backend;
+use crate::error::{new_os_error, Error, ErrorKind};
+#[cfg(target_os = "linux")]
+use crate::spec::Qualifier;
+
+/// The directory the Linux kernel lists PMUs and their events in.
+pub const SYSFS_EVENT_SOURCES: &str = "/sys/bus/event_source/devices";
+
+// sysfs event files describing (rather than encoding) an event.
+const EVENT_METADATA_SUFFIXES: &[&str] = &[".scale", ".unit", ".per-pkg", ".snapshot"];
+
+// Event qualifiers supported by every perf event, and the PMU format terms
+// that support the rest.
+const MODE_QUALIFIERS: &[&str] = &["usr", "os"];
+const FORMAT_QUALIFIERS: &[(&str, &str)] = &[
+    ("edge", "edge"),
+    ("inv", "inv"),
+    ("anythread", "any"),
+    ("cmask", "cmask"),
+    ("umask", "umask"),
+    ("threshold", "thresh"),
+];
+
+/// Returns the PMU format term and value a qualifier sets, or `None` for the
+/// `usr` and `os` mode qualifiers.
+///
+/// [`Qualifier::Other`] qualifiers name the format term directly, with a bare
+/// term set to 1. Returns `EINVAL` if the value of one is not a number.
+///
+/// [`Qualifier::Other`]: ../enum.Qualifier.html#variant.Other
+#[cfg(target_os = "linux")]
+pub(crate) fn qualifier_term(q: &Qualifier) -> io::Result<Option<(&str, u64)>> {
+    let value = match q {
+        Qualifier::Usr | Qualifier::Os => return Ok(None),
+        Qualifier::Edge | Qualifier::Inv | Qualifier::AnyThread => 1,
+        Qualifier::Cmask(v) | Qualifier::Umask(v) | Qualifier::Threshold(v) => *v as u64,
+        Qualifier::Other(_) => {
+            let value = match q.other_value() {
+                Some(v) => parse_value(v).ok_or_else(invalid)?,
+                None => 1,
+            };
+            return Ok(Some((q.key(), value)));
+        }
+    };
+
+    Ok(FORMAT_QUALIFIERS
+        .iter()
+        .find(|(qualifier, _)| *qualifier == q.key())
+        .map(|&(_, term)| (term, value)))
+}
+
+/// List the events that can be counted on the running CPU by the default
+/// backend.
+///
+/// Events are listed by `libpmc` on FreeBSD, and read from the PMUs in
+/// [`SYSFS_EVENT_SOURCES`] (in addition to the generic `perf(1)` event names)
+/// on Linux.
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// for event in events()? {
+///     println!("{} ({}): {}", event.name, event.pmu, event.description.unwrap_or_default());
+/// }
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`SYSFS_EVENT_SOURCES`]: constant.SYSFS_EVENT_SOURCES.html
+pub fn events() -> Result<Vec<EventInfo>, Error> {
+    let backend = default_backend();
+    init_backend(&*backend)?;
+
+    backend.events().map_err(|err| match err.raw_os_error() {
+        Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+        _ => new_os_error(ErrorKind::Unknown, err),
+    })
+}
+
+/// List the events of the PMUs in `dir`, laid out as
+/// [`SYSFS_EVENT_SOURCES`].
+///
+/// Each event is named `pmu/event`, the form accepted by the Linux backend.
+/// sysfs does not describe events, so the description is always `None`.
+///
+/// [`SYSFS_EVENT_SOURCES`]: constant.SYSFS_EVENT_SOURCES.html
+pub fn sysfs_events(dir: impl AsRef<Path>) -> Result<Vec<EventInfo>, Error> {
+    read_sysfs_events(dir.as_ref()).map_err(|err| new_os_error(ErrorKind::Unknown, err))
+}
+
+/// Returns the sorted names of the entries in `dir`.
+fn sorted_entries(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+pub(crate) fn read_sysfs_events(dir: &Path) -> io::Result<Vec<EventInfo>> {
+    let mut out = vec![];
+
+    for pmu in sorted_entries(dir)? {
+        let events_dir = dir.join(&pmu).join("events");
+        if !events_dir.is_dir() {
+            continue;
+        }
+
+        let formats = match sorted_entries(&dir.join(&pmu).join("format")) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+
+        let mut qualifiers: Vec<String> = MODE_QUALIFIERS.iter().map(|q| q.to_string()).collect();
+        for (qualifier, term) in FORMAT_QUALIFIERS {
+            if formats.iter().any(|f| f == term) {
+                qualifiers.push(qualifier.to_string());
+            }
+        }
+
+        for event in sorted_entries(&events_dir)? {
+            if EVENT_METADATA_SUFFIXES.iter().any(|s| event.ends_with(s)) {
+                continue;
+            }
+
+            out.push(EventInfo {
+                name: format!("{}/{}", pmu, event),
+                description: None,
+                pmu: pmu.clone(),
+                qualifiers: qualifiers.clone(),
+            });
+        }
+    }
+
+    Ok(out)
+}
+
+/// A sysfs event resolved to its `perf_event_attr` encoding.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SysfsEncoding {
+    pub(crate) type_: u32,
+    pub(crate) config: [u64; 3],
+}
+
+#[cfg(target_os = "linux")]
+impl SysfsEncoding {
+    /// Set the format `term` of `pmu` to `value`.
+    pub(crate) fn set(&mut self, dir: &Path, pmu: &str, term: &str, value: u64) -> io::Result<()> {
+        // The config words can be set directly.
+        let format = match term {
+            "config" => "config:0-63".to_string(),
+            "config1" => "config1:0-63".to_string(),
+            "config2" => "config2:0-63".to_string(),
+            _ => fs::read_to_string(dir.join(pmu).join("format").join(term))?,
+        };
+
+        let (word, ranges) = format.trim().split_once(':').ok_or_else(invalid)?;
+        let word = match word {
+            "config" => 0,
+            "config1" => 1,
+            "config2" => 2,
+            _ => return Err(invalid()),
+        };
+
+        // Spread the bits of value over the (possibly discontiguous) ranges,
+        // lowest first.
+        let mut value = value;
+        for range in ranges.split(',') {
+            let (lo, hi) = match range.split_once('-') {
+                Some((lo, hi)) => (lo, hi),
+                None => (range, range),
+            };
+            let (lo, hi): (u32, u32) = (
+                lo.parse().map_err(|_| invalid())?,
+                hi.parse().map_err(|_| invalid())?,
+            );
+            if lo > hi || hi > 63 {
+                return Err(invalid());
+            }
+
+            let width = hi - lo + 1;
+            let mask = if width == 64 {
+                u64::MAX
+            } else {
+                (1 << width) - 1
+            };
+            self.config[word] |= (value & mask) << lo;
+            value = value.checked_shr(width).unwrap_or(0);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn invalid() -> io::Error {
+    io::Error::from_raw_os_error(libc::EINVAL)
+}
+
+#[cfg(target_os = "linux")]
+fn parse_value(v: &str) -> Option<u64> {
+    match v.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => v.parse().ok(),
+    }
+}
+
+/// Resolve the sysfs event `pmu/event` listed in `dir` into its encoding.
+#[cfg(target_os = "linux")]
+pub(crate) fn resolve_sysfs_event(dir: &Path, pmu: &str, event: &str) -> io::Result<SysfsEncoding> {
+    let not_found = |e: io::Error| match e.kind() {
+        io::ErrorKind::NotFound => invalid(),
+        _ => e,
+    };
+
+    // Reject names escaping the PMU directories.
+    if [pmu, event]
+        .iter()
+        .any(|v| v.is_empty() || v.contains('/') || v.starts_with('.'))
+    {
+        return Err(invalid());
+    }
+
+    let type_ = fs::read_to_string(dir.join(pmu).join("type"))
+        .map_err(not_found)?
+        .trim()
+        .parse()
+        .map_err(|_| invalid())?;
+
+    let terms = fs::read_to_string(dir.join(pmu).join("events").join(event)).map_err(not_found)?;
+
+    encode_terms(dir, pmu, type_, terms.trim()).map_err(not_found)
+}
+
+/// Resolve the `pmu/terms/` event listed in `dir` into its encoding, such as
+/// `cpu/event=0x3c,umask=0x0/`.
+#[cfg(target_os = "linux")]
+pub(crate) fn resolve_sysfs_terms(dir: &Path, pmu: &str, terms: &str) -> io::Result<SysfsEncoding> {
+    if pmu.is_empty() || pmu.starts_with('.') {
+        return Err(invalid());
+    }
+
+    let type_ = fs::read_to_string(dir.join(pmu).join("type"))
+        .map_err(|_| invalid())?
+        .trim()
+        .parse()
+        .map_err(|_| invalid())?;
+
+    encode_terms(dir, pmu, type_, terms).map_err(|_| invalid())
+}
+
+// Terms are of the form "event=0x3c,umask=0x01,edge" - a bare term is 1.
+#[cfg(target_os = "linux")]
+fn encode_terms(dir: &Path, pmu: &str, type_: u32, terms: &str) -> io::Result<SysfsEncoding> {
+    let mut enc = SysfsEncoding {
+        type_,
+        ..Default::default()
+    };
+
+    for term in terms.split(',').filter(|t| !t.is_empty()) {
+        let (name, value) = match term.split_once('=') {
+            Some((n, v)) => (n, parse_value(v).ok_or_else(invalid)?),
+            None => (term, 1),
+        };
+        if name.contains('/') || name.starts_with('.') {
+            return Err(invalid());
+        }
+        enc.set(dir, pmu, name, value)?;
+    }
+
+    Ok(enc)
+}
@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus};
+use std::thread;
+use std::time::Instant;
+
+use crate::error::{new_os_error, Error, ErrorKind};
+use crate::group::CounterGroup;
+use crate::measure::Measurement;
+
+/// Run `command`, counting the events of the [`CounterGroup`] returned by
+/// `allocate` for the child PID.
+///
+/// `Command::spawn` does not return until the child has called `exec`, so the
+/// child cannot be attached to between the two. Instead the forked child
+/// sends its PID to the parent over a pipe and blocks until the parent writes
+/// to a second pipe, once the counters are attached and running. Should
+/// allocating or starting the counters fail, the parent closes the pipe
+/// without writing and the child exits without running the command.
+pub(crate) fn measure_command(
+    mut command: Command,
+    allocate: impl FnOnce(i32) -> Result<CounterGroup, Error>,
+) -> Result<(ExitStatus, Measurement), Error> {
+    let (mut pid_r, pid_w) = pipe()?;
+    let (go_r, mut go_w) = pipe()?;
+
+    let fds = (pid_w.as_raw_fd(), go_r.as_raw_fd(), go_w.as_raw_fd());
+    // Safety: wait_for_parent only makes async-signal-safe calls, and does
+    // not allocate.
+    unsafe {
+        command.pre_exec(move || wait_for_parent(fds.0, fds.1, fds.2));
+    }
+
+    let spawner = thread::spawn(move || {
+        let child = command.spawn();
+
+        // Close the parent's copy of the write end so the read below does not
+        // block forever if the child was never forked.
+        drop(pid_w);
+        drop(go_r);
+        child
+    });
+
+    // Join the spawner once the child has been released (or abandoned by
+    // closing the pipe), propagating any panic.
+    let join = |spawner: thread::JoinHandle<io::Result<_>>| match spawner.join() {
+        Ok(child) => child.map_err(spawn_error),
+        Err(panic) => std::panic::resume_unwind(panic),
+    };
+
+    let mut buf = [0; mem::size_of::<libc::pid_t>()];
+    if pid_r.read_exact(&mut buf).is_err() {
+        drop(go_w);
+        return Err(join(spawner)
+            .err()
+            .unwrap_or_else(|| spawn_error(io::Error::from_raw_os_error(libc::EPIPE))));
+    }
+    let pid = libc::pid_t::from_ne_bytes(buf);
+
+    let mut group = match allocate(pid) {
+        Ok(v) => v,
+        Err(e) => {
+            drop(go_w);
+            let _ = join(spawner);
+            return Err(e);
+        }
+    };
+
+    let handle = match group.start() {
+        Ok(v) => v,
+        Err(e) => {
+            drop(go_w);
+            let _ = join(spawner);
+            return Err(e);
+        }
+    };
+
+    // Release the child to exec the command.
+    let started = Instant::now();
+    let _ = go_w.write_all(&[1]);
+    drop(go_w);
+
+    let status = join(spawner).and_then(|mut child| child.wait().map_err(spawn_error))?;
+
+    let elapsed = started.elapsed();
+    handle.stop();
+
+    Ok((status, Measurement::new(group.read()?, elapsed)))
+}
+
+// Called in the forked child before exec.
+fn wait_for_parent(pid_w: RawFd, go_r: RawFd, go_w: RawFd) -> io::Result<()> {
+    unsafe {
+        // Close the child's copy of the write end, so closing the parent's
+        // copy is seen as EOF.
+        libc::close(go_w);
+
+        let pid = libc::getpid();
+        let n = libc::write(
+            pid_w,
+            &pid as *const libc::pid_t as *const libc::c_void,
+            mem::size_of::<libc::pid_t>(),
+        );
+        if n != mem::size_of::<libc::pid_t>() as isize {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut go = 0u8;
+        loop {
+            match libc::read(go_r, &mut go as *mut u8 as *mut libc::c_void, 1) {
+                1 => return Ok(()),
+                -1 if io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) => continue,
+                _ => return Err(io::Error::from_raw_os_error(libc::ECANCELED)),
+            }
+        }
+    }
+}
+
+// Returns the (read, write) ends of a pipe, closed on exec.
+fn pipe() -> Result<(File, File), Error> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(spawn_error(io::Error::last_os_error()));
+    }
+
+    unsafe { Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))) }
+}
+
+fn spawn_error(err: io::Error) -> Error {
+    new_os_error(ErrorKind::Spawn, err)
+}
@@ -1,27 +1,23 @@
-use std::ffi::CString;
+use std::fs::File;
 use std::io;
-use std::sync::{Mutex, Once};
-
-#[cfg(target_os = "freebsd")]
-use libc::EDOOFUS;
-#[cfg(target_os = "freebsd")]
-use pmc_sys::{
-    pmc_allocate, pmc_attach, pmc_detach, pmc_id_t, pmc_init, pmc_mode_PMC_MODE_SC,
-    pmc_mode_PMC_MODE_SS, pmc_mode_PMC_MODE_TC, pmc_read, pmc_release, pmc_rw, pmc_start, pmc_stop,
-};
-
-#[cfg(not(target_os = "freebsd"))]
-use super::stubs::*;
+use std::process::{Command, ExitStatus};
+use std::sync::Arc;
 
-use crate::error::{new_error, new_os_error, Error, ErrorKind};
+use crate::backend::{
+    default_backend, AllocSpec, Backend, ChildExit, Mode, PmcId, EDOOFUS, EPROGMISMATCH,
+};
+use crate::command::measure_command;
+use crate::error::{new_error, new_os_error, new_parse_error, Error, ErrorKind, ParseError};
+use crate::event::GenericEvent;
+use crate::group::CounterGroup;
+use crate::measure::Measurement;
+use crate::multiplex::Multiplexer;
+use crate::percpu::{online_cpus, PerCpuCounter};
+use crate::sampler::{Sampler, DEFAULT_SAMPLE_RATE};
+use crate::spec::EventSpec;
+use crate::split::SplitCounter;
 use crate::CPU_ANY;
 
-static PMC_INIT: Once = Once::new();
-
-lazy_static! {
-    static ref BIG_FAT_LOCK: Mutex<u32> = Mutex::new(42);
-}
-
 /// Configure event counter parameters.
 ///
 /// Unless specified, a counter is allocated in counting mode with a system-wide
@@ -41,12 +37,24 @@ lazy_static! {
 pub struct CounterBuilder {
     cpu: Option<i32>,
     pids: Option<Vec<i32>>,
+    backend: Option<Arc<dyn Backend>>,
+    sample_rate: Option<u64>,
+    descendants: bool,
+    track_exits: bool,
+    exclude_user: bool,
+    exclude_kernel: bool,
+    exclude_hv: bool,
+    exclude_idle: bool,
 }
 
 impl CounterBuilder {
     /// Specify the CPU number that the PMC is to be allocated on.
     ///
-    /// Defaults to all CPUs ([`CPU_ANY`]).
+    /// Defaults to all CPUs ([`CPU_ANY`]) for process-scoped counters, and CPU
+    /// 0 for system-scoped counters - use [`per_cpu`] to count system-wide
+    /// events on every CPU.
+    ///
+    /// [`per_cpu`]: #method.per_cpu
     pub fn set_cpu(self, cpu: i32) -> Self {
         Self {
             cpu: Some(cpu),
@@ -70,35 +78,553 @@ impl CounterBuilder {
         }
     }
 
+    /// Use the specified [`Backend`] to allocate counters.
+    ///
+    /// Defaults to the platform backend returned by [`default_backend`].
+    ///
+    /// [`Backend`]: backend/trait.Backend.html
+    /// [`default_backend`]: backend/fn.default_backend.html
+    pub fn set_backend(self, backend: Arc<dyn Backend>) -> Self {
+        Self {
+            backend: Some(backend),
+            ..self
+        }
+    }
+
+    /// Set the number of events between samples for a [`Sampler`].
+    ///
+    /// Defaults to [`DEFAULT_SAMPLE_RATE`]. Ignored when allocating a
+    /// [`Counter`].
+    ///
+    /// [`Sampler`]: struct.Sampler.html
+    /// [`DEFAULT_SAMPLE_RATE`]: constant.DEFAULT_SAMPLE_RATE.html
+    /// [`Counter`]: struct.Counter.html
+    pub fn set_sample_rate(self, rate: u64) -> Self {
+        Self {
+            sample_rate: Some(rate),
+            ..self
+        }
+    }
+
+    /// Count events that occur while the CPU is executing in user mode.
+    ///
+    /// Defaults to true.
+    pub fn count_user(self, count: bool) -> Self {
+        Self {
+            exclude_user: !count,
+            ..self
+        }
+    }
+
+    /// Count events that occur while the CPU is executing in kernel mode.
+    ///
+    /// Defaults to true.
+    pub fn count_kernel(self, count: bool) -> Self {
+        Self {
+            exclude_kernel: !count,
+            ..self
+        }
+    }
+
+    /// Exclude events that occur while the CPU is executing in the hypervisor.
+    ///
+    /// Only supported by the Linux backend, others return an [`Unsupported`]
+    /// error when allocating.
+    ///
+    /// [`Unsupported`]: enum.ErrorKind.html#variant.Unsupported
+    pub fn exclude_hypervisor(self, exclude: bool) -> Self {
+        Self {
+            exclude_hv: exclude,
+            ..self
+        }
+    }
+
+    /// Exclude events that occur while the CPU is idle.
+    ///
+    /// Only supported by the Linux backend, others return an [`Unsupported`]
+    /// error when allocating.
+    ///
+    /// [`Unsupported`]: enum.ErrorKind.html#variant.Unsupported
+    pub fn exclude_idle(self, exclude: bool) -> Self {
+        Self {
+            exclude_idle: exclude,
+            ..self
+        }
+    }
+
+    /// Count events for all descendants of the target PIDs, including child
+    /// processes and threads started after the counter is attached.
+    ///
+    /// This sets the descendants flag of `hwpmc` on FreeBSD, and `inherit` for
+    /// `perf_event_open(2)` on Linux. Only meaningful for process-scoped
+    /// counters.
+    pub fn follow_descendants(self, follow: bool) -> Self {
+        Self {
+            descendants: follow,
+            ..self
+        }
+    }
+
+    /// Record the final counter value of each descendant as it exits,
+    /// retrieved with [`Counter::child_exits`].
+    ///
+    /// Enabling this implies [`follow_descendants`]. Only supported by the
+    /// Linux backend, which relies on the kernel's `inherit_stat` records -
+    /// some kernel versions do not emit a record for every exit, so the
+    /// breakdown may be incomplete.
+    ///
+    /// [`Counter::child_exits`]: struct.Counter.html#method.child_exits
+    /// [`follow_descendants`]: #method.follow_descendants
+    pub fn track_child_exits(self, track: bool) -> Self {
+        Self {
+            track_exits: track,
+            ..self
+        }
+    }
+
     /// Allocate a PMC with the specified configuration, and attach to the
     /// target PIDs (if any).
+    ///
+    /// `event_spec` is either an [`EventSpec`] or a string in the same syntax,
+    /// and is validated before any PMC is allocated - a malformed spec returns
+    /// an [`InvalidEventSpec`] error describing the offending token.
+    ///
+    /// Without target PIDs the counter is system-scoped, and unless a CPU is
+    /// chosen with [`set_cpu`] it only counts events on CPU 0 - neither
+    /// backend can count system-wide on [`CPU_ANY`]. Use [`per_cpu`] to count
+    /// events on every CPU.
+    ///
+    /// [`EventSpec`]: struct.EventSpec.html
+    /// [`InvalidEventSpec`]: enum.ErrorKind.html#variant.InvalidEventSpec
+    /// [`set_cpu`]: #method.set_cpu
+    /// [`CPU_ANY`]: constant.CPU_ANY.html
+    /// [`per_cpu`]: #method.per_cpu
     pub fn allocate(&self, event_spec: impl Into<String>) -> Result<Counter, Error> {
-        Counter::new(event_spec, self.cpu, self.pids.clone())
+        // If there's any pids, request a process counter, otherwise a
+        // system-wide counter.
+        let mode = if self.pids.is_none() {
+            Mode::SystemCounting
+        } else {
+            Mode::ProcessCounting
+        };
+
+        Counter::new(self.backend(), self.spec(event_spec, mode), self.pids.clone())
+    }
+
+    /// Resolve the [`GenericEvent`] `event` into the native event spec for the
+    /// running CPU and configured backend.
+    ///
+    /// Returns an [`UnmappedEvent`] error if the event has no native
+    /// equivalent.
+    ///
+    /// ```no_run
+    /// use pmc::*;
+    ///
+    /// let builder = CounterBuilder::default().attach_to(vec![0]);
+    ///
+    /// let mut group = builder.group(vec![
+    ///     builder.resolve(GenericEvent::Instructions)?,
+    ///     builder.resolve(GenericEvent::Cycles)?,
+    /// ])?;
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [`GenericEvent`]: enum.GenericEvent.html
+    /// [`UnmappedEvent`]: enum.ErrorKind.html#variant.UnmappedEvent
+    pub fn resolve(&self, event: GenericEvent) -> Result<EventSpec, Error> {
+        let spec = self
+            .backend()
+            .generic_event(event)
+            .map_err(|err| match err.raw_os_error() {
+                Some(libc::ENOENT) => new_os_error(ErrorKind::UnmappedEvent, err),
+                Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+                _ => new_os_error(ErrorKind::Unknown, err),
+            })?;
+
+        EventSpec::parse(&spec)
+    }
+
+    /// Allocate a PMC counting the [`GenericEvent`] `event` - see
+    /// [`resolve`].
+    ///
+    /// [`GenericEvent`]: enum.GenericEvent.html
+    /// [`resolve`]: #method.resolve
+    pub fn allocate_generic(&self, event: GenericEvent) -> Result<Counter, Error> {
+        self.allocate(self.resolve(event)?)
+    }
+
+    /// Allocate a pair of PMCs counting `event_spec` in user and kernel mode
+    /// separately, and attach to the target PIDs (if any).
+    ///
+    /// Any [`count_user`] or [`count_kernel`] setting is overridden.
+    ///
+    /// [`count_user`]: #method.count_user
+    /// [`count_kernel`]: #method.count_kernel
+    pub fn allocate_split(&self, event_spec: impl Into<String>) -> Result<SplitCounter, Error> {
+        let event_spec = event_spec.into();
+
+        let user = self
+            .clone()
+            .count_user(true)
+            .count_kernel(false)
+            .allocate(event_spec.clone())?;
+        let kernel = self
+            .clone()
+            .count_user(false)
+            .count_kernel(true)
+            .allocate(event_spec)?;
+
+        Ok(SplitCounter::new(user, kernel))
+    }
+
+    /// Allocate a sampling PMC with the specified configuration, recording
+    /// samples to `log`.
+    ///
+    /// If any target PIDs are configured the PMC is allocated in
+    /// process-scoped sampling mode, otherwise it samples the whole system.
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use pmc::*;
+    ///
+    /// let mut sampler = CounterBuilder::default()
+    ///     .attach_to(vec![0])
+    ///     .set_sample_rate(10_000)
+    ///     .sampler("inst_retired.any", File::create("pmc.log").unwrap())?;
+    ///
+    /// let handle = sampler.start()?;
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn sampler(&self, event_spec: impl Into<String>, log: File) -> Result<Sampler, Error> {
+        let mode = if self.pids.is_none() {
+            Mode::SystemSampling
+        } else {
+            Mode::ProcessSampling
+        };
+
+        let counter = Counter::new(self.backend(), self.spec(event_spec, mode), self.pids.clone())?;
+        Sampler::new(counter, log)
+    }
+
+    /// Allocate a [`CounterGroup`] counting all of `event_specs` together,
+    /// and attach to the target PIDs (if any).
+    ///
+    /// Each event is labelled with its event spec in the values read from the
+    /// group.
+    ///
+    /// ```no_run
+    /// use pmc::*;
+    ///
+    /// let mut group = CounterBuilder::default()
+    ///     .attach_to(vec![0])
+    ///     .group(vec!["instructions", "cycles"])?;
+    ///
+    /// let handle = group.start()?;
+    /// let values = handle.read()?;
+    ///
+    /// let ipc = values.get("instructions").unwrap() as f64
+    ///     / values.get("cycles").unwrap() as f64;
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [`CounterGroup`]: struct.CounterGroup.html
+    pub fn group<T>(&self, event_specs: impl IntoIterator<Item = T>) -> Result<CounterGroup, Error>
+    where
+        T: Into<String>,
+    {
+        let mode = if self.pids.is_none() {
+            Mode::SystemCounting
+        } else {
+            Mode::ProcessCounting
+        };
+
+        let mut counters: Vec<Counter> = vec![];
+        let mut labels = vec![];
+        for event_spec in event_specs {
+            let mut spec = self.spec(event_spec, mode);
+            spec.group = counters.first().map(|leader| leader.id);
+
+            labels.push(spec.event.clone());
+            counters.push(Counter::new(self.backend(), spec, self.pids.clone())?);
+        }
+
+        if counters.is_empty() {
+            return Err(new_error(ErrorKind::InvalidEventSpec));
+        }
+
+        Ok(CounterGroup::new(counters, labels))
+    }
+
+    /// Allocate a PMC counting `event_spec`, and count it while running `f`.
+    ///
+    /// Returns the result of `f`, and a [`Measurement`] of the count and wall
+    /// time. The counter is stopped and released when `f` returns, or if it
+    /// panics.
+    ///
+    /// ```no_run
+    /// use pmc::*;
+    ///
+    /// let (sum, m) = CounterBuilder::default()
+    ///     .attach_to(vec![0])
+    ///     .measure("instructions", || (0..1_000_000u64).sum::<u64>())?;
+    ///
+    /// println!("sum {} took {} instructions in {:?}", sum, m.value(), m.elapsed());
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [`Measurement`]: struct.Measurement.html
+    pub fn measure<T>(
+        &self,
+        event_spec: impl Into<String>,
+        f: impl FnOnce() -> T,
+    ) -> Result<(T, Measurement), Error> {
+        self.measure_group(vec![event_spec], f)
+    }
+
+    /// Allocate a [`CounterGroup`] counting `event_specs`, and count them
+    /// while running `f` - see [`measure`].
+    ///
+    /// [`CounterGroup`]: struct.CounterGroup.html
+    /// [`measure`]: #method.measure
+    pub fn measure_group<T, S>(
+        &self,
+        event_specs: impl IntoIterator<Item = S>,
+        f: impl FnOnce() -> T,
+    ) -> Result<(T, Measurement), Error>
+    where
+        S: Into<String>,
+    {
+        self.group(event_specs)?.measure(f)
+    }
+
+    /// Run `command` as a child process, counting `event_specs` for the child
+    /// (and its descendants, if [`follow_descendants`] is set).
+    ///
+    /// The counters are attached to the child before it executes the command,
+    /// so every event of the command is counted. Any PIDs set with
+    /// [`attach_to`] are ignored. Returns the exit status of the child once it
+    /// has exited, and a [`Measurement`] of the counts and wall time it ran
+    /// for.
+    ///
+    /// If the counters cannot be allocated or started, the child exits before
+    /// executing the command and the error is returned. A [`Spawn`] error is
+    /// returned if the command cannot be run.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use pmc::*;
+    ///
+    /// let mut command = Command::new("cargo");
+    /// command.arg("build");
+    ///
+    /// let (status, m) = CounterBuilder::default()
+    ///     .follow_descendants(true)
+    ///     .measure_command(command, vec!["instructions", "cycles"])?;
+    ///
+    /// println!("{}: {}", status, m);
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [`follow_descendants`]: #method.follow_descendants
+    /// [`attach_to`]: #method.attach_to
+    /// [`Measurement`]: struct.Measurement.html
+    /// [`Spawn`]: enum.ErrorKind.html#variant.Spawn
+    pub fn measure_command<S>(
+        &self,
+        command: Command,
+        event_specs: impl IntoIterator<Item = S>,
+    ) -> Result<(ExitStatus, Measurement), Error>
+    where
+        S: Into<String>,
+    {
+        let specs: Vec<String> = event_specs.into_iter().map(Into::into).collect();
+
+        measure_command(command, |pid| {
+            self.clone().attach_to(vec![pid]).group(specs)
+        })
+    }
+
+    /// Allocate a [`Multiplexer`] counting all of `event_specs` by rotating
+    /// them over `width` hardware counters, and attach to the target PIDs (if
+    /// any).
+    ///
+    /// `width` is only used when the backend does not multiplex counters
+    /// itself, and should be no more than the number of PMCs available.
+    /// Every slice of `width` events is allocated in turn to check it can
+    /// be counted, but only the first is kept allocated until started.
+    ///
+    /// [`Multiplexer`]: struct.Multiplexer.html
+    pub fn multiplex<T>(
+        &self,
+        event_specs: impl IntoIterator<Item = T>,
+        width: usize,
+    ) -> Result<Multiplexer, Error>
+    where
+        T: Into<String>,
+    {
+        let specs = event_specs.into_iter().map(|s| {
+            let spec = s.into();
+            (spec.clone(), spec)
+        });
+        self.multiplex_labelled(specs, width)
+    }
+
+    /// Allocate a [`Multiplexer`] counting the `(label, event spec)` pairs of
+    /// `events` - see [`multiplex`].
+    ///
+    /// [`Multiplexer`]: struct.Multiplexer.html
+    /// [`multiplex`]: #method.multiplex
+    pub(crate) fn multiplex_labelled(
+        &self,
+        events: impl IntoIterator<Item = (String, String)>,
+        width: usize,
+    ) -> Result<Multiplexer, Error> {
+        let mode = if self.pids.is_none() {
+            Mode::SystemCounting
+        } else {
+            Mode::ProcessCounting
+        };
+
+        let mut specs = vec![];
+        let mut labels = vec![];
+        for (label, event_spec) in events {
+            specs.push(self.spec(event_spec, mode));
+            labels.push(label);
+        }
+
+        if specs.is_empty() {
+            return Err(new_error(ErrorKind::InvalidEventSpec));
+        }
+
+        Multiplexer::new(self.backend(), specs, self.pids.clone(), labels, width)
+    }
+
+    /// Allocate a system-scoped [`PerCpuCounter`] counting `event_spec` on
+    /// every online CPU.
+    ///
+    /// Any CPU set with [`set_cpu`] is ignored, and configuring target PIDs
+    /// returns a [`BadScope`] error.
+    ///
+    /// [`PerCpuCounter`]: struct.PerCpuCounter.html
+    /// [`set_cpu`]: #method.set_cpu
+    /// [`BadScope`]: enum.ErrorKind.html#variant.BadScope
+    pub fn per_cpu(&self, event_spec: impl Into<String>) -> Result<PerCpuCounter, Error> {
+        self.per_cpu_on(event_spec, online_cpus()?)
+    }
+
+    /// Allocate a system-scoped [`PerCpuCounter`] counting `event_spec` on
+    /// each of `cpus`.
+    ///
+    /// [`PerCpuCounter`]: struct.PerCpuCounter.html
+    pub fn per_cpu_on(
+        &self,
+        event_spec: impl Into<String>,
+        cpus: impl IntoIterator<Item = i32>,
+    ) -> Result<PerCpuCounter, Error> {
+        if self.pids.is_some() {
+            return Err(new_error(ErrorKind::BadScope));
+        }
+
+        let event_spec = event_spec.into();
+        let mut counters = vec![];
+        let mut failed = vec![];
+        for cpu in cpus {
+            let mut spec = self.spec(event_spec.clone(), Mode::SystemCounting);
+            spec.cpu = cpu;
+            match Counter::new(self.backend(), spec, None) {
+                Ok(c) => counters.push((cpu, c)),
+                Err(err) => failed.push((cpu, err)),
+            }
+        }
+
+        if counters.is_empty() {
+            // Report why the first CPU failed, if any were requested.
+            return Err(match failed.into_iter().next() {
+                Some((_, err)) => err,
+                None => new_error(ErrorKind::BadScope),
+            });
+        }
+
+        Ok(PerCpuCounter::new(counters, failed))
+    }
+
+    fn backend(&self) -> Arc<dyn Backend> {
+        self.backend.clone().unwrap_or_else(default_backend)
+    }
+
+    fn spec(&self, event_spec: impl Into<String>, mode: Mode) -> AllocSpec {
+        let cpu = self
+            .cpu
+            .unwrap_or_else(|| if mode.is_system() { 0 } else { CPU_ANY });
+
+        let mut spec = AllocSpec::new(event_spec.into(), mode, cpu);
+        spec.descendants = self.descendants || self.track_exits;
+        spec.track_exits = self.track_exits;
+        spec.exclude_user = self.exclude_user;
+        spec.exclude_kernel = self.exclude_kernel;
+        spec.exclude_hv = self.exclude_hv;
+        spec.exclude_idle = self.exclude_idle;
+        if !mode.is_counting() {
+            spec.sample_rate = self.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+        }
+        spec
     }
 }
 
 #[derive(Debug)]
 struct AttachHandle {
-    id: pmc_id_t,
+    id: PmcId,
     pid: i32,
+    backend: Arc<dyn Backend>,
+    detached: bool,
 }
 
-impl Drop for AttachHandle {
-    fn drop(&mut self) {
+impl AttachHandle {
+    fn new(id: PmcId, pid: i32, backend: Arc<dyn Backend>) -> Self {
+        Self {
+            id,
+            pid,
+            backend,
+            detached: false,
+        }
+    }
+
+    fn detach(&mut self) -> io::Result<()> {
         // BUG: do not attempt to detach from pid 0 or risk live-locking the
         // machine.
         //
         //      https://bugs.freebsd.org/bugzilla/show_bug.cgi?id=227041
         //
-        if self.pid != 0 {
-            unsafe { pmc_detach(self.id, self.pid) };
+        if self.pid == 0 || self.detached {
+            return Ok(());
         }
+
+        let res = self.backend.detach(self.id, self.pid);
+
+        // A process that has exited is detached already.
+        self.detached = match &res {
+            Ok(()) => true,
+            Err(err) => err.raw_os_error() == Some(libc::ESRCH),
+        };
+        res
+    }
+}
+
+impl Drop for AttachHandle {
+    fn drop(&mut self) {
+        let _ = self.detach();
     }
 }
 
 /// A handle to a running PMC counter.
 ///
 /// Dropping this handle causes the counter to stop recording events.
+#[derive(Debug)]
 pub struct Running<'a> {
     counter: &'a mut Counter,
 }
@@ -142,7 +668,94 @@ impl<'a> std::fmt::Display for Running<'a> {
 
 impl<'a> Drop for Running<'a> {
     fn drop(&mut self) {
-        unsafe { pmc_stop(self.counter.id) };
+        let _ = self.counter.backend.stop(self.counter.id);
+    }
+}
+
+/// An owned handle to a running PMC counter.
+///
+/// Unlike [`Running`], an `OwnedRunning` handle does not borrow the
+/// [`Counter`] - it can be stored in a struct, sent to another thread, or
+/// shared between threads (such as in an `Arc`) and read from any of them.
+///
+/// Dropping this handle stops and releases the counter, while [`stop`]
+/// returns it.
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use std::thread;
+/// use pmc::*;
+///
+/// let counter = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .allocate("instructions")?;
+///
+/// let running = Arc::new(counter.start_owned()?);
+///
+/// // Report the count from another thread while this one works.
+/// let reporter = {
+///     let running = Arc::clone(&running);
+///     thread::spawn(move || println!("instructions: {}", running.read().unwrap()))
+/// };
+/// reporter.join().unwrap();
+///
+/// let counter = Arc::try_unwrap(running).unwrap().stop();
+/// println!("instructions: {}", counter.read()?);
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Running`]: struct.Running.html
+/// [`Counter`]: struct.Counter.html
+/// [`stop`]: #method.stop
+#[derive(Debug)]
+pub struct OwnedRunning {
+    // Always Some until stopped.
+    counter: Option<Counter>,
+}
+
+impl OwnedRunning {
+    fn counter_ref(&self) -> &Counter {
+        self.counter.as_ref().expect("counter already stopped")
+    }
+
+    /// Read the current counter value.
+    pub fn read(&self) -> Result<u64, Error> {
+        self.counter_ref().read()
+    }
+
+    /// Set the value of the counter.
+    pub fn set(&mut self, value: u64) -> Result<u64, Error> {
+        self.counter
+            .as_mut()
+            .expect("counter already stopped")
+            .set(value)
+    }
+
+    /// Returns the running counter.
+    pub fn counter(&self) -> &Counter {
+        self.counter_ref()
+    }
+
+    /// Stop the counter from recording new events, and return it.
+    pub fn stop(mut self) -> Counter {
+        let counter = self.counter.take().expect("counter already stopped");
+        let _ = counter.backend.stop(counter.id);
+        counter
+    }
+}
+
+impl std::fmt::Display for OwnedRunning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.counter_ref().fmt(f)
+    }
+}
+
+impl Drop for OwnedRunning {
+    fn drop(&mut self) {
+        if let Some(counter) = &self.counter {
+            let _ = counter.backend.stop(counter.id);
+        }
     }
 }
 
@@ -170,98 +783,244 @@ impl<'a> Drop for Running<'a> {
 /// ```
 #[derive(Debug)]
 pub struct Counter {
-    id: pmc_id_t,
+    id: PmcId,
     attached: Option<Vec<AttachHandle>>,
+    backend: Arc<dyn Backend>,
 }
 
 impl Counter {
-    fn new(
-        event_spec: impl Into<String>,
-        cpu: Option<i32>,
+    pub(crate) fn new(
+        backend: Arc<dyn Backend>,
+        spec: AllocSpec,
         pids: Option<Vec<i32>>,
     ) -> Result<Self, Error> {
-        // If there's any pids, request a process counter, otherwise a
-        // system-wide counter.
-        // XXX Needs support for sampling PMCs.
-        let pmc_mode = if pids.is_none() {
-            pmc_mode_PMC_MODE_SC
-        } else {
-            pmc_mode_PMC_MODE_TC
-        };
-        let cpu = cpu.unwrap_or_else(|| {
-            if pmc_mode == pmc_mode_PMC_MODE_SC || pmc_mode == pmc_mode_PMC_MODE_SS {
-                0
-            } else {
-                CPU_ANY
-            }
-        });
-
-        // It appears pmc_allocate isn't thread safe, so take a lock while
-        // calling it.
-        let _guard = BIG_FAT_LOCK.lock().unwrap();
-
-        init_pmc_once()?;
+        // Validate the event spec before making any syscalls.
+        let (event, positions) = EventSpec::parse_positioned(&spec.event)?;
+        if let Some(i) = spec.mode_conflict(&event) {
+            return Err(new_parse_error(
+                ErrorKind::InvalidEventSpec,
+                ParseError::new(
+                    "qualifier conflicts with an excluded mode",
+                    positions[i],
+                    event.qualifiers()[i].to_string(),
+                ),
+            ));
+        }
 
-        let c_spec =
-            CString::new(event_spec.into()).map_err(|_| new_error(ErrorKind::InvalidEventSpec))?;
+        // Backends are responsible for serialising any calls that are not
+        // thread safe, so allocations on many threads proceed concurrently.
+        init_backend(&*backend)?;
 
         // Allocate the PMC
-        let mut id = 0;
-        if unsafe { pmc_allocate(c_spec.as_ptr(), pmc_mode, 0, cpu, &mut id, 0) } != 0 {
-            return match io::Error::raw_os_error(&io::Error::last_os_error()) {
-                Some(libc::EINVAL) => Err(new_os_error(ErrorKind::AllocInit)),
-                _ => Err(new_os_error(ErrorKind::Unknown)),
-            };
-        }
+        let id = backend
+            .allocate(&spec)
+            .map_err(|err| match err.raw_os_error() {
+                // perf reports events the CPU does not have with ENOENT.
+                Some(libc::EINVAL) | Some(libc::ENOENT) => new_os_error(ErrorKind::AllocInit, err),
+                Some(libc::EPERM) => new_os_error(ErrorKind::Forbidden, err),
+                Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+                _ => new_os_error(ErrorKind::Unknown, err),
+            })?;
 
         // Initialise the counter so dropping it releases the PMC
-        let mut c = Counter { id, attached: None };
+        let mut c = Counter {
+            id,
+            attached: None,
+            backend,
+        };
 
         // Attach to pids, if any, and collect handles so dropping them later
         // causes them to detach.
         //
         // The handles MUST be dropped before the Counter instance.
         if let Some(pids) = pids {
-            let mut handles = vec![];
+            c.attached = Some(vec![]);
 
             for pid in pids {
-                if unsafe { pmc_attach(id, pid) } != 0 {
-                    return match io::Error::raw_os_error(&io::Error::last_os_error()) {
-                        Some(libc::EBUSY) => unreachable!(),
-                        Some(libc::EEXIST) => Err(new_os_error(ErrorKind::AlreadyAttached)),
-                        Some(libc::EPERM) => Err(new_os_error(ErrorKind::Forbidden)),
-                        Some(libc::EINVAL) | Some(libc::ESRCH) => {
-                            Err(new_os_error(ErrorKind::BadTarget))
-                        }
-                        _ => Err(new_os_error(ErrorKind::Unknown)),
-                    };
-                }
-
-                handles.push(AttachHandle { id, pid })
+                c.attach(pid)?;
             }
-
-            c.attached = Some(handles)
         }
 
         Ok(c)
     }
 
+    pub(crate) fn id(&self) -> PmcId {
+        self.id
+    }
+
+    pub(crate) fn backend(&self) -> &Arc<dyn Backend> {
+        &self.backend
+    }
+
+    /// Attach this counter to `pid`, counting its events in addition to those
+    /// of the PIDs already attached.
+    ///
+    /// Only counters allocated with target PIDs (see
+    /// [`CounterBuilder::attach_to`]) can be attached to further processes -
+    /// a [`BadScope`] error is returned for system-wide counters.
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use pmc::*;
+    ///
+    /// let mut counter = CounterBuilder::default()
+    ///     .attach_to(vec![])
+    ///     .allocate("instructions")?;
+    ///
+    /// let mut worker = Command::new("./worker").spawn().unwrap();
+    /// counter.attach(worker.id() as i32)?;
+    ///
+    /// let handle = counter.start()?;
+    /// worker.wait().unwrap();
+    /// handle.stop();
+    ///
+    /// counter.detach(worker.id() as i32)?;
+    /// assert!(counter.attached_pids().is_empty());
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [`CounterBuilder::attach_to`]: struct.CounterBuilder.html#method.attach_to
+    /// [`BadScope`]: enum.ErrorKind.html#variant.BadScope
+    pub fn attach(&mut self, pid: i32) -> Result<(), Error> {
+        let handles = match &mut self.attached {
+            Some(v) => v,
+            None => return Err(new_error(ErrorKind::BadScope)),
+        };
+        self.backend
+            .attach(self.id, pid)
+            .map_err(|err| match err.raw_os_error() {
+                Some(libc::EBUSY) => new_os_error(ErrorKind::BusyTarget, err),
+                Some(libc::EEXIST) => new_os_error(ErrorKind::AlreadyAttached, err),
+                Some(libc::EPERM) => new_os_error(ErrorKind::Forbidden, err),
+                Some(libc::EINVAL) | Some(libc::ESRCH) => new_os_error(ErrorKind::BadTarget, err),
+                Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+                _ => new_os_error(ErrorKind::Unknown, err),
+            })?;
+
+        // Dropping the handle detaches the counter.
+        handles.push(AttachHandle::new(self.id, pid, Arc::clone(&self.backend)));
+        Ok(())
+    }
+
+    /// Detach this counter from `pid`, no longer counting its events.
+    ///
+    /// Events already counted for `pid` are still included in the counter
+    /// value. Returns a [`BadTarget`] error if the counter is not attached to
+    /// `pid`. Detaching from a process that has exited succeeds.
+    ///
+    /// Detaching from PID 0 can live-lock FreeBSD machines, so returns a
+    /// [`Forbidden`] error - the counter remains attached until it is
+    /// released.
+    ///
+    /// [`BadTarget`]: enum.ErrorKind.html#variant.BadTarget
+    /// [`Forbidden`]: enum.ErrorKind.html#variant.Forbidden
+    pub fn detach(&mut self, pid: i32) -> Result<(), Error> {
+        let handles = match &mut self.attached {
+            Some(v) => v,
+            None => return Err(new_error(ErrorKind::BadTarget)),
+        };
+        let idx = match handles.iter().position(|h| h.pid == pid) {
+            Some(v) => v,
+            None => return Err(new_error(ErrorKind::BadTarget)),
+        };
+        if pid == 0 {
+            return Err(new_error(ErrorKind::Forbidden));
+        }
+
+        match handles[idx].detach() {
+            Ok(()) => {}
+            // The process exited, detaching the counter.
+            Err(err) if err.raw_os_error() == Some(libc::ESRCH) => {}
+            Err(err) => {
+                return Err(match err.raw_os_error() {
+                    Some(libc::EINVAL) => new_os_error(ErrorKind::BadTarget, err),
+                    Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+                    _ => new_os_error(ErrorKind::Unknown, err),
+                })
+            }
+        }
+
+        handles.remove(idx);
+        Ok(())
+    }
+
+    /// Returns the PIDs this counter is attached to, in the order they were
+    /// attached.
+    ///
+    /// System-wide counters are not attached to any PIDs.
+    pub fn attached_pids(&self) -> Vec<i32> {
+        self.attached
+            .iter()
+            .flatten()
+            .map(|h| h.pid)
+            .collect()
+    }
+
+    /// Returns the attached PIDs whose processes have exited, in the order
+    /// they were attached.
+    ///
+    /// Exited processes remain attached until detached, and the events they
+    /// counted before exiting are still included in the counter value.
+    ///
+    /// ```no_run
+    /// use pmc::*;
+    ///
+    /// let mut counter = CounterBuilder::default()
+    ///     .attach_to(vec![1234, 1235])
+    ///     .allocate("instructions")?;
+    ///
+    /// // ...
+    ///
+    /// for pid in counter.exited_pids()? {
+    ///     counter.detach(pid)?;
+    /// }
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn exited_pids(&self) -> Result<Vec<i32>, Error> {
+        let mut exited = vec![];
+        for pid in self.attached_pids() {
+            let gone = self
+                .backend
+                .exited(self.id, pid)
+                .map_err(|err| match err.raw_os_error() {
+                    Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+                    _ => new_os_error(ErrorKind::Unknown, err),
+                })?;
+            if gone {
+                exited.push(pid);
+            }
+        }
+        Ok(exited)
+    }
+
     /// Start this counter.
     ///
     /// The counter stops when the returned [`Running`] handle is dropped.
     #[must_use = "counter only runs until handle is dropped"]
     pub fn start(&mut self) -> Result<Running<'_>, Error> {
-        if unsafe { pmc_start(self.id) } != 0 {
-            return match io::Error::raw_os_error(&io::Error::last_os_error()) {
-                Some(EDOOFUS) => Err(new_os_error(ErrorKind::LogFileRequired)),
-                Some(libc::ENXIO) => Err(new_os_error(ErrorKind::BadScope)),
-                _ => Err(new_os_error(ErrorKind::Unknown)),
-            };
-        }
+        self.backend.start(self.id).map_err(start_error)?;
 
         Ok(Running { counter: self })
     }
 
+    /// Start this counter, returning an owned handle to it.
+    ///
+    /// The handle can be moved or shared between threads, and returns the
+    /// counter when stopped - see [`OwnedRunning`]. The counter is released if
+    /// it cannot be started.
+    ///
+    /// [`OwnedRunning`]: struct.OwnedRunning.html
+    #[must_use = "counter only runs until handle is dropped"]
+    pub fn start_owned(self) -> Result<OwnedRunning, Error> {
+        self.backend.start(self.id).map_err(start_error)?;
+
+        Ok(OwnedRunning {
+            counter: Some(self),
+        })
+    }
+
     /// Read the counter value.
     ///
     /// This call is valid for both running, stopped, and unused counters.
@@ -282,12 +1041,47 @@ impl Counter {
     /// # Ok::<(), Error>(())
     /// ```
     pub fn read(&self) -> Result<u64, Error> {
-        let mut value: u64 = 0;
-        if unsafe { pmc_read(self.id, &mut value) } != 0 {
-            return Err(new_os_error(ErrorKind::Unknown));
-        }
+        self.backend.read(self.id).map_err(read_error)
+    }
 
-        Ok(value)
+    /// Returns the final counter values of the descendants that exited since
+    /// the last call.
+    ///
+    /// The counter must be allocated with [`track_child_exits`] enabled,
+    /// otherwise a [`BadScope`] error is returned. Exited descendants are
+    /// still included in the value returned by [`read`].
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    /// use pmc::*;
+    ///
+    /// let mut counter = CounterBuilder::default()
+    ///     .attach_to(vec![0])
+    ///     .track_child_exits(true)
+    ///     .allocate("instructions")?;
+    ///
+    /// let handle = counter.start()?;
+    /// Command::new("true").status().unwrap();
+    /// handle.stop();
+    ///
+    /// for exit in counter.child_exits()? {
+    ///     println!("{}: {}", exit.pid, exit.value);
+    /// }
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [`track_child_exits`]: struct.CounterBuilder.html#method.track_child_exits
+    /// [`BadScope`]: enum.ErrorKind.html#variant.BadScope
+    /// [`read`]: #method.read
+    pub fn child_exits(&self) -> Result<Vec<ChildExit>, Error> {
+        self.backend
+            .child_exits(self.id)
+            .map_err(|err| match err.raw_os_error() {
+                Some(libc::EINVAL) => new_os_error(ErrorKind::BadScope, err),
+                Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+                _ => new_os_error(ErrorKind::Unknown, err),
+            })
     }
 
     /// Set an explicit counter value.
@@ -310,16 +1104,13 @@ impl Counter {
     /// # Ok::<(), Error>(())
     /// ```
     pub fn set(&mut self, value: u64) -> Result<u64, Error> {
-        let mut old: u64 = 0;
-        if unsafe { pmc_rw(self.id, value, &mut old) } != 0 {
-            let err = io::Error::last_os_error();
-            return match io::Error::raw_os_error(&err) {
+        self.backend
+            .write(self.id, value)
+            .map_err(|err| match err.raw_os_error() {
                 Some(libc::EBUSY) => panic!("{}", err.to_string()),
-                _ => Err(new_os_error(ErrorKind::Unknown)),
-            };
-        }
-
-        Ok(old)
+                Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+                _ => new_os_error(ErrorKind::Unknown, err),
+            })
     }
 }
 
@@ -334,29 +1125,38 @@ impl std::fmt::Display for Counter {
 
 impl Drop for Counter {
     fn drop(&mut self) {
-        let _guard = BIG_FAT_LOCK.lock().unwrap();
-
         // The handles MUST be dropped before the Counter instance
         self.attached = None;
 
-        unsafe {
-            pmc_release(self.id);
-        }
+        let _ = self.backend.release(self.id);
     }
 }
 
-fn init_pmc_once() -> Result<(), Error> {
-    let mut maybe_err = Ok(());
-    PMC_INIT.call_once(|| {
-        if unsafe { pmc_init() } != 0 {
-            maybe_err = match io::Error::raw_os_error(&io::Error::last_os_error()) {
-                Some(libc::ENOENT) => Err(new_os_error(ErrorKind::Init)),
-                Some(libc::ENXIO) => Err(new_os_error(ErrorKind::Unsupported)),
-                Some(libc::EPROGMISMATCH) => Err(new_os_error(ErrorKind::VersionMismatch)),
-                _ => Err(new_os_error(ErrorKind::Unknown)),
-            };
-            return;
-        }
-    });
-    maybe_err
+pub(crate) fn start_error(err: io::Error) -> Error {
+    match err.raw_os_error() {
+        Some(EDOOFUS) => new_os_error(ErrorKind::LogFileRequired, err),
+        Some(libc::ENXIO) => new_os_error(ErrorKind::BadScope, err),
+        Some(libc::ESRCH) => new_os_error(ErrorKind::TargetExited, err),
+        Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+        _ => new_os_error(ErrorKind::Unknown, err),
+    }
+}
+
+pub(crate) fn read_error(err: io::Error) -> Error {
+    match err.raw_os_error() {
+        Some(libc::ESRCH) => new_os_error(ErrorKind::TargetExited, err),
+        Some(libc::ENOSPC) => new_os_error(ErrorKind::NotScheduled, err),
+        Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+        _ => new_os_error(ErrorKind::Unknown, err),
+    }
+}
+
+pub(crate) fn init_backend(backend: &dyn Backend) -> Result<(), Error> {
+    backend.init().map_err(|err| match err.raw_os_error() {
+        Some(libc::ENOENT) => new_os_error(ErrorKind::Init, err),
+        Some(libc::ENXIO) | Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+        Some(libc::EPERM) => new_os_error(ErrorKind::Forbidden, err),
+        Some(EPROGMISMATCH) => new_os_error(ErrorKind::VersionMismatch, err),
+        _ => new_os_error(ErrorKind::Unknown, err),
+    })
 }
@@ -0,0 +1,399 @@
+use std::fmt;
+
+use crate::event::GenericEvent;
+
+/// The vendor, family, model and stepping of a CPU, as reported by `CPUID`
+/// leaf 1.
+///
+/// `CpuId` is displayed in the `vendor-family-model-stepping` form used to
+/// key vendor event tables (see [`EventTable::for_cpu`]), such as
+/// `GenuineIntel-6-55-4`: the family is decimal, and the model and stepping
+/// are upper case hex.
+///
+/// ```
+/// use pmc::*;
+///
+/// let cpu = CpuId::new("GenuineIntel", 6, 0x55, 4);
+/// assert_eq!(cpu.to_string(), "GenuineIntel-6-55-4");
+/// ```
+///
+/// [`EventTable::for_cpu`]: struct.EventTable.html#method.for_cpu
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CpuId {
+    vendor: String,
+    family: u32,
+    model: u32,
+    stepping: u32,
+}
+
+impl CpuId {
+    /// Describe a CPU.
+    pub fn new(vendor: impl Into<String>, family: u32, model: u32, stepping: u32) -> Self {
+        Self {
+            vendor: vendor.into(),
+            family,
+            model,
+            stepping,
+        }
+    }
+
+    /// Identify the running CPU.
+    ///
+    /// Returns `None` on CPUs without the `CPUID` instruction.
+    pub fn current() -> Option<Self> {
+        current_cpuid().and_then(|cpuid| Self::from_cpuid(&cpuid))
+    }
+
+    /// Decode the CPU identification from the `cpuid` leaves.
+    fn from_cpuid(cpuid: &dyn Fn(u32, u32) -> Regs) -> Option<Self> {
+        let (max_leaf, ebx, ecx, edx) = cpuid(0, 0);
+        if max_leaf < 1 {
+            return None;
+        }
+
+        let eax = cpuid(1, 0).0;
+        let stepping = eax & 0xf;
+        let base_model = (eax >> 4) & 0xf;
+        let base_family = (eax >> 8) & 0xf;
+
+        // The extended family and model fields only apply to some base
+        // families.
+        let mut family = base_family;
+        if base_family == 0xf {
+            family += (eax >> 20) & 0xff;
+        }
+        let mut model = base_model;
+        if base_family == 0x6 || base_family == 0xf {
+            model |= ((eax >> 16) & 0xf) << 4;
+        }
+
+        Some(Self::new(
+            vendor_string(&[ebx, edx, ecx]),
+            family,
+            model,
+            stepping,
+        ))
+    }
+
+    /// The vendor string, such as `GenuineIntel` or `AuthenticAMD`.
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    /// The CPU family, including the extended family bits.
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    /// The CPU model, including the extended model bits.
+    pub fn model(&self) -> u32 {
+        self.model
+    }
+
+    /// The CPU stepping.
+    pub fn stepping(&self) -> u32 {
+        self.stepping
+    }
+}
+
+impl fmt::Display for CpuId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{:X}-{:X}",
+            self.vendor, self.family, self.model, self.stepping
+        )
+    }
+}
+
+/// The pre-defined architectural performance events of Intel CPUs, as
+/// enumerated by `CPUID` leaf `0xA`.
+///
+/// Architectural events count the same condition on every Intel CPU
+/// supporting them, regardless of the microarchitecture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArchEvent {
+    /// Unhalted core cycles.
+    CoreCycles,
+
+    /// Instructions retired.
+    InstructionsRetired,
+
+    /// Unhalted reference cycles, unaffected by frequency scaling.
+    ReferenceCycles,
+
+    /// Last level cache references.
+    LlcReferences,
+
+    /// Last level cache misses.
+    LlcMisses,
+
+    /// Branch instructions retired.
+    BranchInstructionsRetired,
+
+    /// Mispredicted branch instructions retired.
+    BranchMissesRetired,
+
+    /// Top-down microarchitecture analysis issue slots.
+    TopdownSlots,
+}
+
+// The architectural events, in the order of their CPUID.0AH:EBX bits.
+const ARCH_EVENTS: &[ArchEvent] = &[
+    ArchEvent::CoreCycles,
+    ArchEvent::InstructionsRetired,
+    ArchEvent::ReferenceCycles,
+    ArchEvent::LlcReferences,
+    ArchEvent::LlcMisses,
+    ArchEvent::BranchInstructionsRetired,
+    ArchEvent::BranchMissesRetired,
+    ArchEvent::TopdownSlots,
+];
+
+impl ArchEvent {
+    /// Returns the [`GenericEvent`] counting the same condition, if any.
+    ///
+    /// [`GenericEvent`]: enum.GenericEvent.html
+    pub fn generic(&self) -> Option<GenericEvent> {
+        match self {
+            ArchEvent::CoreCycles => Some(GenericEvent::Cycles),
+            ArchEvent::InstructionsRetired => Some(GenericEvent::Instructions),
+            ArchEvent::LlcReferences => Some(GenericEvent::CacheReferences),
+            ArchEvent::LlcMisses => Some(GenericEvent::CacheMisses),
+            ArchEvent::BranchInstructionsRetired => Some(GenericEvent::Branches),
+            ArchEvent::BranchMissesRetired => Some(GenericEvent::BranchMisses),
+            ArchEvent::ReferenceCycles | ArchEvent::TopdownSlots => None,
+        }
+    }
+}
+
+/// The performance monitoring capabilities of a CPU, as reported by `CPUID`.
+///
+/// On Intel CPUs the counters and architectural events are enumerated by the
+/// architectural performance monitoring leaf `0xA`. AMD CPUs report the number
+/// of core counters through the extended leaves (`0x80000001`, and
+/// `0x80000022` for PerfMonV2) and have no fixed-function counters or
+/// architectural event enumeration.
+///
+/// `CPUID` is executed in userspace, so no kernel support or permissions are
+/// needed - this makes `PmuInfo` useful for checking an event set can be
+/// counted before allocating it:
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let events = vec![GenericEvent::Instructions, GenericEvent::BranchMisses];
+///
+/// let info = PmuInfo::current().expect("no CPUID");
+/// if info.gp_counters() == 0 {
+///     println!("no PMU available (hypervisor: {:?})", info.hypervisor_vendor());
+/// } else if info.gp_counters() < events.len() as u32 {
+///     println!("events will be multiplexed");
+/// }
+/// ```
+///
+/// Hypervisors often do not virtualise the PMU, reporting no counters (or
+/// counters that always read zero) - see [`is_virtualised`].
+///
+/// [`is_virtualised`]: #method.is_virtualised
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PmuInfo {
+    cpu: CpuId,
+    version: u32,
+    gp_counters: u32,
+    gp_counter_width: u32,
+    fixed_counters: u32,
+    fixed_counter_width: u32,
+    arch_events: Vec<ArchEvent>,
+    hypervisor: bool,
+    hypervisor_vendor: Option<String>,
+}
+
+impl PmuInfo {
+    /// Read the capabilities of the running CPU.
+    ///
+    /// Returns `None` on CPUs without the `CPUID` instruction.
+    pub fn current() -> Option<Self> {
+        current_cpuid().and_then(Self::from_cpuid)
+    }
+
+    /// Decode the capabilities from the `(eax, ebx, ecx, edx)` registers
+    /// returned by `cpuid(leaf, subleaf)`.
+    ///
+    /// This allows the capabilities of a recorded (or made up) CPU to be
+    /// decoded. Returns `None` if leaf 1 is not supported.
+    pub fn from_cpuid(cpuid: impl Fn(u32, u32) -> (u32, u32, u32, u32)) -> Option<Self> {
+        let cpu = CpuId::from_cpuid(&cpuid)?;
+
+        let mut info = Self {
+            cpu,
+            version: 0,
+            gp_counters: 0,
+            gp_counter_width: 0,
+            fixed_counters: 0,
+            fixed_counter_width: 0,
+            arch_events: vec![],
+            hypervisor: cpuid(1, 0).2 & (1 << 31) != 0,
+            hypervisor_vendor: None,
+        };
+
+        let max_leaf = cpuid(0, 0).0;
+        match info.cpu.vendor() {
+            "AuthenticAMD" | "HygonGenuine" => {
+                let max_ext = cpuid(0x8000_0000, 0).0;
+                if max_ext >= 0x8000_0022 && cpuid(0x8000_0022, 0).0 & 1 != 0 {
+                    info.version = 2;
+                    info.gp_counters = cpuid(0x8000_0022, 0).1 & 0xf;
+                } else if max_ext >= 0x8000_0001 && cpuid(0x8000_0001, 0).2 & (1 << 23) != 0 {
+                    // PerfCtrExtCore
+                    info.version = 1;
+                    info.gp_counters = 6;
+                } else {
+                    info.version = 1;
+                    info.gp_counters = 4;
+                }
+                info.gp_counter_width = 48;
+            }
+            _ if max_leaf >= 0xa => {
+                let (eax, ebx, _, edx) = cpuid(0xa, 0);
+                info.version = eax & 0xff;
+                info.gp_counters = (eax >> 8) & 0xff;
+                info.gp_counter_width = (eax >> 16) & 0xff;
+
+                // A set EBX bit marks an unavailable event.
+                let n = (eax >> 24) & 0xff;
+                info.arch_events = ARCH_EVENTS
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| (i as u32) < n && ebx & (1 << i) == 0)
+                    .map(|(_, e)| *e)
+                    .collect();
+
+                if info.version > 1 {
+                    info.fixed_counters = edx & 0x1f;
+                    info.fixed_counter_width = (edx >> 5) & 0xff;
+                }
+            }
+            _ => {}
+        }
+
+        // Hypervisors identify themselves at leaf 0x40000000.
+        if info.hypervisor {
+            let (_, ebx, ecx, edx) = cpuid(0x4000_0000, 0);
+            let vendor = vendor_string(&[ebx, ecx, edx]);
+            if !vendor.is_empty() {
+                info.hypervisor_vendor = Some(vendor);
+            }
+        }
+
+        Some(info)
+    }
+
+    /// The CPU identification.
+    pub fn cpu(&self) -> &CpuId {
+        &self.cpu
+    }
+
+    /// The architectural performance monitoring version on Intel CPUs, or 2
+    /// for AMD CPUs supporting PerfMonV2 (otherwise 1).
+    ///
+    /// Returns 0 if performance monitoring is not enumerated.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The number of general-purpose counters per logical CPU.
+    pub fn gp_counters(&self) -> u32 {
+        self.gp_counters
+    }
+
+    /// The bit width of the general-purpose counters.
+    pub fn gp_counter_width(&self) -> u32 {
+        self.gp_counter_width
+    }
+
+    /// The number of fixed-function counters.
+    pub fn fixed_counters(&self) -> u32 {
+        self.fixed_counters
+    }
+
+    /// The bit width of the fixed-function counters.
+    pub fn fixed_counter_width(&self) -> u32 {
+        self.fixed_counter_width
+    }
+
+    /// Returns the architectural events supported by the CPU.
+    pub fn arch_events(&self) -> &[ArchEvent] {
+        &self.arch_events
+    }
+
+    /// Returns true if the CPU supports the architectural event `event`.
+    pub fn supports(&self, event: ArchEvent) -> bool {
+        self.arch_events.contains(&event)
+    }
+
+    /// Returns true if running under a hypervisor.
+    pub fn is_virtualised(&self) -> bool {
+        self.hypervisor
+    }
+
+    /// The hypervisor vendor string, such as `KVMKVMKVM` or `Microsoft Hv`,
+    /// if running under a hypervisor that reports one.
+    pub fn hypervisor_vendor(&self) -> Option<&str> {
+        self.hypervisor_vendor.as_deref()
+    }
+}
+
+impl fmt::Display for PmuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} x {}-bit general-purpose, {} x {}-bit fixed counters",
+            self.cpu,
+            self.gp_counters,
+            self.gp_counter_width,
+            self.fixed_counters,
+            self.fixed_counter_width
+        )?;
+
+        match (self.hypervisor, &self.hypervisor_vendor) {
+            (true, Some(v)) => write!(f, " (hypervisor: {})", v),
+            (true, None) => write!(f, " (hypervisor)"),
+            (false, _) => Ok(()),
+        }
+    }
+}
+
+/// The `(eax, ebx, ecx, edx)` registers returned by `CPUID`.
+type Regs = (u32, u32, u32, u32);
+
+/// Returns the `CPUID` instruction of the running CPU, if it has one.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn current_cpuid() -> Option<impl Fn(u32, u32) -> Regs> {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::__cpuid_count;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::__cpuid_count;
+
+    Some(|leaf, subleaf| {
+        // Safety: CPUID is available on every CPU Rust supports for these
+        // targets, and has no side effects - newer toolchains mark it safe.
+        #[allow(unused_unsafe)]
+        let r = unsafe { __cpuid_count(leaf, subleaf) };
+        (r.eax, r.ebx, r.ecx, r.edx)
+    })
+}
+
+/// Returns the `CPUID` instruction of the running CPU, if it has one.
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn current_cpuid() -> Option<fn(u32, u32) -> Regs> {
+    None
+}
+
+/// Concatenate the little-endian bytes of `regs` into a vendor string.
+fn vendor_string(regs: &[u32]) -> String {
+    let bytes: Vec<u8> = regs.iter().flat_map(|r| r.to_le_bytes().to_vec()).collect();
+    String::from_utf8_lossy(&bytes)
+        .trim_end_matches('\0')
+        .to_string()
+}
@@ -8,7 +8,7 @@ pub struct Error {
     cause: Option<Box<dyn std::error::Error>>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ErrorKind {
     /// An unknown error
     Unknown,
@@ -21,7 +21,9 @@ pub enum ErrorKind {
     /// [`libpmc`]: https://www.freebsd.org/cgi/man.cgi?query=pmc
     Init,
 
-    /// The system CPU does not support performance monitor counters.
+    /// The system CPU does not support performance monitor counters, there
+    /// is no PMC backend available for this platform, or the backend does not
+    /// support the requested feature.
     Unsupported,
 
     /// The kernel PMC interface differs from what this crate is using.
@@ -61,6 +63,12 @@ pub enum ErrorKind {
     /// The requested event requires a configured log file to write results to.
     LogFileRequired,
 
+    /// A [`Sampler`] is already recording to a different log file, and the
+    /// backend supports a single log file at a time.
+    ///
+    /// [`Sampler`]: struct.Sampler.html
+    LogFileInUse,
+
     /// The requested target PID is already being monitored by another process.
     BusyTarget,
 
@@ -69,28 +77,67 @@ pub enum ErrorKind {
 
     /// The caller does not have the appropriate permissions.
     Forbidden,
+
+    /// The provided metric formula could not be parsed.
+    ///
+    /// The [cause] of the error is a [`ParseError`] describing the problem.
+    ///
+    /// [cause]: struct.Error.html#method.cause
+    /// [`ParseError`]: struct.ParseError.html
+    InvalidFormula,
+
+    /// The provided vendor event table could not be parsed.
+    ///
+    /// The [cause] of the error is a [`ParseError`] describing the problem.
+    ///
+    /// [cause]: struct.Error.html#method.cause
+    /// [`ParseError`]: struct.ParseError.html
+    InvalidEventTable,
+
+    /// The requested [`GenericEvent`] has no native equivalent on this CPU or
+    /// backend.
+    ///
+    /// [`GenericEvent`]: enum.GenericEvent.html
+    UnmappedEvent,
+
+    /// A process the counter is attached to has exited.
+    ///
+    /// The counter keeps the events counted before the process exited - see
+    /// [`Counter::exited_pids`].
+    ///
+    /// [`Counter::exited_pids`]: struct.Counter.html#method.exited_pids
+    TargetExited,
+
+    /// The events of a group were never counted, as the PMU could not
+    /// schedule them together - usually because the group needs more
+    /// counters than the CPU has.
+    NotScheduled,
+
+    /// The command to be measured could not be spawned, or waited on.
+    ///
+    /// The [cause] of the error is the underlying I/O error.
+    ///
+    /// [cause]: struct.Error.html#method.cause
+    Spawn,
 }
 
 impl std::error::Error for Error {
     fn description(&self) -> &str {
-        match self.kind {
-            ErrorKind::Init => "missing hwpmc in kernel",
-            ErrorKind::Unloaded => "hwpmc unloaded from kernel",
-            ErrorKind::Unsupported => "unsupported CPU",
-            ErrorKind::VersionMismatch => "unexpected hwpmc version",
-            ErrorKind::AllocInit => "failed to allocate counter",
-            ErrorKind::BusyTarget => "target is busy",
-            ErrorKind::BadTarget => "target PID does not exist",
-            ErrorKind::AlreadyAttached => "PMC already attached to target process",
-            ErrorKind::Forbidden => "forbidden",
-            _ => "unknown error",
-        }
+        self.message()
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.cause()
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{}", self.message())?;
+        if let Some(cause) = &self.cause {
+            write!(f, ": {}", cause)?;
+        }
+        Ok(())
     }
 }
 
@@ -102,23 +149,105 @@ impl PartialEq for Error {
 }
 
 impl Error {
+    fn message(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::Unknown => "unknown error",
+            ErrorKind::UnexpectedSignal => "unexpected signal",
+            ErrorKind::Init => "missing hwpmc in kernel",
+            ErrorKind::Unloaded => "hwpmc unloaded from kernel",
+            ErrorKind::Unsupported => "unsupported by the PMC backend",
+            ErrorKind::VersionMismatch => "unexpected hwpmc version",
+            ErrorKind::InvalidEventSpec => "invalid event specification",
+            ErrorKind::AllocInit => "failed to allocate counter",
+            ErrorKind::BusyTarget => "target is busy",
+            ErrorKind::BadTarget => "target PID does not exist",
+            ErrorKind::AlreadyAttached => "PMC already attached to target process",
+            ErrorKind::BadScope => "invalid scope for event",
+            ErrorKind::LogFileRequired => "event requires a log file",
+            ErrorKind::Forbidden => "forbidden",
+            ErrorKind::LogFileInUse => "log file in use by another sampler",
+            ErrorKind::InvalidFormula => "invalid metric formula",
+            ErrorKind::InvalidEventTable => "invalid event table",
+            ErrorKind::UnmappedEvent => "no native event for generic event",
+            ErrorKind::TargetExited => "target process exited",
+            ErrorKind::NotScheduled => "group was never scheduled on the PMU",
+            ErrorKind::Spawn => "failed to run command",
+        }
+    }
+
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
 
-    pub fn cause(&self) -> Option<&dyn std::error::Error> {
+    pub fn cause(&self) -> Option<&(dyn std::error::Error + 'static)> {
         self.cause.as_deref()
     }
 }
 
-pub(crate) fn new_os_error(kind: ErrorKind) -> Error {
-    // Get the last OS error to reference as the cause
+pub(crate) fn new_os_error(kind: ErrorKind, cause: io::Error) -> Error {
+    // Reference the OS error reported by the backend as the cause
     Error {
         kind,
-        cause: Some(Box::new(io::Error::last_os_error())),
+        cause: Some(Box::new(cause)),
     }
 }
 
 pub(crate) fn new_error(kind: ErrorKind) -> Error {
     Error { kind, cause: None }
 }
+
+pub(crate) fn new_parse_error(kind: ErrorKind, cause: ParseError) -> Error {
+    Error {
+        kind,
+        cause: Some(Box::new(cause)),
+    }
+}
+
+/// A description of why an input string could not be parsed, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    message: String,
+    position: usize,
+    token: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(message: impl Into<String>, position: usize, token: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            position,
+            token: token.into(),
+        }
+    }
+
+    /// A description of the problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The byte offset of the offending token in the input.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The offending token, or an empty string at the end of the input.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(f, "{} at end of input", self.message)
+        } else {
+            write!(
+                f,
+                "{} at position {} ('{}')",
+                self.message, self.position, self.token
+            )
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+
+use crate::cpuid::CpuId;
+use crate::spec::EventSpec;
+
+/// Kernel-provided software events that do not require a hardware PMU.
+///
+/// Software events can be allocated through the same [`CounterBuilder`] API as
+/// hardware events, making them useful on machines without PMC support (such
+/// as most virtual machines):
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let mut counter = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .allocate(SoftwareEvent::TaskClock)?;
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// Not all events are supported by every backend - unsupported events fail
+/// to allocate with [`ErrorKind::AllocInit`].
+///
+/// [`CounterBuilder`]: struct.CounterBuilder.html
+/// [`ErrorKind::AllocInit`]: enum.ErrorKind.html#variant.AllocInit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoftwareEvent {
+    /// Nanoseconds of CPU time consumed by the measured task(s).
+    TaskClock,
+
+    /// Nanoseconds elapsed on the per-CPU high-resolution timer.
+    CpuClock,
+
+    /// Number of context switches.
+    ContextSwitches,
+
+    /// Number of times a task migrated to a different CPU.
+    CpuMigrations,
+
+    /// Number of page faults, both minor and major.
+    PageFaults,
+
+    /// Number of minor page faults, serviced without disk I/O.
+    MinorPageFaults,
+
+    /// Number of major page faults, requiring disk I/O.
+    MajorPageFaults,
+}
+
+impl SoftwareEvent {
+    /// Returns the event name, as used by `perf(1)`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoftwareEvent::TaskClock => "task-clock",
+            SoftwareEvent::CpuClock => "cpu-clock",
+            SoftwareEvent::ContextSwitches => "context-switches",
+            SoftwareEvent::CpuMigrations => "cpu-migrations",
+            SoftwareEvent::PageFaults => "page-faults",
+            SoftwareEvent::MinorPageFaults => "minor-faults",
+            SoftwareEvent::MajorPageFaults => "major-faults",
+        }
+    }
+}
+
+impl fmt::Display for SoftwareEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl From<SoftwareEvent> for String {
+    fn from(event: SoftwareEvent) -> Self {
+        event.name().to_string()
+    }
+}
+
+/// Hardware events available under different names on every CPU vendor and
+/// backend.
+///
+/// The same event is named `inst_retired.any` on Intel and `ex_ret_instr` on
+/// AMD by `libpmc`, and `instructions` by `perf(1)`. A `GenericEvent` is
+/// resolved to the native event for the running CPU and backend by
+/// [`CounterBuilder::resolve`], or allocated directly with
+/// [`CounterBuilder::allocate_generic`]:
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let mut counter = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .allocate_generic(GenericEvent::Instructions)?;
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// Events without a native equivalent on the running CPU fail to resolve with
+/// [`ErrorKind::UnmappedEvent`].
+///
+/// [`CounterBuilder::resolve`]: struct.CounterBuilder.html#method.resolve
+/// [`CounterBuilder::allocate_generic`]: struct.CounterBuilder.html#method.allocate_generic
+/// [`ErrorKind::UnmappedEvent`]: enum.ErrorKind.html#variant.UnmappedEvent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GenericEvent {
+    /// Instructions retired.
+    Instructions,
+
+    /// Core cycles while not halted.
+    Cycles,
+
+    /// Branch instructions retired.
+    Branches,
+
+    /// Mispredicted branch instructions retired.
+    BranchMisses,
+
+    /// Cache accesses, usually of the last level cache.
+    CacheReferences,
+
+    /// Cache misses, usually of the last level cache.
+    CacheMisses,
+
+    /// Loads from the L1 data cache.
+    L1dLoads,
+
+    /// Loads missing the L1 data cache.
+    L1dLoadMisses,
+
+    /// Demand data loads from the L2 cache.
+    L2Loads,
+
+    /// Demand data loads missing the L2 cache.
+    L2LoadMisses,
+
+    /// Loads from the last level cache.
+    LlcLoads,
+
+    /// Loads missing the last level cache.
+    LlcLoadMisses,
+
+    /// Loads missing the data TLB.
+    DtlbLoadMisses,
+}
+
+// The libpmc (and vendor perfmon table) names of each generic event, by CPU
+// vendor. Some events only exist on recent models (the mem_load_retired and
+// mem_inst_retired events are Skylake onwards), so backends check the name is
+// an event of the running CPU.
+#[rustfmt::skip]
+const VENDOR_EVENTS: &[(&str, GenericEvent, &str)] = &[
+    ("GenuineIntel", GenericEvent::Instructions, "inst_retired.any"),
+    ("GenuineIntel", GenericEvent::Cycles, "cpu_clk_unhalted.thread"),
+    ("GenuineIntel", GenericEvent::Branches, "br_inst_retired.all_branches"),
+    ("GenuineIntel", GenericEvent::BranchMisses, "br_misp_retired.all_branches"),
+    ("GenuineIntel", GenericEvent::CacheReferences, "longest_lat_cache.reference"),
+    ("GenuineIntel", GenericEvent::CacheMisses, "longest_lat_cache.miss"),
+    ("GenuineIntel", GenericEvent::L1dLoads, "mem_inst_retired.all_loads"),
+    ("GenuineIntel", GenericEvent::L1dLoadMisses, "mem_load_retired.l1_miss"),
+    ("GenuineIntel", GenericEvent::L2Loads, "l2_rqsts.all_demand_data_rd"),
+    ("GenuineIntel", GenericEvent::L2LoadMisses, "l2_rqsts.demand_data_rd_miss"),
+    ("GenuineIntel", GenericEvent::LlcLoadMisses, "mem_load_retired.l3_miss"),
+    ("GenuineIntel", GenericEvent::DtlbLoadMisses, "dtlb_load_misses.miss_causes_a_walk"),
+    ("AuthenticAMD", GenericEvent::Instructions, "ex_ret_instr"),
+    ("AuthenticAMD", GenericEvent::Cycles, "ls_not_halted_cyc"),
+    ("AuthenticAMD", GenericEvent::Branches, "ex_ret_brn"),
+    ("AuthenticAMD", GenericEvent::BranchMisses, "ex_ret_brn_misp"),
+    ("AuthenticAMD", GenericEvent::DtlbLoadMisses, "ls_l1_d_tlb_miss.all"),
+];
+
+// Every generic event, for looking up events by name.
+const ALL_GENERIC_EVENTS: &[GenericEvent] = &[
+    GenericEvent::Instructions,
+    GenericEvent::Cycles,
+    GenericEvent::Branches,
+    GenericEvent::BranchMisses,
+    GenericEvent::CacheReferences,
+    GenericEvent::CacheMisses,
+    GenericEvent::L1dLoads,
+    GenericEvent::L1dLoadMisses,
+    GenericEvent::L2Loads,
+    GenericEvent::L2LoadMisses,
+    GenericEvent::LlcLoads,
+    GenericEvent::LlcLoadMisses,
+    GenericEvent::DtlbLoadMisses,
+];
+
+impl GenericEvent {
+    /// Returns the generic event called `name` (as returned by [`name`]), if
+    /// any.
+    ///
+    /// ```
+    /// use pmc::*;
+    ///
+    /// assert_eq!(GenericEvent::from_name("branch-misses"), Some(GenericEvent::BranchMisses));
+    /// assert_eq!(GenericEvent::from_name("inst_retired.any"), None);
+    /// ```
+    ///
+    /// [`name`]: #method.name
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL_GENERIC_EVENTS
+            .iter()
+            .copied()
+            .find(|e| e.name() == name)
+    }
+
+    /// Returns the event name, as used by `perf(1)`.
+    ///
+    /// `perf(1)` has no generic L2 cache events, so these are named in the
+    /// same style as the last level cache events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GenericEvent::Instructions => "instructions",
+            GenericEvent::Cycles => "cycles",
+            GenericEvent::Branches => "branches",
+            GenericEvent::BranchMisses => "branch-misses",
+            GenericEvent::CacheReferences => "cache-references",
+            GenericEvent::CacheMisses => "cache-misses",
+            GenericEvent::L1dLoads => "L1-dcache-loads",
+            GenericEvent::L1dLoadMisses => "L1-dcache-load-misses",
+            GenericEvent::L2Loads => "L2-loads",
+            GenericEvent::L2LoadMisses => "L2-load-misses",
+            GenericEvent::LlcLoads => "LLC-loads",
+            GenericEvent::LlcLoadMisses => "LLC-load-misses",
+            GenericEvent::DtlbLoadMisses => "dTLB-load-misses",
+        }
+    }
+
+    /// Returns the name of the vendor-defined event counting this event on
+    /// `cpu`, as used by `libpmc` and the vendor perfmon tables.
+    ///
+    /// Returns `None` if the vendor has no equivalent core event - the last
+    /// level cache of AMD CPUs is counted by a separate PMU, for example. The
+    /// event is not necessarily available on older models of the vendor's
+    /// CPUs.
+    ///
+    /// ```
+    /// use pmc::*;
+    ///
+    /// let zen2 = CpuId::new("AuthenticAMD", 23, 0x31, 0);
+    /// assert_eq!(GenericEvent::Instructions.vendor_name(&zen2), Some("ex_ret_instr"));
+    /// assert_eq!(GenericEvent::CacheMisses.vendor_name(&zen2), None);
+    /// ```
+    pub fn vendor_name(&self, cpu: &CpuId) -> Option<&'static str> {
+        VENDOR_EVENTS
+            .iter()
+            .find(|&&(vendor, event, _)| vendor == cpu.vendor() && event == *self)
+            .map(|&(_, _, name)| name)
+    }
+}
+
+impl fmt::Display for GenericEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An event programmed directly by its x86 event select code and modifiers,
+/// for events the backend has no name for.
+///
+/// A `RawEvent` can be passed to [`CounterBuilder`] anywhere an event spec
+/// is accepted. It converts to the `rNNNN` spec naming the [`config`]
+/// encoding, which the Linux backend counts as a `PERF_TYPE_RAW` event, and
+/// `libpmc` is given as `event=0xNN,umask=0xNN` with the equivalent
+/// qualifiers. Qualifiers such as `usr` may be added to the converted
+/// [`EventSpec`].
+///
+/// ```
+/// use pmc::*;
+///
+/// // UOPS_ISSUED.STALL_CYCLES on Skylake
+/// let stalls = RawEvent::new(0x0e, 0x01).cmask(1).inv();
+/// assert_eq!(stalls.config(), 0x0180010e);
+/// assert_eq!(stalls.to_string(), "r180010e");
+///
+/// let spec = EventSpec::from(stalls).usr();
+/// assert_eq!(spec.to_string(), "r180010e,usr");
+/// ```
+///
+/// [`CounterBuilder`]: struct.CounterBuilder.html
+/// [`config`]: #method.config
+/// [`EventSpec`]: struct.EventSpec.html
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct RawEvent {
+    event: u16,
+    umask: u8,
+    cmask: u8,
+    inv: bool,
+    edge: bool,
+    any_thread: bool,
+}
+
+impl RawEvent {
+    /// Initialise a raw event selecting `event` with the unit mask `umask`.
+    ///
+    /// AMD event select codes are up to 12 bits wide, Intel codes 8 bits.
+    pub fn new(event: u16, umask: u8) -> Self {
+        Self {
+            event: event & 0xfff,
+            umask,
+            ..Default::default()
+        }
+    }
+
+    /// Decode an event from its event select register layout - the inverse
+    /// of [`config`].
+    ///
+    /// [`config`]: #method.config
+    pub fn from_config(config: u64) -> Self {
+        Self {
+            event: ((config & 0xff) | ((config >> 32) & 0xf) << 8) as u16,
+            umask: (config >> 8) as u8,
+            cmask: (config >> 24) as u8,
+            inv: config & (1 << 23) != 0,
+            edge: config & (1 << 18) != 0,
+            any_thread: config & (1 << 21) != 0,
+        }
+    }
+
+    /// Only count cycles where at least `cmask` events occur.
+    pub fn cmask(mut self, cmask: u8) -> Self {
+        self.cmask = cmask;
+        self
+    }
+
+    /// Invert the counter mask comparison.
+    pub fn inv(mut self) -> Self {
+        self.inv = true;
+        self
+    }
+
+    /// Count the rising edges of the event condition.
+    pub fn edge(mut self) -> Self {
+        self.edge = true;
+        self
+    }
+
+    /// Count events from any hardware thread on the core.
+    pub fn any_thread(mut self) -> Self {
+        self.any_thread = true;
+        self
+    }
+
+    /// The event select code.
+    pub fn event_select(&self) -> u16 {
+        self.event
+    }
+
+    /// The unit mask.
+    pub fn unit_mask(&self) -> u8 {
+        self.umask
+    }
+
+    /// The counter mask, or 0 to count every event.
+    pub fn counter_mask(&self) -> u8 {
+        self.cmask
+    }
+
+    /// Returns true if the counter mask comparison is inverted.
+    pub fn is_inverted(&self) -> bool {
+        self.inv
+    }
+
+    /// Returns true if rising edges are counted.
+    pub fn is_edge(&self) -> bool {
+        self.edge
+    }
+
+    /// Returns true if every hardware thread on the core is counted.
+    pub fn is_any_thread(&self) -> bool {
+        self.any_thread
+    }
+
+    /// Returns the event encoded as the x86 event select register, as used
+    /// by `PERF_TYPE_RAW` events.
+    ///
+    /// The upper 4 bits of AMD event codes are placed in bits 32-35.
+    pub fn config(&self) -> u64 {
+        let event = u64::from(self.event);
+
+        (event & 0xff)
+            | u64::from(self.umask) << 8
+            | u64::from(self.edge) << 18
+            | u64::from(self.any_thread) << 21
+            | u64::from(self.inv) << 23
+            | u64::from(self.cmask) << 24
+            | (event >> 8) << 32
+    }
+
+    /// Returns the raw event named by `spec`, if its name is in the `rNNNN`
+    /// form a `RawEvent` converts to.
+    ///
+    /// Configs setting bits outside those a `RawEvent` encodes are not raw
+    /// events of this form.
+    ///
+    /// ```
+    /// use pmc::*;
+    ///
+    /// let spec = EventSpec::parse("r180010e,usr")?;
+    /// assert_eq!(RawEvent::from_spec(&spec), Some(RawEvent::new(0x0e, 0x01).cmask(1).inv()));
+    ///
+    /// // Bit 22 (the enable bit) is not part of a RawEvent.
+    /// assert_eq!(RawEvent::from_spec(&EventSpec::new("r4000c0")), None);
+    /// assert_eq!(RawEvent::from_spec(&EventSpec::new("instructions")), None);
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn from_spec(spec: &EventSpec) -> Option<Self> {
+        let hex = spec.name().strip_prefix('r')?;
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let config = u64::from_str_radix(hex, 16).ok()?;
+        let raw = Self::from_config(config);
+        if raw.config() != config {
+            return None;
+        }
+        Some(raw)
+    }
+}
+
+impl fmt::Display for RawEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "r{:x}", self.config())
+    }
+}
+
+impl From<RawEvent> for String {
+    fn from(event: RawEvent) -> Self {
+        event.to_string()
+    }
+}
+
+impl From<RawEvent> for EventSpec {
+    fn from(event: RawEvent) -> Self {
+        EventSpec::new(event.to_string())
+    }
+}
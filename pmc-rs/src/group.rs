@@ -0,0 +1,205 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::backend::{Backend, PmcId};
+use crate::counter::{read_error, start_error, Counter};
+use crate::error::Error;
+use crate::measure::Measurement;
+
+/// A set of counters that are started, stopped and read together.
+///
+/// Groups are initialised using [`CounterBuilder::group`]. All the counters
+/// in a group cover the same measurement window, making them suitable for
+/// computing ratios such as instructions per cycle or cache hit rates.
+///
+/// Backends that support hardware event groups (such as [`Perf`]) schedule
+/// and read the group atomically, others start and read the counters in quick
+/// succession.
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let mut group = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .group(vec!["instructions", "cycles"])?;
+///
+/// let handle = group.start()?;
+///
+/// // Do some work...
+///
+/// handle.stop();
+///
+/// for (label, value) in group.read()?.iter() {
+///     println!("{}: {}", label, value);
+/// }
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`CounterBuilder::group`]: struct.CounterBuilder.html#method.group
+/// [`Perf`]: backend/struct.Perf.html
+#[derive(Debug)]
+pub struct CounterGroup {
+    // The group leader is always first.
+    counters: Vec<Counter>,
+    labels: Vec<String>,
+    ids: Vec<PmcId>,
+}
+
+impl CounterGroup {
+    pub(crate) fn new(counters: Vec<Counter>, labels: Vec<String>) -> Self {
+        let ids = counters.iter().map(|c| c.id()).collect();
+        Self {
+            counters,
+            labels,
+            ids,
+        }
+    }
+
+    /// Returns the labels of the events in this group, in allocation order.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Start all the counters in this group.
+    ///
+    /// The counters stop when the returned [`RunningGroup`] handle is dropped.
+    ///
+    /// [`RunningGroup`]: struct.RunningGroup.html
+    #[must_use = "group only runs until handle is dropped"]
+    pub fn start(&mut self) -> Result<RunningGroup<'_>, Error> {
+        self.backend().start_group(&self.ids).map_err(start_error)?;
+
+        Ok(RunningGroup { group: self })
+    }
+
+    /// Read the values of all counters in this group at the same point in
+    /// time.
+    ///
+    /// Returns a [`NotScheduled`] error if the group was started but the PMU
+    /// was never able to count it.
+    ///
+    /// [`NotScheduled`]: enum.ErrorKind.html#variant.NotScheduled
+    pub fn read(&self) -> Result<GroupReading, Error> {
+        let values = self.backend().read_group(&self.ids).map_err(read_error)?;
+
+        Ok(GroupReading {
+            values: self.labels.iter().cloned().zip(values).collect(),
+        })
+    }
+
+    /// Count the events in this group while running `f`, returning the
+    /// result of `f` and the counts.
+    ///
+    /// The counters are stopped when `f` returns - or unwinds, should it
+    /// panic. The counts cover only the execution of `f`, but are added to
+    /// any counted before.
+    pub fn measure<T>(&mut self, f: impl FnOnce() -> T) -> Result<(T, Measurement), Error> {
+        let handle = self.start()?;
+        let started = Instant::now();
+
+        let result = f();
+
+        let elapsed = started.elapsed();
+        handle.stop();
+
+        Ok((result, Measurement::new(self.read()?, elapsed)))
+    }
+
+    pub(crate) fn ids(&self) -> &[PmcId] {
+        &self.ids
+    }
+
+    pub(crate) fn backend(&self) -> &Arc<dyn Backend> {
+        // Groups always contain at least one counter.
+        self.counters[0].backend()
+    }
+}
+
+impl Drop for CounterGroup {
+    fn drop(&mut self) {
+        // Release the group members before the leader.
+        while let Some(counter) = self.counters.pop() {
+            drop(counter)
+        }
+    }
+}
+
+/// A handle to a running [`CounterGroup`].
+///
+/// Dropping this handle causes all counters in the group to stop recording
+/// events.
+///
+/// [`CounterGroup`]: struct.CounterGroup.html
+#[derive(Debug)]
+pub struct RunningGroup<'a> {
+    group: &'a mut CounterGroup,
+}
+
+impl<'a> RunningGroup<'a> {
+    /// Read the current values of all counters in the group.
+    pub fn read(&self) -> Result<GroupReading, Error> {
+        self.group.read()
+    }
+
+    /// Stop the counters from recording new events.
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl<'a> Drop for RunningGroup<'a> {
+    fn drop(&mut self) {
+        let _ = self.group.backend().stop_group(&self.group.ids);
+    }
+}
+
+/// The values of a [`CounterGroup`], read at the same point in time.
+///
+/// [`CounterGroup`]: struct.CounterGroup.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupReading {
+    values: Vec<(String, u64)>,
+}
+
+impl GroupReading {
+    pub(crate) fn new(values: Vec<(String, u64)>) -> Self {
+        Self { values }
+    }
+
+    /// Returns the value of the event labelled `label`.
+    pub fn get(&self, label: &str) -> Option<u64> {
+        self.values
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|&(_, v)| v)
+    }
+
+    /// Returns an iterator of `(label, value)` pairs, in allocation order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.values.iter().map(|(l, v)| (l.as_str(), *v))
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Display for GroupReading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (label, value)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", label, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+//! A minimal JSON reader, sufficient for loading vendor event tables without
+//! pulling in a serialisation framework.
+
+use crate::error::ParseError;
+
+// The deepest nesting of arrays and objects accepted. Event tables nest two
+// levels deep, and the limit stops hostile input from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// A parsed JSON value, and the byte offset it starts at.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Json {
+    pub(crate) pos: usize,
+    pub(crate) value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Returns the value of `key` if this is an object containing it.
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match &self.value {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Json]> {
+        match &self.value {
+            Value::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// Parse `input` as a single JSON document.
+pub(crate) fn parse(input: &str) -> Result<Json, ParseError> {
+    let mut p = Parser {
+        input,
+        pos: 0,
+        depth: 0,
+    };
+    let value = p.value()?;
+    p.skip_whitespace();
+    if p.pos < input.len() {
+        return Err(p.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    /// An error at the current position, quoting the character found.
+    fn error(&self, message: &str) -> ParseError {
+        let token = self.peek().map(|c| c.to_string()).unwrap_or_default();
+        ParseError::new(message, self.pos, token)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, ParseError> {
+        self.skip_whitespace();
+        let pos = self.pos;
+
+        let value = match self.peek() {
+            Some('{') | Some('[') if self.depth == MAX_DEPTH => {
+                return Err(self.error("nesting too deep"))
+            }
+            Some('{') => self.nested(Self::object)?,
+            Some('[') => self.nested(Self::array)?,
+            Some('"') => Value::String(self.string()?),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number()?,
+            Some(_) => self.literal()?,
+            None => return Err(self.error("expected value")),
+        };
+
+        Ok(Json { pos, value })
+    }
+
+    /// Parse an array or object with `f`, one level deeper.
+    fn nested(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<Value, ParseError>,
+    ) -> Result<Value, ParseError> {
+        self.depth += 1;
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self) -> Result<Value, ParseError> {
+        let rest = &self.input[self.pos..];
+        for (text, value) in &[
+            ("null", Value::Null),
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+        ] {
+            if rest.starts_with(text) {
+                self.pos += text.len();
+                return Ok(value.clone());
+            }
+        }
+        Err(self.error("expected value"))
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        let text = &self.input[start..self.pos];
+        text.parse()
+            .map(Value::Number)
+            .map_err(|_| ParseError::new("invalid number", start, text))
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+
+        let mut out = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.pos += c.len_utf8();
+
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escape = self.pos - 1;
+                    let c = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += c.len_utf8();
+                    out.push(match c {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let hex = self.input.get(self.pos..self.pos + 4).unwrap_or("");
+                            let code = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+                            self.pos += hex.len();
+                            // Surrogate pairs are not needed for event tables,
+                            // and are replaced.
+                            code.unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        c => {
+                            return Err(ParseError::new(
+                                "invalid escape",
+                                escape,
+                                format!("\\{}", c),
+                            ))
+                        }
+                    });
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect('[')?;
+        let mut out = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(out));
+        }
+
+        loop {
+            out.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(out));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.expect('{')?;
+        let mut out = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(out));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            out.push((key, self.value()?));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(out));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 #![warn(missing_docs)]
 
 //! `pmc-rs` provides a safe abstraction for interacting with Performance
-//! Monitor Counters on [`FreeBSD`].
+//! Monitor Counters on [`FreeBSD`] and Linux.
 //!
 //! PMCs are part of the CPU hardware and are typically used to profile CPU
 //! micro-architecture events such as L1/L2/etc cache hit ratio, instructions
@@ -17,11 +17,17 @@
 //! Encodings for Pre-Defined Architectural Performance Events"`).
 //!
 //! `pmc-rs` makes use of [`libpmc`] and the [`hwpmc`] kernel module on
-//! [`FreeBSD`].
+//! [`FreeBSD`], and the [`perf_event_open(2)`] interface on Linux. The system
+//! PMC interface is accessed through a pluggable
+//! [`Backend`], allowing alternative implementations to be used by a
+//! [`CounterBuilder`].
 //!
+//! [`Backend`]: backend/trait.Backend.html
+//! [`CounterBuilder`]: struct.CounterBuilder.html
 //! [`FreeBSD`]: https://www.freebsd.org/
 //! [`hwpmc`]: https://www.freebsd.org/cgi/man.cgi?query=hwpmc
 //! [`libpmc`]: https://www.freebsd.org/cgi/man.cgi?query=pmc
+//! [`perf_event_open(2)`]: https://man7.org/linux/man-pages/man2/perf_event_open.2.html
 
 #[macro_use]
 extern crate lazy_static;
@@ -33,8 +39,51 @@ pub use error::*;
 mod counter;
 pub use counter::*;
 
-#[cfg(not(target_os = "freebsd"))]
-mod stubs;
+mod event;
+pub use event::*;
+
+mod sampler;
+pub use sampler::*;
+
+mod group;
+pub use group::*;
+
+mod metrics;
+pub use metrics::*;
+
+mod measure;
+pub use measure::*;
+
+mod command;
+
+mod multiplex;
+pub use multiplex::*;
+
+mod poller;
+pub use poller::*;
+
+mod percpu;
+pub use percpu::*;
+
+mod split;
+pub use split::*;
+
+mod spec;
+pub use spec::*;
+
+mod catalog;
+pub use catalog::*;
+
+mod cpuid;
+pub use cpuid::*;
+
+mod json;
+
+mod perfmon;
+pub use perfmon::*;
+
+pub mod backend;
+pub use backend::is_available;
 
 #[cfg(not(target_os = "freebsd"))]
 const _CPU_ANY: i32 = -1;
@@ -47,5 +96,3 @@ const _CPU_ANY: i32 = pmc_sys::PMC_CPU_ANY;
 /// `CPU_ANY` is a convenience value for readability and should be preferred
 /// over using `0` directly.
 pub const CPU_ANY: i32 = _CPU_ANY;
-
-// TODO: add sampler type that records to a log file
@@ -0,0 +1,50 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::group::GroupReading;
+
+/// The counts and wall time of a measured closure.
+///
+/// Measurements are taken by [`CounterBuilder::measure`],
+/// [`CounterBuilder::measure_group`] and [`CounterGroup::measure`].
+///
+/// [`CounterBuilder::measure`]: struct.CounterBuilder.html#method.measure
+/// [`CounterBuilder::measure_group`]: struct.CounterBuilder.html#method.measure_group
+/// [`CounterGroup::measure`]: struct.CounterGroup.html#method.measure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    counts: GroupReading,
+    elapsed: Duration,
+}
+
+impl Measurement {
+    pub(crate) fn new(counts: GroupReading, elapsed: Duration) -> Self {
+        Self { counts, elapsed }
+    }
+
+    /// Returns the count of the event labelled `label`.
+    pub fn get(&self, label: &str) -> Option<u64> {
+        self.counts.get(label)
+    }
+
+    /// Returns the count of the first (or only) measured event.
+    pub fn value(&self) -> u64 {
+        self.counts.iter().next().map(|(_, v)| v).unwrap_or(0)
+    }
+
+    /// Returns the counts of all the measured events.
+    pub fn counts(&self) -> &GroupReading {
+        &self.counts
+    }
+
+    /// Returns the wall time the closure ran for.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {:?}", self.counts, self.elapsed)
+    }
+}
@@ -0,0 +1,491 @@
+use std::fmt;
+
+use crate::counter::CounterBuilder;
+use crate::error::{new_error, new_parse_error, Error, ErrorKind, ParseError};
+use crate::event::GenericEvent;
+use crate::group::GroupReading;
+use crate::multiplex::Multiplexer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(f64),
+    Event(String),
+    Neg(Box<Expr>),
+    Bin(Box<Expr>, BinOp, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, lookup: &dyn Fn(&str) -> Option<f64>) -> Option<f64> {
+        match self {
+            Expr::Num(v) => Some(*v),
+            Expr::Event(name) => lookup(name),
+            Expr::Neg(e) => e.eval(lookup).map(|v| -v),
+            Expr::Bin(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(lookup)?, rhs.eval(lookup)?);
+                match op {
+                    BinOp::Add => Some(lhs + rhs),
+                    BinOp::Sub => Some(lhs - rhs),
+                    BinOp::Mul => Some(lhs * rhs),
+                    BinOp::Div if rhs == 0.0 => None,
+                    BinOp::Div => Some(lhs / rhs),
+                }
+            }
+        }
+    }
+
+    fn events<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Expr::Num(_) => {}
+            Expr::Event(name) => {
+                if !out.contains(&name.as_str()) {
+                    out.push(name)
+                }
+            }
+            Expr::Neg(e) => e.events(out),
+            Expr::Bin(lhs, _, rhs) => {
+                lhs.events(out);
+                rhs.events(out);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+/// Split a formula into `(position, token)` pairs.
+fn tokenise(formula: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<(usize, char)> = formula.char_indices().collect();
+    let mut tokens = vec![];
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '+' | '-' | '*' | '/' => {
+                tokens.push((pos, Token::Op(c)));
+                i += 1;
+            }
+            '(' => {
+                tokens.push((pos, Token::Open));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((pos, Token::Close));
+                i += 1;
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.') {
+                    i += 1;
+                }
+                let end = chars.get(i).map(|&(p, _)| p).unwrap_or(formula.len());
+                let text = &formula[pos..end];
+                let value = text
+                    .parse()
+                    .map_err(|_| ParseError::new("invalid number", chars[start].0, text))?;
+                tokens.push((pos, Token::Num(value)));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                // Event names may contain '-' (as in "branch-misses") when it
+                // is directly followed by a letter - subtraction requires
+                // whitespace or a non-letter operand.
+                while i < chars.len() {
+                    let c = chars[i].1;
+                    let next_alpha = chars.get(i + 1).map(|&(_, c)| c.is_alphabetic()) == Some(true);
+                    if c.is_alphanumeric() || "_.:@".contains(c) || (c == '-' && next_alpha) {
+                        i += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let end = chars.get(i).map(|&(p, _)| p).unwrap_or(formula.len());
+                tokens.push((pos, Token::Ident(formula[pos..end].to_string())));
+            }
+            c => return Err(ParseError::new("unexpected character", pos, c.to_string())),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser for metric formulas.
+struct Parser<'a> {
+    formula: &'a str,
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        match self.tokens.get(self.pos) {
+            Some((pos, token)) => {
+                let text = match token {
+                    Token::Num(_) | Token::Ident(_) => self.formula[*pos..]
+                        .split(|c: char| c.is_whitespace() || "()*/+".contains(c))
+                        .next()
+                        .unwrap_or_default(),
+                    _ => &self.formula[*pos..*pos + 1],
+                };
+                ParseError::new(message, *pos, text)
+            }
+            None => ParseError::new(message, self.formula.len(), ""),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(c @ '+')) | Some(Token::Op(c @ '-')) = self.peek() {
+            let op = if *c == '+' { BinOp::Add } else { BinOp::Sub };
+            self.pos += 1;
+            lhs = Expr::Bin(Box::new(lhs), op, Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Op(c @ '*')) | Some(Token::Op(c @ '/')) = self.peek() {
+            let op = if *c == '*' { BinOp::Mul } else { BinOp::Div };
+            self.pos += 1;
+            lhs = Expr::Bin(Box::new(lhs), op, Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        let expr = match self.peek().cloned() {
+            Some(Token::Num(v)) => Expr::Num(v),
+            Some(Token::Ident(name)) => Expr::Event(name),
+            Some(Token::Op('-')) => {
+                self.pos += 1;
+                return Ok(Expr::Neg(Box::new(self.factor()?)));
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(self.error("expected ')'"));
+                }
+                expr
+            }
+            _ => return Err(self.error("expected event name, number or '('")),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+fn parse(formula: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        formula,
+        tokens: tokenise(formula)?,
+        pos: 0,
+    };
+
+    let expr = parser.expr()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(parser.error("unexpected token"));
+    }
+    Ok(expr)
+}
+
+/// A named formula computed over event counter values.
+///
+/// Formulas reference events by name, and support `+`, `-`, `*`, `/`,
+/// numeric constants and parentheses. An event name may contain `-` (such as
+/// `branch-misses`), so subtraction must be surrounded by whitespace.
+///
+/// Events named after a [`GenericEvent`] (as the built-in metrics are) are
+/// resolved to the native event for the running CPU and backend when
+/// allocated by a [`MetricSet`].
+///
+/// ```
+/// use pmc::*;
+///
+/// let ipc = Metric::new("ipc", "inst_retired.any / cpu_clk_unhalted.thread")?;
+///
+/// assert_eq!(ipc.events(), vec!["inst_retired.any", "cpu_clk_unhalted.thread"]);
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`GenericEvent`]: enum.GenericEvent.html
+/// [`MetricSet`]: struct.MetricSet.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    name: String,
+    formula: String,
+    expr: Expr,
+}
+
+impl Metric {
+    /// Parse `formula` into a metric called `name`.
+    ///
+    /// Returns an [`ErrorKind::InvalidFormula`] error if the formula cannot
+    /// be parsed.
+    ///
+    /// [`ErrorKind::InvalidFormula`]: enum.ErrorKind.html#variant.InvalidFormula
+    pub fn new(name: impl Into<String>, formula: impl Into<String>) -> Result<Self, Error> {
+        let formula = formula.into();
+        let expr = parse(&formula).map_err(|e| new_parse_error(ErrorKind::InvalidFormula, e))?;
+
+        Ok(Self {
+            name: name.into(),
+            formula,
+            expr,
+        })
+    }
+
+    /// Instructions retired per unhalted core cycle.
+    pub fn ipc() -> Self {
+        builtin("ipc", "instructions / cycles")
+    }
+
+    /// Unhalted core cycles per instruction retired.
+    pub fn cpi() -> Self {
+        builtin("cpi", "cycles / instructions")
+    }
+
+    /// The fraction of retired branches that were mispredicted.
+    pub fn branch_mispredict_rate() -> Self {
+        builtin("branch_mispredict_rate", "branch-misses / branches")
+    }
+
+    /// The fraction of loads that missed the L1 data cache.
+    pub fn l1_miss_ratio() -> Self {
+        builtin("l1_miss_ratio", "L1-dcache-load-misses / L1-dcache-loads")
+    }
+
+    /// The fraction of demand data loads reaching the L2 cache that missed it.
+    pub fn l2_miss_ratio() -> Self {
+        builtin("l2_miss_ratio", "L2-load-misses / L2-loads")
+    }
+
+    /// The fraction of loads reaching the last level cache that missed it.
+    pub fn llc_miss_ratio() -> Self {
+        builtin("llc_miss_ratio", "LLC-load-misses / LLC-loads")
+    }
+
+    /// Returns the name of the metric.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the formula the metric was parsed from.
+    pub fn formula(&self) -> &str {
+        &self.formula
+    }
+
+    /// Returns the events referenced by the formula, in order of first use.
+    pub fn events(&self) -> Vec<&str> {
+        let mut out = vec![];
+        self.expr.events(&mut out);
+        out
+    }
+
+    /// Evaluate the metric over the values in `reading`.
+    ///
+    /// Returns `None` if an event is missing from `reading`, or the formula
+    /// divides by zero.
+    pub fn evaluate(&self, reading: &GroupReading) -> Option<f64> {
+        self.evaluate_with(|event| reading.get(event).map(|v| v as f64))
+    }
+
+    /// Evaluate the metric, resolving event values with `lookup`.
+    pub fn evaluate_with(&self, lookup: impl Fn(&str) -> Option<f64>) -> Option<f64> {
+        self.expr.eval(&lookup)
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.name, self.formula)
+    }
+}
+
+fn builtin(name: &str, formula: &str) -> Metric {
+    Metric::new(name, formula).expect("invalid built-in metric")
+}
+
+/// A set of [`Metric`]s computed over the same measurement.
+///
+/// The set determines the events needed to compute all of its metrics, and
+/// allocates them as a [`Multiplexer`] - a set of metrics usually needs more
+/// events than the CPU has counters. Each metric is computed over the scaled
+/// estimates of its events.
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let metrics = MetricSet::default()
+///     .with(Metric::ipc())
+///     .with(Metric::new("misses_per_instr", "LLC-load-misses / instructions")?);
+///
+/// let mut mux = metrics.allocate(&CounterBuilder::default().attach_to(vec![0]), 4)?;
+///
+/// let handle = mux.start()?;
+///
+/// // Do some work...
+///
+/// println!("{}", metrics.evaluate(&handle.read()?.scaled()));
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Metric`]: struct.Metric.html
+/// [`Multiplexer`]: struct.Multiplexer.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricSet {
+    metrics: Vec<Metric>,
+}
+
+impl MetricSet {
+    /// Returns a set containing all the built-in metrics.
+    pub fn standard() -> Self {
+        Self::default()
+            .with(Metric::ipc())
+            .with(Metric::cpi())
+            .with(Metric::branch_mispredict_rate())
+            .with(Metric::l1_miss_ratio())
+            .with(Metric::l2_miss_ratio())
+            .with(Metric::llc_miss_ratio())
+    }
+
+    /// Add `metric` to the set.
+    pub fn with(mut self, metric: Metric) -> Self {
+        self.metrics.push(metric);
+        self
+    }
+
+    /// Returns the metrics in the set.
+    pub fn metrics(&self) -> &[Metric] {
+        &self.metrics
+    }
+
+    /// Returns the events required to compute every metric in the set, in
+    /// order of first use.
+    pub fn events(&self) -> Vec<&str> {
+        let mut out: Vec<&str> = vec![];
+        for event in self.metrics.iter().flat_map(|m| m.events()) {
+            if !out.contains(&event) {
+                out.push(event);
+            }
+        }
+        out
+    }
+
+    /// Allocate a [`Multiplexer`] counting all the events required by the
+    /// set, using the configuration in `builder`, and rotating the events
+    /// over `width` counters if the backend does not multiplex them itself.
+    ///
+    /// Events named after a [`GenericEvent`] are resolved with
+    /// [`CounterBuilder::resolve`]. Generic events without a native
+    /// equivalent on the running CPU, or whose native event cannot be
+    /// allocated, are not counted, so the metrics using them evaluate to
+    /// `None` - an [`UnmappedEvent`] error is only returned if none of the
+    /// events can be counted.
+    ///
+    /// [`Multiplexer`]: struct.Multiplexer.html
+    /// [`GenericEvent`]: enum.GenericEvent.html
+    /// [`CounterBuilder::resolve`]: struct.CounterBuilder.html#method.resolve
+    /// [`UnmappedEvent`]: enum.ErrorKind.html#variant.UnmappedEvent
+    pub fn allocate(&self, builder: &CounterBuilder, width: usize) -> Result<Multiplexer, Error> {
+        let mut events = vec![];
+        for event in self.events() {
+            let spec = match GenericEvent::from_name(event).map(|e| builder.resolve(e)) {
+                None => event.to_string(),
+                Some(Ok(spec)) => spec.to_string(),
+                Some(Err(e)) if e.kind() == &ErrorKind::UnmappedEvent => continue,
+                Some(Err(e)) => return Err(e),
+            };
+
+            // A resolved name is not necessarily an event the CPU has, so
+            // generic events are probed by allocating them once.
+            if GenericEvent::from_name(event).is_some() {
+                match builder.allocate(spec.clone()) {
+                    Ok(_) => (),
+                    Err(e) if e.kind() == &ErrorKind::AllocInit => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            events.push((event.to_string(), spec));
+        }
+
+        if events.is_empty() && !self.metrics.is_empty() {
+            return Err(new_error(ErrorKind::UnmappedEvent));
+        }
+        builder.multiplex_labelled(events, width)
+    }
+
+    /// Evaluate every metric in the set over the values in `reading`.
+    pub fn evaluate(&self, reading: &GroupReading) -> MetricValues {
+        MetricValues {
+            values: self
+                .metrics
+                .iter()
+                .map(|m| (m.name.clone(), m.evaluate(reading)))
+                .collect(),
+        }
+    }
+}
+
+/// The computed values of a [`MetricSet`].
+///
+/// [`MetricSet`]: struct.MetricSet.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricValues {
+    values: Vec<(String, Option<f64>)>,
+}
+
+impl MetricValues {
+    /// Returns the value of the metric called `name`.
+    ///
+    /// Returns `None` if there is no such metric, or it could not be computed.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.values
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|&(_, v)| v)
+    }
+
+    /// Returns an iterator of `(name, value)` pairs, in the order the metrics
+    /// were added to the set.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<f64>)> {
+        self.values.iter().map(|(n, v)| (n.as_str(), *v))
+    }
+}
+
+impl fmt::Display for MetricValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match value {
+                Some(v) => write!(f, "{}: {:.4}", name, v)?,
+                None => write!(f, "{}: n/a", name)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,519 @@
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::backend::{AllocSpec, Backend, TimedValue};
+use crate::counter::{read_error, start_error, Counter};
+use crate::error::{new_error, Error, ErrorKind};
+use crate::group::GroupReading;
+
+/// The default interval between rotations of userspace-multiplexed counters.
+pub const DEFAULT_MULTIPLEX_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+struct State {
+    backend: Arc<dyn Backend>,
+    specs: Vec<AllocSpec>,
+    pids: Option<Vec<i32>>,
+
+    // Every counter when the backend multiplexes them, otherwise only the
+    // counters of the current slice - a slice's counters are released when
+    // it is rotated out, so no more than `width` PMCs are allocated at once.
+    counters: Vec<Option<Counter>>,
+
+    // Whether the backend reports enabled & running times itself.
+    kernel_timed: bool,
+
+    // Userspace rotation state - the counters are rotated in slices of
+    // `width` counters.
+    width: usize,
+    slice: usize,
+    slice_started: Option<Instant>,
+    time_enabled: Duration,
+    time_running: Vec<Duration>,
+
+    // The counts of released counters, from the slices they ran in.
+    counted: Vec<u64>,
+
+    // The kind of the first error rotating in the background since the last
+    // read.
+    rotate_error: Option<ErrorKind>,
+}
+
+impl State {
+    fn slice_range(&self) -> std::ops::Range<usize> {
+        let start = self.slice * self.width;
+        start..(start + self.width).min(self.specs.len())
+    }
+
+    // usize::div_ceil needs Rust 1.73.
+    #[allow(clippy::manual_div_ceil)]
+    fn num_slices(&self) -> usize {
+        (self.specs.len() + self.width - 1) / self.width
+    }
+
+    // The counters that are counted while running.
+    fn running_range(&self) -> std::ops::Range<usize> {
+        if self.kernel_timed {
+            0..self.specs.len()
+        } else {
+            self.slice_range()
+        }
+    }
+
+    fn allocate(&self, i: usize) -> Result<Counter, Error> {
+        Counter::new(
+            Arc::clone(&self.backend),
+            self.specs[i].clone(),
+            self.pids.clone(),
+        )
+    }
+
+    /// Allocate the counters of the current slice.
+    fn allocate_slice(&mut self) -> Result<(), Error> {
+        for i in self.slice_range() {
+            if self.counters[i].is_none() {
+                self.counters[i] = Some(self.allocate(i)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Release the counters of the current slice, keeping their counts.
+    ///
+    /// A counter that cannot be read loses the events of the slice, so the
+    /// `elapsed` time of the slice is not counted as running either.
+    fn release_slice(&mut self, elapsed: Duration) {
+        for i in self.slice_range() {
+            if let Some(c) = self.counters[i].take() {
+                match c.read() {
+                    Ok(v) => self.counted[i] += v,
+                    Err(_) => self.time_running[i] -= elapsed,
+                }
+            }
+        }
+    }
+
+    fn start(&mut self) -> Result<(), Error> {
+        for c in self.counters[self.running_range()].iter().flatten() {
+            self.backend.start(c.id()).map_err(start_error)?;
+        }
+        self.slice_started = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Stop counting, returning the time the current slice was running.
+    fn stop(&mut self) -> Duration {
+        let range = self.running_range();
+        for c in self.counters[range.clone()].iter().flatten() {
+            let _ = self.backend.stop(c.id());
+        }
+
+        // Account the time the current slice was running.
+        let elapsed = match self.slice_started.take() {
+            Some(started) => started.elapsed(),
+            None => return Duration::default(),
+        };
+        self.time_enabled += elapsed;
+        for i in range {
+            if self.counters[i].is_some() {
+                self.time_running[i] += elapsed;
+            }
+        }
+        elapsed
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        if self.kernel_timed || self.slice_started.is_none() {
+            return Ok(());
+        }
+
+        let elapsed = self.stop();
+        self.release_slice(elapsed);
+        self.slice = (self.slice + 1) % self.num_slices();
+
+        // Keep rotating should a slice fail to allocate, leaving its events
+        // uncounted for the slice.
+        let allocated = self.allocate_slice();
+        self.start()?;
+        allocated
+    }
+
+    fn read(&mut self) -> Result<Vec<TimedValue>, Error> {
+        if let Some(kind) = self.rotate_error.take() {
+            return Err(new_error(kind));
+        }
+
+        if self.kernel_timed {
+            return self
+                .counters
+                .iter()
+                .flatten()
+                .map(|c| self.backend.read_timed(c.id()).map_err(read_error))
+                .collect();
+        }
+
+        // Include the time the current slice has been running for so far.
+        let partial = self.slice_started.map(|s| s.elapsed()).unwrap_or_default();
+        let range = self.slice_range();
+
+        self.counters
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let mut running = self.time_running[i];
+                if range.contains(&i) && c.is_some() {
+                    running += partial;
+                }
+
+                let live = match c {
+                    Some(c) => c.read()?,
+                    None => 0,
+                };
+
+                Ok(TimedValue {
+                    value: self.counted[i] + live,
+                    time_enabled: (self.time_enabled + partial).as_nanos() as u64,
+                    time_running: running.as_nanos() as u64,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A set of counters multiplexed over a limited number of hardware PMCs.
+///
+/// CPUs have a small number of hardware counters, limiting the number of
+/// events that can be counted at once. A `Multiplexer` counts more events than
+/// there are PMCs by rotating the events over the available counters, and
+/// scaling each count by the fraction of time it was actually counting.
+///
+/// Backends that multiplex PMCs in the kernel (such as [`Perf`]) report the
+/// time each event was enabled & running themselves. For all other backends,
+/// the events are split into slices of `width` counters, and a background
+/// thread rotates the running slice every [`DEFAULT_MULTIPLEX_INTERVAL`] (see
+/// [`set_interval`]). Only the counters of the running slice are allocated -
+/// the PMCs of a slice are released when it is rotated out, and those of the
+/// next slice allocated - so `width` PMCs are needed at most.
+///
+/// Multiplexers are initialised using [`CounterBuilder::multiplex`].
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let mut mux = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .multiplex(
+///         vec!["inst_retired.any", "cpu_clk_unhalted.thread", "br_misp_retired.all_branches"],
+///         2,
+///     )?;
+///
+/// let handle = mux.start()?;
+///
+/// // Do some work...
+///
+/// handle.stop();
+///
+/// for v in mux.read()?.iter() {
+///     println!("{}: ~{} ({:.0}% confidence)", v.label(), v.scaled(), v.confidence() * 100.0);
+/// }
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Perf`]: backend/struct.Perf.html
+/// [`DEFAULT_MULTIPLEX_INTERVAL`]: constant.DEFAULT_MULTIPLEX_INTERVAL.html
+/// [`set_interval`]: #method.set_interval
+/// [`CounterBuilder::multiplex`]: struct.CounterBuilder.html#method.multiplex
+#[derive(Debug)]
+pub struct Multiplexer {
+    state: Arc<Mutex<State>>,
+    labels: Vec<String>,
+    interval: Duration,
+}
+
+impl Multiplexer {
+    pub(crate) fn new(
+        backend: Arc<dyn Backend>,
+        specs: Vec<AllocSpec>,
+        pids: Option<Vec<i32>>,
+        labels: Vec<String>,
+        width: usize,
+    ) -> Result<Self, Error> {
+        let n = specs.len();
+        let mut state = State {
+            backend,
+            specs,
+            pids,
+            counters: (0..n).map(|_| None).collect(),
+            kernel_timed: false,
+            width: width.max(1),
+            slice: 0,
+            slice_started: None,
+            time_enabled: Duration::default(),
+            time_running: vec![Duration::default(); n],
+            counted: vec![0; n],
+            rotate_error: None,
+        };
+
+        // Probe for kernel-provided enabled & running times.
+        let first = state.allocate(0)?;
+        state.kernel_timed = state.backend.read_timed(first.id()).is_ok();
+
+        if state.kernel_timed {
+            state.counters[0] = Some(first);
+            for i in 1..n {
+                state.counters[i] = Some(state.allocate(i)?);
+            }
+        } else {
+            // Check every slice can be allocated, one at a time, finishing
+            // with the first slice allocated ready to start.
+            drop(first);
+            for slice in 1..state.num_slices() {
+                state.slice = slice;
+                state
+                    .slice_range()
+                    .map(|i| state.allocate(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+            }
+            state.slice = 0;
+            state.allocate_slice()?;
+        }
+
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+            labels,
+            interval: DEFAULT_MULTIPLEX_INTERVAL,
+        })
+    }
+
+    /// Set the interval between rotations of userspace-multiplexed counters.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Returns the labels of the multiplexed events, in allocation order.
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Returns true if the backend multiplexes the counters itself, rather
+    /// than the counters being rotated in userspace.
+    pub fn is_kernel_multiplexed(&self) -> bool {
+        self.state().kernel_timed
+    }
+
+    /// Start counting.
+    ///
+    /// The counters stop when the returned [`RunningMultiplexer`] handle is
+    /// dropped.
+    ///
+    /// [`RunningMultiplexer`]: struct.RunningMultiplexer.html
+    #[must_use = "multiplexer only runs until handle is dropped"]
+    pub fn start(&mut self) -> Result<RunningMultiplexer<'_>, Error> {
+        let rotate = {
+            let mut state = self.state();
+            state.start()?;
+            !state.kernel_timed && state.num_slices() > 1
+        };
+
+        // Spawn a thread to rotate the counters, stopped by dropping the
+        // sender.
+        let rotator = if rotate {
+            let (tx, rx) = mpsc::channel::<()>();
+            let state = Arc::clone(&self.state);
+            let interval = self.interval;
+            let handle = thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                    let mut state = state.lock().unwrap();
+                    if let Err(e) = state.rotate() {
+                        state.rotate_error.get_or_insert(e.kind().clone());
+                    }
+                }
+            });
+            Some((tx, handle))
+        } else {
+            None
+        };
+
+        Ok(RunningMultiplexer { mux: self, rotator })
+    }
+
+    /// Read the current estimated value of every event.
+    ///
+    /// Should rotating the counters in the background have failed since the
+    /// last read, the kind of the first such error is returned instead - the
+    /// events of a slice that failed to rotate are left uncounted for that
+    /// slice.
+    pub fn read(&self) -> Result<MultiplexReading, Error> {
+        let values = self.state().read()?;
+
+        Ok(MultiplexReading {
+            values: self
+                .labels
+                .iter()
+                .cloned()
+                .zip(values)
+                .map(|(label, v)| ScaledValue { label, raw: v })
+                .collect(),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// A handle to a running [`Multiplexer`].
+///
+/// Dropping this handle causes all counters to stop recording events.
+///
+/// [`Multiplexer`]: struct.Multiplexer.html
+#[derive(Debug)]
+pub struct RunningMultiplexer<'a> {
+    mux: &'a mut Multiplexer,
+    rotator: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl<'a> RunningMultiplexer<'a> {
+    /// Read the current estimated value of every event.
+    pub fn read(&self) -> Result<MultiplexReading, Error> {
+        self.mux.read()
+    }
+
+    /// Immediately rotate to the next slice of userspace-multiplexed counters.
+    ///
+    /// This is a no-op for kernel-multiplexed counters.
+    pub fn rotate(&self) -> Result<(), Error> {
+        self.mux.state().rotate()
+    }
+
+    /// Stop the counters from recording new events.
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl<'a> Drop for RunningMultiplexer<'a> {
+    fn drop(&mut self) {
+        if let Some((tx, handle)) = self.rotator.take() {
+            drop(tx);
+            let _ = handle.join();
+        }
+        self.mux.state().stop();
+    }
+}
+
+/// An estimated event count, scaled by the fraction of time the event was
+/// counted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScaledValue {
+    label: String,
+    raw: TimedValue,
+}
+
+impl ScaledValue {
+    /// Returns the event label.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the raw counter value, counted while the event was running.
+    pub fn raw(&self) -> u64 {
+        self.raw.value
+    }
+
+    /// Returns the total time the event was enabled.
+    pub fn time_enabled(&self) -> Duration {
+        Duration::from_nanos(self.raw.time_enabled)
+    }
+
+    /// Returns the time the event was actually counting.
+    pub fn time_running(&self) -> Duration {
+        Duration::from_nanos(self.raw.time_running)
+    }
+
+    /// Returns the estimated event count over the whole time the event was
+    /// enabled.
+    ///
+    /// Events that never ran are estimated as 0.
+    pub fn scaled(&self) -> u64 {
+        if self.raw.time_running == 0 {
+            return 0;
+        }
+        (self.raw.value as f64 * self.raw.time_enabled as f64 / self.raw.time_running as f64) as u64
+    }
+
+    /// Returns the fraction of the enabled time the event was counting, from
+    /// 0.0 (never counted) to 1.0 (counted the whole time, so the value is
+    /// exact).
+    ///
+    /// The lower the confidence, the more the [`scaled`] estimate is
+    /// extrapolated from the raw value.
+    ///
+    /// [`scaled`]: #method.scaled
+    pub fn confidence(&self) -> f64 {
+        if self.raw.time_enabled == 0 {
+            return 0.0;
+        }
+        (self.raw.time_running as f64 / self.raw.time_enabled as f64).min(1.0)
+    }
+}
+
+/// The estimated values of all events in a [`Multiplexer`].
+///
+/// [`Multiplexer`]: struct.Multiplexer.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiplexReading {
+    values: Vec<ScaledValue>,
+}
+
+impl MultiplexReading {
+    /// Returns the value of the event labelled `label`.
+    pub fn get(&self, label: &str) -> Option<&ScaledValue> {
+        self.values.iter().find(|v| v.label == label)
+    }
+
+    /// Returns an iterator of the event values, in allocation order.
+    pub fn iter(&self) -> impl Iterator<Item = &ScaledValue> {
+        self.values.iter()
+    }
+
+    /// Returns the scaled estimates as a [`GroupReading`], allowing
+    /// [`Metric`]s to be computed over multiplexed events.
+    ///
+    /// Events that never ran are left out of the reading, so metrics using
+    /// them evaluate to `None` rather than being computed from a count of 0.
+    ///
+    /// [`GroupReading`]: struct.GroupReading.html
+    /// [`Metric`]: struct.Metric.html
+    pub fn scaled(&self) -> GroupReading {
+        GroupReading::new(
+            self.values
+                .iter()
+                .filter(|v| v.raw.time_running > 0)
+                .map(|v| (v.label.clone(), v.scaled()))
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for MultiplexReading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, v) in self.values.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}: {} ({:.1}%)",
+                v.label,
+                v.scaled(),
+                v.confidence() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
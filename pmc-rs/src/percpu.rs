@@ -0,0 +1,176 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::backend::{Backend, PmcId};
+use crate::counter::{read_error, start_error, Counter};
+use crate::error::{new_os_error, Error, ErrorKind};
+
+/// Returns the CPU numbers of all online CPUs.
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// println!("online CPUs: {:?}", online_cpus()?);
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+pub fn online_cpus() -> Result<Vec<i32>, Error> {
+    _online_cpus().map_err(|err| new_os_error(ErrorKind::Unknown, err))
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn _online_cpus() -> std::io::Result<Vec<i32>> {
+    crate::backend::online_cpus()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+fn _online_cpus() -> std::io::Result<Vec<i32>> {
+    match unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        n => Ok((0..n as i32).collect()),
+    }
+}
+
+/// The same event counted separately on each of a set of CPUs.
+///
+/// A system-scoped [`Counter`] counts events on a single CPU - a
+/// `PerCpuCounter` allocates one counter per CPU so system-wide counts cover
+/// the whole machine, while still allowing a per-CPU breakdown.
+///
+/// CPUs that fail to allocate the event (for example, because the CPU went
+/// offline or all its PMCs are in use) are skipped and reported by
+/// [`failed`]. Allocation only fails outright if no CPU could be allocated.
+///
+/// Per-CPU counters are initialised using [`CounterBuilder::per_cpu`].
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let mut instr = CounterBuilder::default().per_cpu("inst_retired.any")?;
+///
+/// for (cpu, err) in instr.failed() {
+///     eprintln!("failed to allocate on CPU {}: {}", cpu, err);
+/// }
+///
+/// let handle = instr.start()?;
+///
+/// // Do some work...
+///
+/// handle.stop();
+///
+/// println!("total: {}", instr.read_total()?);
+/// for (cpu, value) in instr.read_per_cpu()? {
+///     println!("CPU {}: {}", cpu, value);
+/// }
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Counter`]: struct.Counter.html
+/// [`failed`]: #method.failed
+/// [`CounterBuilder::per_cpu`]: struct.CounterBuilder.html#method.per_cpu
+#[derive(Debug)]
+pub struct PerCpuCounter {
+    counters: Vec<(i32, Counter)>,
+    failed: Vec<(i32, Error)>,
+}
+
+impl PerCpuCounter {
+    pub(crate) fn new(counters: Vec<(i32, Counter)>, failed: Vec<(i32, Error)>) -> Self {
+        Self { counters, failed }
+    }
+
+    /// Returns the CPUs the event was allocated on.
+    pub fn cpus(&self) -> Vec<i32> {
+        self.counters.iter().map(|(cpu, _)| *cpu).collect()
+    }
+
+    /// Returns the CPUs the event failed to allocate on, and the reason.
+    pub fn failed(&self) -> &[(i32, Error)] {
+        &self.failed
+    }
+
+    /// Start the counter on all CPUs.
+    ///
+    /// The counters stop when the returned [`RunningPerCpu`] handle is
+    /// dropped.
+    ///
+    /// [`RunningPerCpu`]: struct.RunningPerCpu.html
+    #[must_use = "counter only runs until handle is dropped"]
+    pub fn start(&mut self) -> Result<RunningPerCpu<'_>, Error> {
+        self.backend()
+            .start_group(&self.ids())
+            .map_err(start_error)?;
+
+        Ok(RunningPerCpu { counter: self })
+    }
+
+    /// Read the sum of the counter values across all CPUs.
+    pub fn read_total(&self) -> Result<u64, Error> {
+        Ok(self
+            .read_per_cpu()?
+            .into_iter()
+            .fold(0, |total, (_, v)| total.wrapping_add(v)))
+    }
+
+    /// Read the counter value of each CPU, ordered as [`cpus`].
+    ///
+    /// [`cpus`]: #method.cpus
+    pub fn read_per_cpu(&self) -> Result<Vec<(i32, u64)>, Error> {
+        let values = self.backend().read_group(&self.ids()).map_err(read_error)?;
+
+        Ok(self.cpus().into_iter().zip(values).collect())
+    }
+
+    fn ids(&self) -> Vec<PmcId> {
+        self.counters.iter().map(|(_, c)| c.id()).collect()
+    }
+
+    fn backend(&self) -> &Arc<dyn Backend> {
+        // At least one CPU is always allocated.
+        self.counters[0].1.backend()
+    }
+}
+
+/// A handle to a running [`PerCpuCounter`].
+///
+/// Dropping this handle causes the counter to stop recording events on all
+/// CPUs.
+///
+/// [`PerCpuCounter`]: struct.PerCpuCounter.html
+#[derive(Debug)]
+pub struct RunningPerCpu<'a> {
+    counter: &'a mut PerCpuCounter,
+}
+
+impl<'a> RunningPerCpu<'a> {
+    /// Read the current sum of the counter values across all CPUs.
+    pub fn read_total(&self) -> Result<u64, Error> {
+        self.counter.read_total()
+    }
+
+    /// Read the current counter value of each CPU.
+    pub fn read_per_cpu(&self) -> Result<Vec<(i32, u64)>, Error> {
+        self.counter.read_per_cpu()
+    }
+
+    /// Stop the counters from recording new events.
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl<'a> Drop for RunningPerCpu<'a> {
+    fn drop(&mut self) {
+        let _ = self.counter.backend().stop_group(&self.counter.ids());
+    }
+}
+
+impl fmt::Display for PerCpuCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.read_total() {
+            Ok(v) => write!(f, "{}", v),
+            Err(e) => write!(f, "error: {}", e),
+        }
+    }
+}
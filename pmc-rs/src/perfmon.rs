@@ -0,0 +1,580 @@
+use std::fs;
+use std::path::Path;
+
+use crate::backend::EventInfo;
+use crate::cpuid::CpuId;
+use crate::error::{new_error, new_os_error, new_parse_error, Error, ErrorKind, ParseError};
+use crate::event::RawEvent;
+use crate::json::{self, Json, Value};
+use crate::metrics::Metric;
+use crate::spec::EventSpec;
+
+/// A core PMU event described by a vendor perfmon event table.
+///
+/// The fields follow the Intel and AMD JSON event files, and together encode
+/// the event for the general-purpose counters (see [`config`]).
+///
+/// [`config`]: #method.config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PerfmonEvent {
+    name: String,
+    code: u16,
+    umask: u8,
+    cmask: u8,
+    inv: bool,
+    edge: bool,
+    any_thread: bool,
+    description: Option<String>,
+}
+
+impl PerfmonEvent {
+    /// The event name, such as `INST_RETIRED.ANY` or `ex_ret_instr`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The event select code.
+    ///
+    /// AMD event codes can be up to 12 bits wide.
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// The unit mask.
+    pub fn umask(&self) -> u8 {
+        self.umask
+    }
+
+    /// The counter mask, or 0 to count every event.
+    pub fn cmask(&self) -> u8 {
+        self.cmask
+    }
+
+    /// Returns true if the counter mask comparison is inverted.
+    pub fn inv(&self) -> bool {
+        self.inv
+    }
+
+    /// Returns true if the event counts rising edges.
+    pub fn edge(&self) -> bool {
+        self.edge
+    }
+
+    /// Returns true if the event counts every hardware thread on the core.
+    pub fn any_thread(&self) -> bool {
+        self.any_thread
+    }
+
+    /// The description given by the event table, if any.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Returns the raw encoding of the event.
+    ///
+    /// ```
+    /// use pmc::*;
+    ///
+    /// let table = EventTable::parse(r#"[
+    ///     { "EventName": "UOPS_ISSUED.STALL_CYCLES", "EventCode": "0x0E", "UMask": "0x01",
+    ///       "CounterMask": "1", "Invert": "1" }
+    /// ]"#)?;
+    ///
+    /// let event = table.get("uops_issued.stall_cycles").unwrap();
+    /// assert_eq!(event.raw(), RawEvent::new(0x0e, 0x01).cmask(1).inv());
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn raw(&self) -> RawEvent {
+        let mut raw = RawEvent::new(self.code, self.umask).cmask(self.cmask);
+        if self.inv {
+            raw = raw.inv();
+        }
+        if self.edge {
+            raw = raw.edge();
+        }
+        if self.any_thread {
+            raw = raw.any_thread();
+        }
+        raw
+    }
+
+    /// Returns the raw encoding of the event, laid out as the x86 event
+    /// select register - see [`RawEvent::config`].
+    ///
+    /// [`RawEvent::config`]: struct.RawEvent.html#method.config
+    pub fn config(&self) -> u64 {
+        self.raw().config()
+    }
+
+    /// Returns a spec for counting the raw encoding of the event with
+    /// [`CounterBuilder`], regardless of the event names known to the kernel
+    /// or `libpmc`.
+    ///
+    /// [`CounterBuilder`]: struct.CounterBuilder.html
+    pub fn spec(&self) -> EventSpec {
+        self.raw().into()
+    }
+}
+
+impl From<&PerfmonEvent> for EventInfo {
+    fn from(v: &PerfmonEvent) -> Self {
+        EventInfo {
+            name: v.name.clone(),
+            description: v.description.clone(),
+            pmu: "cpu".to_string(),
+            qualifiers: vec!["usr".to_string(), "os".to_string()],
+        }
+    }
+}
+
+/// The events and metrics of a vendor perfmon JSON event table.
+///
+/// Intel and AMD publish per-microarchitecture JSON files describing the
+/// events of their CPUs (also distributed with the Linux kernel source in
+/// `tools/perf/pmu-events/arch/x86`). An `EventTable` loads these files,
+/// allowing any listed event to be counted by name - not only the aliases
+/// known to the running kernel or `libpmc`.
+///
+/// Both the array-of-events layout, and the newer layout wrapping the array in
+/// an `"Events"` object are accepted. Uncore events (those with a `"Unit"`)
+/// are skipped, as they are not counted by the core PMU. Events programmed
+/// through an additional MSR (those with a non-zero `"MSRIndex"`, such as
+/// `OFFCORE_RESPONSE`) are also skipped, as the MSR value is not part of the
+/// raw encoding. Entries with a `"MetricName"` and `"MetricExpr"` are loaded
+/// as [`Metric`]s - formulas using syntax the metric parser does not support
+/// are skipped.
+///
+/// ```
+/// use pmc::*;
+///
+/// let table = EventTable::parse(r#"[
+///     { "EventName": "INST_RETIRED.ANY_P", "EventCode": "0xC0", "UMask": "0x00",
+///       "BriefDescription": "Number of instructions retired." },
+///     { "MetricName": "IPC", "MetricExpr": "INST_RETIRED.ANY_P / CPU_CLK_UNHALTED.THREAD_P" }
+/// ]"#)?;
+///
+/// let event = table.get("inst_retired.any_p").unwrap();
+/// assert_eq!(event.code(), 0xc0);
+/// assert_eq!(event.description(), Some("Number of instructions retired."));
+/// assert_eq!(table.metrics()[0].name(), "IPC");
+///
+/// assert_eq!(table.spec("INST_RETIRED.ANY_P")?.to_string(), "rc0");
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Metric`]: struct.Metric.html
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventTable {
+    events: Vec<PerfmonEvent>,
+    metrics: Vec<Metric>,
+}
+
+impl EventTable {
+    /// Parse a JSON event table.
+    ///
+    /// Returns an [`ErrorKind::InvalidEventTable`] error with a
+    /// [`ParseError`] [cause] if the JSON is malformed, or an event field is
+    /// invalid.
+    ///
+    /// [`ErrorKind::InvalidEventTable`]: enum.ErrorKind.html#variant.InvalidEventTable
+    /// [`ParseError`]: struct.ParseError.html
+    /// [cause]: struct.Error.html#method.cause
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        json::parse(input)
+            .and_then(|doc| parse_table(input, &doc))
+            .map_err(|e| new_parse_error(ErrorKind::InvalidEventTable, e))
+    }
+
+    /// Load the event table at `path`.
+    ///
+    /// If `path` is a directory, every `.json` file in it is loaded and
+    /// merged, as laid out in the Linux kernel source tree.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let read_err = |e| new_os_error(ErrorKind::Unknown, e);
+
+        if !path.is_dir() {
+            return Self::parse(&fs::read_to_string(path).map_err(read_err)?);
+        }
+
+        let mut files = fs::read_dir(path)
+            .and_then(|dir| {
+                dir.map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(read_err)?;
+        files.retain(|f| f.extension().map(|e| e == "json") == Some(true));
+        files.sort();
+
+        let mut table = Self::default();
+        for file in files {
+            table.extend(Self::parse(&fs::read_to_string(file).map_err(read_err)?)?);
+        }
+        Ok(table)
+    }
+
+    /// Load the core event table for the running CPU from the collection of
+    /// tables in `dir`.
+    ///
+    /// `dir` must contain a `mapfile.csv` mapping CPUs to table files, as
+    /// published by Intel and in the Linux kernel source tree.
+    ///
+    /// Returns an [`ErrorKind::Unsupported`] error if the running CPU cannot
+    /// be identified, or has no table.
+    ///
+    /// ```no_run
+    /// use pmc::*;
+    ///
+    /// let table = EventTable::for_cpu("/usr/src/linux/tools/perf/pmu-events/arch/x86")?;
+    ///
+    /// let mut counter = CounterBuilder::default().allocate(table.spec("BR_MISP_RETIRED.ALL_BRANCHES")?)?;
+    /// #
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [`ErrorKind::Unsupported`]: enum.ErrorKind.html#variant.Unsupported
+    pub fn for_cpu(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let cpu = CpuId::current().ok_or_else(|| new_error(ErrorKind::Unsupported))?;
+        Self::for_cpu_id(dir, &cpu)
+    }
+
+    /// Load the core event table for `cpu` from the collection of tables in
+    /// `dir` - see [`for_cpu`].
+    ///
+    /// [`for_cpu`]: #method.for_cpu
+    pub fn for_cpu_id(dir: impl AsRef<Path>, cpu: &CpuId) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        let mapfile = fs::read_to_string(dir.join("mapfile.csv"))
+            .map_err(|e| new_os_error(ErrorKind::Unknown, e))?;
+
+        // Rows are of the form "GenuineIntel-6-55-[01234],V1.28,/SKX/events/skylakex_core.json,core".
+        for row in mapfile.lines() {
+            let cols: Vec<&str> = row.split(',').map(str::trim).collect();
+            if cols.len() < 4 || cols[3] != "core" || !cpu_matches(cols[0], cpu) {
+                continue;
+            }
+            return Self::load(dir.join(cols[2].trim_start_matches('/')));
+        }
+
+        Err(new_error(ErrorKind::Unsupported))
+    }
+
+    /// Add the events and metrics of `other` to this table.
+    pub fn extend(&mut self, other: EventTable) {
+        self.events.extend(other.events);
+        self.metrics.extend(other.metrics);
+    }
+
+    /// Returns the events in the table.
+    pub fn events(&self) -> &[PerfmonEvent] {
+        &self.events
+    }
+
+    /// Returns the metrics in the table.
+    ///
+    /// Metric formulas refer to events by their table names.
+    pub fn metrics(&self) -> &[Metric] {
+        &self.metrics
+    }
+
+    /// Returns the event called `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&PerfmonEvent> {
+        self.events
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns a spec for counting the event called `name` - see
+    /// [`PerfmonEvent::spec`].
+    ///
+    /// Returns an [`ErrorKind::InvalidEventSpec`] error if the table has no
+    /// such event.
+    ///
+    /// [`PerfmonEvent::spec`]: struct.PerfmonEvent.html#method.spec
+    /// [`ErrorKind::InvalidEventSpec`]: enum.ErrorKind.html#variant.InvalidEventSpec
+    pub fn spec(&self, name: &str) -> Result<EventSpec, Error> {
+        self.get(name)
+            .map(PerfmonEvent::spec)
+            .ok_or_else(|| new_error(ErrorKind::InvalidEventSpec))
+    }
+}
+
+fn parse_table(input: &str, doc: &Json) -> Result<EventTable, ParseError> {
+    // Newer Intel files wrap the events in an object with a "Header".
+    let entries = doc.get("Events").unwrap_or(doc).as_array().ok_or_else(|| {
+        ParseError::new("expected an array of events", doc.pos, token(input, doc))
+    })?;
+
+    let mut table = EventTable::default();
+    for entry in entries {
+        if let Some(name) = entry.get("MetricName").and_then(Json::as_str) {
+            let metric = entry
+                .get("MetricExpr")
+                .and_then(Json::as_str)
+                .and_then(|expr| Metric::new(name, expr).ok());
+            table.metrics.extend(metric);
+            continue;
+        }
+
+        let name = match entry.get("EventName").and_then(Json::as_str) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        // Uncore events are counted by other PMUs, and events without an
+        // encoding (such as ARM architectural aliases) cannot be counted raw.
+        // Fixed counter events may only list a unit mask.
+        let code = field(input, entry, "EventCode", 0xfff)?;
+        if entry.get("Unit").is_some() || (code.is_none() && entry.get("UMask").is_none()) {
+            continue;
+        }
+
+        // Events configured through an extra MSR (such as the offcore
+        // response events) are not fully described by their raw encoding.
+        if field(input, entry, "MSRIndex", u64::MAX)?.unwrap_or(0) != 0 {
+            continue;
+        }
+
+        let flag = |key| field(input, entry, key, 1).map(|v| v == Some(1));
+        table.events.push(PerfmonEvent {
+            name: name.to_string(),
+            code: code.unwrap_or(0) as u16,
+            umask: field(input, entry, "UMask", 0xff)?.unwrap_or(0) as u8,
+            cmask: field(input, entry, "CounterMask", 0xff)?.unwrap_or(0) as u8,
+            inv: flag("Invert")?,
+            edge: flag("EdgeDetect")?,
+            any_thread: flag("AnyThread")?,
+            description: ["BriefDescription", "PublicDescription"]
+                .iter()
+                .find_map(|k| entry.get(k).and_then(Json::as_str))
+                .map(str::to_string),
+        });
+    }
+
+    Ok(table)
+}
+
+/// Returns the first character of `value`, for error reporting.
+fn token(input: &str, value: &Json) -> String {
+    input[value.pos..]
+        .chars()
+        .next()
+        .map(|c| c.to_string())
+        .unwrap_or_default()
+}
+
+/// Read the numeric field `key` of `entry`, no greater than `max`.
+///
+/// Numbers are usually strings in decimal or `0x` prefixed hex. Fields
+/// listing several values (such as `"0xB7, 0xBB"`) use the first.
+fn field(input: &str, entry: &Json, key: &str, max: u64) -> Result<Option<u64>, ParseError> {
+    let value = match entry.get(key) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let n = match &value.value {
+        Value::Null => return Ok(None),
+        Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 => Some(*n as u64),
+        Value::String(s) => {
+            let s = s.split(',').next().unwrap_or_default().trim();
+            match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => s.parse().ok(),
+            }
+        }
+        _ => None,
+    };
+
+    match n {
+        Some(n) if n <= max => Ok(Some(n)),
+        _ => {
+            let text = match value.as_str() {
+                Some(s) => s.to_string(),
+                None => token(input, value),
+            };
+            Err(ParseError::new(format!("invalid {}", key), value.pos, text))
+        }
+    }
+}
+
+/// Returns true if the mapfile `pattern` matches `cpu`.
+///
+/// Patterns are regular expressions matched against the whole of the
+/// `vendor-family-model-stepping` string when they include a stepping, and
+/// against `vendor-family-model` otherwise.
+///
+/// Only the syntax used by the Intel and Linux mapfiles is supported -
+/// literals, bracketed character classes of single characters, ranges and
+/// `[:xdigit:]`, parenthesised groups with `|` alternation, and the `+`
+/// quantifier (such as `AuthenticAMD-23-([12][0-9A-F]|[[:xdigit:]]+)`).
+/// Patterns using any other syntax never match.
+fn cpu_matches(pattern: &str, cpu: &CpuId) -> bool {
+    let mut dashes = 0;
+    let mut in_class = false;
+    for c in pattern.chars() {
+        match c {
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '-' if !in_class => dashes += 1,
+            _ => {}
+        }
+    }
+
+    let id = if dashes >= 3 {
+        cpu.to_string()
+    } else {
+        format!("{}-{}-{:X}", cpu.vendor(), cpu.family(), cpu.model())
+    };
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    let alts = match parse_alternatives(&pattern, &mut i) {
+        Some(v) if i == pattern.len() => v,
+        _ => return false,
+    };
+
+    let input: Vec<char> = id.chars().collect();
+    match_alternatives(&alts, &input, 0).contains(&input.len())
+}
+
+// The subset of regular expression syntax used by mapfiles: literals,
+// character classes, and groups with alternation, each optionally repeated
+// with '+'.
+enum Atom {
+    Char(char),
+    Class(Vec<(char, char)>),
+    Group(Vec<Vec<Piece>>),
+}
+
+struct Piece {
+    atom: Atom,
+    repeat: bool,
+}
+
+fn parse_alternatives(pattern: &[char], i: &mut usize) -> Option<Vec<Vec<Piece>>> {
+    let mut alts = vec![vec![]];
+
+    while let Some(&c) = pattern.get(*i) {
+        *i += 1;
+        let atom = match c {
+            ')' => {
+                *i -= 1;
+                break;
+            }
+            '|' => {
+                alts.push(vec![]);
+                continue;
+            }
+            '(' => {
+                let group = parse_alternatives(pattern, i)?;
+                if pattern.get(*i) != Some(&')') {
+                    return None;
+                }
+                *i += 1;
+                Atom::Group(group)
+            }
+            '[' => parse_class(pattern, i)?,
+            // Other regular expression syntax is not used by mapfiles.
+            '.' | '?' | '*' | '+' | '\\' | '^' | '$' | '{' | '}' | ']' => return None,
+            c => Atom::Char(c),
+        };
+
+        let repeat = pattern.get(*i) == Some(&'+');
+        if repeat {
+            *i += 1;
+        }
+
+        alts.last_mut()?.push(Piece { atom, repeat });
+    }
+
+    Some(alts)
+}
+
+fn parse_class(pattern: &[char], i: &mut usize) -> Option<Atom> {
+    let mut ranges = vec![];
+    loop {
+        let c = *pattern.get(*i)?;
+        *i += 1;
+        match c {
+            ']' if !ranges.is_empty() => return Some(Atom::Class(ranges)),
+            '[' if pattern.get(*i) == Some(&':') => {
+                let rest: String = pattern[*i..].iter().collect();
+                let (name, _) = rest[1..].split_once(":]")?;
+                *i += name.len() + 3;
+                match name {
+                    "xdigit" => ranges.extend(&[('0', '9'), ('a', 'f'), ('A', 'F')]),
+                    _ => return None,
+                }
+            }
+            ']' | '[' | '^' | '\\' => return None,
+            c if pattern.get(*i) == Some(&'-') && pattern.get(*i + 1) != Some(&']') => {
+                ranges.push((c, *pattern.get(*i + 1)?));
+                *i += 2;
+            }
+            c => ranges.push((c, c)),
+        }
+    }
+}
+
+/// Returns every position a match of `alts` starting at `start` can end at.
+fn match_alternatives(alts: &[Vec<Piece>], input: &[char], start: usize) -> Vec<usize> {
+    let mut out: Vec<usize> = alts
+        .iter()
+        .flat_map(|seq| match_sequence(seq, input, start))
+        .collect();
+    out.sort_unstable();
+    out.dedup();
+    out
+}
+
+fn match_sequence(seq: &[Piece], input: &[char], start: usize) -> Vec<usize> {
+    let mut positions = vec![start];
+
+    for piece in seq {
+        let mut out = vec![];
+
+        // Every repetition consumes at least one character, or makes no
+        // progress - either way the input length bounds the repetitions.
+        let mut current = positions;
+        let mut n = 0;
+        while !current.is_empty() && n <= input.len() {
+            n += 1;
+            let mut next: Vec<usize> = current
+                .iter()
+                .flat_map(|&p| match_atom(&piece.atom, input, p))
+                .collect();
+            next.sort_unstable();
+            next.dedup();
+            out.extend(&next);
+            if !piece.repeat {
+                break;
+            }
+            current = next;
+        }
+
+        out.sort_unstable();
+        out.dedup();
+        positions = out;
+    }
+
+    positions
+}
+
+fn match_atom(atom: &Atom, input: &[char], pos: usize) -> Vec<usize> {
+    let c = input.get(pos);
+    let matched = match (atom, c) {
+        (Atom::Group(alts), _) => return match_alternatives(alts, input, pos),
+        (_, None) => false,
+        (Atom::Char(want), Some(c)) => want == c,
+        (Atom::Class(ranges), Some(c)) => ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(c)),
+    };
+
+    if matched {
+        vec![pos + 1]
+    } else {
+        vec![]
+    }
+}
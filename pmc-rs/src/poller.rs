@@ -0,0 +1,392 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::counter::{read_error, start_error, Counter};
+use crate::error::Error;
+use crate::group::{CounterGroup, GroupReading};
+
+/// The default interval between reads of a [`Poller`].
+///
+/// [`Poller`]: struct.Poller.html
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The default number of samples retained by a [`Poller`].
+///
+/// [`Poller`]: struct.Poller.html
+pub const DEFAULT_POLL_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+enum Source {
+    Counter(Counter),
+    Group(CounterGroup),
+}
+
+impl Source {
+    fn labels(&self) -> Vec<String> {
+        match self {
+            Source::Counter(_) => vec!["value".to_string()],
+            Source::Group(g) => g.labels().to_vec(),
+        }
+    }
+
+    fn start(&self) -> Result<(), Error> {
+        match self {
+            Source::Counter(c) => c.backend().start(c.id()),
+            Source::Group(g) => g.backend().start_group(g.ids()),
+        }
+        .map_err(start_error)
+    }
+
+    fn stop(&self) {
+        let _ = match self {
+            Source::Counter(c) => c.backend().stop(c.id()),
+            Source::Group(g) => g.backend().stop_group(g.ids()),
+        };
+    }
+
+    fn read(&self) -> Result<Vec<u64>, Error> {
+        match self {
+            Source::Counter(c) => Ok(vec![c.read()?]),
+            Source::Group(g) => g.backend().read_group(g.ids()).map_err(read_error),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Series {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+    dropped: u64,
+
+    // The values & time of the last read, the next sample is relative to.
+    last: Vec<u64>,
+    last_at: Instant,
+    started: Instant,
+}
+
+impl Series {
+    fn push(&mut self, labels: &[String], values: Vec<u64>, at: Instant) {
+        let deltas = labels
+            .iter()
+            .cloned()
+            .zip(
+                values
+                    .iter()
+                    .zip(&self.last)
+                    .map(|(v, l)| v.wrapping_sub(*l)),
+            )
+            .collect();
+
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+            self.dropped += 1;
+        }
+        self.samples.push_back(Sample {
+            at: at - self.started,
+            interval: at - self.last_at,
+            deltas: GroupReading::new(deltas),
+        });
+
+        self.last = values;
+        self.last_at = at;
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    source: Mutex<Source>,
+    series: Mutex<Series>,
+    labels: Vec<String>,
+}
+
+impl State {
+    fn source(&self) -> MutexGuard<'_, Source> {
+        self.source.lock().unwrap()
+    }
+
+    fn series(&self) -> MutexGuard<'_, Series> {
+        self.series.lock().unwrap()
+    }
+
+    // Read the source and record the change since the last read.
+    //
+    // Failed reads are skipped - the next sample covers the missed interval.
+    fn poll(&self) {
+        let values = match self.source().read() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let at = Instant::now();
+
+        self.series().push(&self.labels, values, at);
+    }
+}
+
+/// Periodically reads a [`Counter`] or [`CounterGroup`] on a background
+/// thread, recording how the counts change over time.
+///
+/// Every interval (see [`set_interval`]) the counters are read, and the change
+/// since the previous read is recorded as a timestamped [`Sample`]. A final
+/// sample is recorded when the poller is stopped, so the deltas of every
+/// sample add up to the total counted.
+///
+/// Samples are held in memory until drained, with the oldest samples discarded
+/// once [`DEFAULT_POLL_CAPACITY`] samples are held (see [`set_capacity`]). The
+/// samples can be read or drained while the poller is running.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use pmc::*;
+///
+/// let group = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .group(vec!["instructions", "cycles"])?;
+///
+/// let mut poller = Poller::from(group);
+/// poller.set_interval(Duration::from_millis(10));
+///
+/// let handle = poller.start()?;
+///
+/// // Do some work, periodically draining the samples...
+/// for sample in handle.drain() {
+///     println!("{}", sample);
+/// }
+///
+/// handle.stop();
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Counter`]: struct.Counter.html
+/// [`CounterGroup`]: struct.CounterGroup.html
+/// [`set_interval`]: #method.set_interval
+/// [`Sample`]: struct.Sample.html
+/// [`DEFAULT_POLL_CAPACITY`]: constant.DEFAULT_POLL_CAPACITY.html
+/// [`set_capacity`]: #method.set_capacity
+#[derive(Debug)]
+pub struct Poller {
+    state: Arc<State>,
+    interval: Duration,
+}
+
+impl Poller {
+    fn new(source: Source) -> Self {
+        let labels = source.labels();
+        let now = Instant::now();
+
+        Self {
+            state: Arc::new(State {
+                source: Mutex::new(source),
+                series: Mutex::new(Series {
+                    samples: VecDeque::new(),
+                    capacity: DEFAULT_POLL_CAPACITY,
+                    dropped: 0,
+                    last: Vec::new(),
+                    last_at: now,
+                    started: now,
+                }),
+                labels,
+            }),
+            interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Set the interval between reads.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Set the maximum number of samples held, discarding the oldest samples
+    /// if more are currently held.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0, "poller capacity must be non-zero");
+
+        let mut series = self.state.series();
+        series.capacity = capacity;
+        while series.samples.len() > capacity {
+            series.samples.pop_front();
+            series.dropped += 1;
+        }
+    }
+
+    /// Returns the labels of the polled events, in allocation order.
+    ///
+    /// A single [`Counter`] is labelled `value`.
+    ///
+    /// [`Counter`]: struct.Counter.html
+    pub fn labels(&self) -> &[String] {
+        &self.state.labels
+    }
+
+    /// Start the counters, and begin polling them.
+    ///
+    /// Sample timestamps are relative to this call. The counters stop, and
+    /// polling ends, when the returned [`RunningPoller`] handle is dropped.
+    ///
+    /// [`RunningPoller`]: struct.RunningPoller.html
+    #[must_use = "poller only runs until handle is dropped"]
+    pub fn start(&mut self) -> Result<RunningPoller<'_>, Error> {
+        {
+            let source = self.state.source();
+            let last = source.read()?;
+            source.start()?;
+
+            let now = Instant::now();
+            let mut series = self.state.series();
+            series.last = last;
+            series.last_at = now;
+            series.started = now;
+        }
+
+        // Spawn a thread to read the counters, stopped by dropping the
+        // sender.
+        let (tx, rx) = mpsc::channel::<()>();
+        let state = Arc::clone(&self.state);
+        let interval = self.interval;
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                state.poll();
+            }
+        });
+
+        Ok(RunningPoller {
+            poller: self,
+            reader: Some((tx, handle)),
+        })
+    }
+
+    /// Returns a copy of the samples currently held, oldest first.
+    pub fn samples(&self) -> Vec<Sample> {
+        self.state.series().samples.iter().cloned().collect()
+    }
+
+    /// Remove and return the samples currently held, oldest first.
+    pub fn drain(&self) -> Vec<Sample> {
+        self.state.series().samples.drain(..).collect()
+    }
+
+    /// Returns the number of samples discarded because the capacity was
+    /// reached before they were drained.
+    pub fn dropped(&self) -> u64 {
+        self.state.series().dropped
+    }
+}
+
+impl From<Counter> for Poller {
+    fn from(counter: Counter) -> Self {
+        Self::new(Source::Counter(counter))
+    }
+}
+
+impl From<CounterGroup> for Poller {
+    fn from(group: CounterGroup) -> Self {
+        Self::new(Source::Group(group))
+    }
+}
+
+/// A handle to a running [`Poller`].
+///
+/// Dropping this handle records a final sample, and causes the counters to
+/// stop recording events.
+///
+/// [`Poller`]: struct.Poller.html
+#[derive(Debug)]
+pub struct RunningPoller<'a> {
+    poller: &'a mut Poller,
+    reader: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl<'a> RunningPoller<'a> {
+    /// Returns a copy of the samples currently held, oldest first.
+    pub fn samples(&self) -> Vec<Sample> {
+        self.poller.samples()
+    }
+
+    /// Remove and return the samples currently held, oldest first.
+    pub fn drain(&self) -> Vec<Sample> {
+        self.poller.drain()
+    }
+
+    /// Returns the number of samples discarded because the capacity was
+    /// reached before they were drained.
+    pub fn dropped(&self) -> u64 {
+        self.poller.dropped()
+    }
+
+    /// Stop polling, and stop the counters from recording new events.
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl<'a> Drop for RunningPoller<'a> {
+    fn drop(&mut self) {
+        if let Some((tx, handle)) = self.reader.take() {
+            drop(tx);
+            let _ = handle.join();
+        }
+
+        let state = &self.poller.state;
+        state.source().stop();
+        state.poll();
+    }
+}
+
+/// The change in the polled counters over one interval of a [`Poller`].
+///
+/// [`Poller`]: struct.Poller.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    at: Duration,
+    interval: Duration,
+    deltas: GroupReading,
+}
+
+impl Sample {
+    /// Returns the time the sample was taken, relative to the poller starting.
+    pub fn at(&self) -> Duration {
+        self.at
+    }
+
+    /// Returns the time since the previous sample, that the deltas were
+    /// counted over.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the change in each polled event over the interval.
+    pub fn deltas(&self) -> &GroupReading {
+        &self.deltas
+    }
+
+    /// Returns the change in the event labelled `label`.
+    pub fn get(&self, label: &str) -> Option<u64> {
+        self.deltas.get(label)
+    }
+
+    /// Returns the change in the first polled event - the only event when
+    /// polling a single [`Counter`].
+    ///
+    /// [`Counter`]: struct.Counter.html
+    pub fn value(&self) -> u64 {
+        self.deltas
+            .iter()
+            .next()
+            .map(|(_, v)| v)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{:?}: {}", self.at, self.deltas)
+    }
+}
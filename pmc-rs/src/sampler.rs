@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use crate::counter::{Counter, Running};
+use crate::error::{new_os_error, Error, ErrorKind};
+
+/// The default number of events between samples, matching `pmcstat(8)`.
+pub const DEFAULT_SAMPLE_RATE: u64 = 65536;
+
+/// An allocated sampling PMC, recording samples to a log file.
+///
+/// Samplers are initialised using [`CounterBuilder::sampler`]. Every
+/// `sample_rate` events the PMC records a sample (such as the instruction
+/// pointer of the process) to the configured log file, which can then be
+/// analysed with tools such as `pmcstat(8)`.
+///
+/// Dropping the sampler flushes any buffered samples, releases the PMC and
+/// closes the log file.
+///
+/// The backend records every sampler's samples to a single log file (for
+/// [`hwpmc`], one per process), so samplers existing at the same time must
+/// share the same file - allocating a sampler for a different file fails with
+/// [`LogFileInUse`]. The log is closed once the last sampler using it is
+/// dropped.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use pmc::*;
+///
+/// let mut sampler = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .sampler("inst_retired.any", File::create("pmc.log").unwrap())?;
+///
+/// let handle = sampler.start()?;
+///
+/// // Do some work...
+///
+/// handle.stop();
+/// sampler.flush()?;
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`CounterBuilder::sampler`]: struct.CounterBuilder.html#method.sampler
+/// [`hwpmc`]: https://www.freebsd.org/cgi/man.cgi?query=hwpmc
+/// [`LogFileInUse`]: enum.ErrorKind.html#variant.LogFileInUse
+#[derive(Debug)]
+pub struct Sampler {
+    // Always Some until dropped, allowing the PMC to be released before the
+    // log file is closed.
+    counter: Option<Counter>,
+    log: File,
+}
+
+impl Sampler {
+    pub(crate) fn new(counter: Counter, log: File) -> Result<Self, Error> {
+        counter
+            .backend()
+            .configure_log(log.as_raw_fd())
+            .map_err(log_error)?;
+
+        Ok(Self {
+            counter: Some(counter),
+            log,
+        })
+    }
+
+    /// Start sampling.
+    ///
+    /// The sampler stops when the returned [`Running`] handle is dropped.
+    ///
+    /// [`Running`]: struct.Running.html
+    #[must_use = "sampler only runs until handle is dropped"]
+    pub fn start(&mut self) -> Result<Running<'_>, Error> {
+        self.counter.as_mut().unwrap().start()
+    }
+
+    /// Flush any buffered samples to the log file.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.counter().backend().flush_log().map_err(log_error)
+    }
+
+    /// Returns a reference to the log file samples are recorded to.
+    pub fn log(&self) -> &File {
+        &self.log
+    }
+
+    fn counter(&self) -> &Counter {
+        self.counter.as_ref().unwrap()
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        let counter = self.counter.take().unwrap();
+        let backend = counter.backend().clone();
+
+        let _ = backend.flush_log();
+
+        // The PMC MUST be released before the log file is closed
+        drop(counter);
+
+        let _ = backend.close_log();
+    }
+}
+
+fn log_error(err: io::Error) -> Error {
+    match err.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) => new_os_error(ErrorKind::Forbidden, err),
+        Some(libc::ENOSYS) => new_os_error(ErrorKind::Unsupported, err),
+        Some(libc::EBUSY) => new_os_error(ErrorKind::LogFileInUse, err),
+        _ => new_os_error(ErrorKind::Unknown, err),
+    }
+}
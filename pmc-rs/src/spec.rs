@@ -0,0 +1,394 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{new_parse_error, Error, ErrorKind, ParseError};
+
+/// A qualifier modifying how an [`EventSpec`] is counted.
+///
+/// Qualifiers follow the `libpmc` event specification syntax (see `man
+/// pmc.core`), and are written after the event name separated by commas.
+/// Qualifiers without a variant of their own are kept as [`Other`], and passed
+/// to the backend unchanged.
+///
+/// [`EventSpec`]: struct.EventSpec.html
+/// [`Other`]: #variant.Other
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Qualifier {
+    /// Count events while the CPU is executing in user mode (`usr`).
+    Usr,
+
+    /// Count events while the CPU is executing in kernel mode (`os`).
+    Os,
+
+    /// Count the rising edges of the event condition (`edge`).
+    Edge,
+
+    /// Invert the counter mask comparison (`inv`).
+    Inv,
+
+    /// Count events from any hardware thread on the core (`anythread`).
+    AnyThread,
+
+    /// Only count cycles where at least this many events occur (`cmask=`).
+    ///
+    /// This and the other numeric qualifiers are 8-bit fields, so larger
+    /// values are rejected when the spec is parsed or allocated.
+    Cmask(u32),
+
+    /// The unit mask selecting the event sub-type (`umask=`).
+    Umask(u32),
+
+    /// The event threshold (`threshold=`).
+    Threshold(u32),
+
+    /// Any other `key` or `key=value` qualifier, such as `ldlat=3` or
+    /// `offcore_rsp=0x10003c0091`, holding the qualifier as written.
+    Other(String),
+}
+
+impl Qualifier {
+    pub(crate) fn key(&self) -> &str {
+        match self {
+            Qualifier::Usr => "usr",
+            Qualifier::Os => "os",
+            Qualifier::Edge => "edge",
+            Qualifier::Inv => "inv",
+            Qualifier::AnyThread => "anythread",
+            Qualifier::Cmask(_) => "cmask",
+            Qualifier::Umask(_) => "umask",
+            Qualifier::Threshold(_) => "threshold",
+            Qualifier::Other(v) => v.split('=').next().unwrap_or_default(),
+        }
+    }
+
+    /// Returns the value of an [`Other`] qualifier, if any.
+    ///
+    /// [`Other`]: #variant.Other
+    pub(crate) fn other_value(&self) -> Option<&str> {
+        match self {
+            Qualifier::Other(v) => v.split_once('=').map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Qualifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Qualifier::Cmask(v) | Qualifier::Threshold(v) => write!(f, "{}={}", self.key(), v),
+            Qualifier::Umask(v) => write!(f, "{}={:#x}", self.key(), v),
+            Qualifier::Other(v) => write!(f, "{}", v),
+            _ => write!(f, "{}", self.key()),
+        }
+    }
+}
+
+/// An event name and its qualifiers, such as
+/// `inst_retired.any,usr,cmask=1`.
+///
+/// `EventSpec` values can be built programmatically, or parsed from the
+/// `libpmc` event specification syntax. Parsing validates the event name and
+/// qualifiers without making any system calls, reporting the offending token
+/// of a malformed specification as a [`ParseError`].
+///
+/// The name may also be a `perf(1)` style PMU event, either naming a sysfs
+/// event (`cpu/cache-misses/`) or listing its format terms
+/// (`cpu/event=0x3c,umask=0x0/`) - commas between the slashes are part of the
+/// name.
+///
+/// [`CounterBuilder::allocate`] accepts an `EventSpec` anywhere a string
+/// event specification can be used, and validates string specifications with
+/// the same parser before allocating.
+///
+/// ```
+/// use pmc::*;
+///
+/// let spec = EventSpec::new("uops_issued.any").usr().cmask(1).inv();
+/// assert_eq!(spec.to_string(), "uops_issued.any,usr,cmask=1,inv");
+///
+/// let parsed: EventSpec = "uops_issued.any,usr,cmask=1,inv".parse()?;
+/// assert_eq!(parsed, spec);
+///
+/// let err = EventSpec::parse("uops_issued.any,usr,cmask=lots").unwrap_err();
+/// assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`ParseError`]: struct.ParseError.html
+/// [`CounterBuilder::allocate`]: struct.CounterBuilder.html#method.allocate
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EventSpec {
+    name: String,
+    qualifiers: Vec<Qualifier>,
+}
+
+impl EventSpec {
+    /// Initialise a spec for the event `name`, without any qualifiers.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            qualifiers: vec![],
+        }
+    }
+
+    /// Parse a `libpmc` event specification.
+    ///
+    /// Returns an [`ErrorKind::InvalidEventSpec`] error with a [`ParseError`]
+    /// [cause] if the specification is malformed.
+    ///
+    /// [`ErrorKind::InvalidEventSpec`]: enum.ErrorKind.html#variant.InvalidEventSpec
+    /// [`ParseError`]: struct.ParseError.html
+    /// [cause]: struct.Error.html#method.cause
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        Self::parse_positioned(spec).map(|(event, _)| event)
+    }
+
+    /// Parse a `libpmc` event specification as [`parse`] does, also returning
+    /// the byte offset of each qualifier in `spec`.
+    ///
+    /// [`parse`]: #method.parse
+    pub(crate) fn parse_positioned(spec: &str) -> Result<(Self, Vec<usize>), Error> {
+        parse(spec).map_err(|e| new_parse_error(ErrorKind::InvalidEventSpec, e))
+    }
+
+    /// Returns the event name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the qualifiers, in the order they were added.
+    pub fn qualifiers(&self) -> &[Qualifier] {
+        &self.qualifiers
+    }
+
+    /// Add `qualifier`, replacing any existing qualifier of the same kind.
+    pub fn with(mut self, qualifier: Qualifier) -> Self {
+        match self
+            .qualifiers
+            .iter_mut()
+            .find(|q| q.key() == qualifier.key())
+        {
+            Some(q) => *q = qualifier,
+            None => self.qualifiers.push(qualifier),
+        }
+        self
+    }
+
+    /// Count events in user mode - see [`Qualifier::Usr`].
+    ///
+    /// [`Qualifier::Usr`]: enum.Qualifier.html#variant.Usr
+    pub fn usr(self) -> Self {
+        self.with(Qualifier::Usr)
+    }
+
+    /// Count events in kernel mode - see [`Qualifier::Os`].
+    ///
+    /// [`Qualifier::Os`]: enum.Qualifier.html#variant.Os
+    pub fn os(self) -> Self {
+        self.with(Qualifier::Os)
+    }
+
+    /// Count rising edges - see [`Qualifier::Edge`].
+    ///
+    /// [`Qualifier::Edge`]: enum.Qualifier.html#variant.Edge
+    pub fn edge(self) -> Self {
+        self.with(Qualifier::Edge)
+    }
+
+    /// Invert the counter mask - see [`Qualifier::Inv`].
+    ///
+    /// [`Qualifier::Inv`]: enum.Qualifier.html#variant.Inv
+    pub fn inv(self) -> Self {
+        self.with(Qualifier::Inv)
+    }
+
+    /// Count events from any hardware thread - see [`Qualifier::AnyThread`].
+    ///
+    /// [`Qualifier::AnyThread`]: enum.Qualifier.html#variant.AnyThread
+    pub fn any_thread(self) -> Self {
+        self.with(Qualifier::AnyThread)
+    }
+
+    /// Set the counter mask - see [`Qualifier::Cmask`].
+    ///
+    /// [`Qualifier::Cmask`]: enum.Qualifier.html#variant.Cmask
+    pub fn cmask(self, cmask: u32) -> Self {
+        self.with(Qualifier::Cmask(cmask))
+    }
+
+    /// Set the unit mask - see [`Qualifier::Umask`].
+    ///
+    /// [`Qualifier::Umask`]: enum.Qualifier.html#variant.Umask
+    pub fn umask(self, umask: u32) -> Self {
+        self.with(Qualifier::Umask(umask))
+    }
+
+    /// Set the threshold - see [`Qualifier::Threshold`].
+    ///
+    /// [`Qualifier::Threshold`]: enum.Qualifier.html#variant.Threshold
+    pub fn threshold(self, threshold: u32) -> Self {
+        self.with(Qualifier::Threshold(threshold))
+    }
+}
+
+impl FromStr for EventSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for EventSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for q in &self.qualifiers {
+            write!(f, ",{}", q)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<EventSpec> for String {
+    fn from(v: EventSpec) -> Self {
+        v.to_string()
+    }
+}
+
+impl From<&EventSpec> for String {
+    fn from(v: &EventSpec) -> Self {
+        v.to_string()
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | ':' | '/')
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse(spec: &str) -> Result<(EventSpec, Vec<usize>), ParseError> {
+    // Split the spec into comma separated tokens, tracking the byte offset of
+    // each. Commas between the slashes of a PMU event are part of the name.
+    let mut tokens = vec![];
+    let mut start = 0;
+    let mut in_pmu = false;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '/' if tokens.is_empty() => in_pmu = !in_pmu,
+            ',' if !in_pmu => {
+                tokens.push((start, &spec[start..i]));
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    tokens.push((start, &spec[start..]));
+
+    let (name_pos, name) = tokens[0];
+    if name.is_empty() {
+        let text = if spec.is_empty() { "" } else { "," };
+        return Err(ParseError::new("expected event name", name_pos, text));
+    }
+    // The format terms of a PMU event are checked by the backend.
+    let mut in_pmu = false;
+    let invalid = name.char_indices().find(|&(_, c)| {
+        in_pmu ^= c == '/';
+        !(is_name_char(c) || (in_pmu && matches!(c, '=' | ',')))
+    });
+    if let Some((i, c)) = invalid {
+        return Err(ParseError::new(
+            "invalid character in event name",
+            name_pos + i,
+            c.to_string(),
+        ));
+    }
+
+    let mut event = EventSpec::new(name);
+    let mut positions = vec![];
+    for &(pos, token) in &tokens[1..] {
+        if token.is_empty() {
+            // An empty token is either a trailing comma, or two in a row.
+            let text = if pos == spec.len() { "" } else { "," };
+            return Err(ParseError::new("expected qualifier", pos, text));
+        }
+
+        let (key, value) = match token.split_once('=') {
+            Some((k, v)) => (k, Some(v)),
+            None => (token, None),
+        };
+
+        let flag = match key {
+            "usr" => Some(Qualifier::Usr),
+            "os" => Some(Qualifier::Os),
+            "edge" => Some(Qualifier::Edge),
+            "inv" => Some(Qualifier::Inv),
+            "anythread" => Some(Qualifier::AnyThread),
+            _ => None,
+        };
+
+        let qualifier = match (flag, value) {
+            (Some(q), None) => q,
+            (Some(_), Some(_)) => {
+                return Err(ParseError::new(
+                    "qualifier does not take a value",
+                    pos,
+                    token,
+                ))
+            }
+            (None, _) if matches!(key, "cmask" | "umask" | "threshold") => {
+                let value = value
+                    .filter(|v| !v.is_empty())
+                    .ok_or_else(|| ParseError::new("expected qualifier value", pos, token))?;
+
+                let n = parse_number(value)
+                    .ok_or_else(|| ParseError::new("invalid number", pos + key.len() + 1, value))?;
+
+                // The counter mask, unit mask and threshold are 8-bit fields
+                // of the event select register.
+                if n > 0xff {
+                    return Err(ParseError::new(
+                        "value out of range",
+                        pos + key.len() + 1,
+                        value,
+                    ));
+                }
+
+                match key {
+                    "cmask" => Qualifier::Cmask(n),
+                    "umask" => Qualifier::Umask(n),
+                    _ => Qualifier::Threshold(n),
+                }
+            }
+            (None, _) => {
+                // Other qualifiers are passed to the backend, which rejects
+                // any it does not support.
+                if key.is_empty() || !key.chars().all(is_key_char) {
+                    return Err(ParseError::new("invalid qualifier", pos, token));
+                }
+                if matches!(value, Some(v) if v.is_empty() || v.contains('=')) {
+                    return Err(ParseError::new("expected qualifier value", pos, token));
+                }
+                Qualifier::Other(token.to_string())
+            }
+        };
+
+        if event.qualifiers.iter().any(|q| q.key() == qualifier.key()) {
+            return Err(ParseError::new("duplicate qualifier", pos, token));
+        }
+        event.qualifiers.push(qualifier);
+        positions.push(pos);
+    }
+
+    Ok((event, positions))
+}
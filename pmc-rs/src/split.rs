@@ -0,0 +1,119 @@
+use std::fmt;
+
+use crate::counter::{Counter, Running};
+use crate::error::Error;
+
+/// The same event counted separately in user and kernel mode.
+///
+/// Split counters are initialised using [`CounterBuilder::allocate_split`].
+///
+/// ```no_run
+/// use pmc::*;
+///
+/// let mut instr = CounterBuilder::default()
+///     .attach_to(vec![0])
+///     .allocate_split("inst_retired.any")?;
+///
+/// let handle = instr.start()?;
+///
+/// // Do some work...
+///
+/// handle.stop();
+///
+/// let r = instr.read()?;
+/// println!("user: {}, kernel: {}", r.user(), r.kernel());
+/// #
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`CounterBuilder::allocate_split`]: struct.CounterBuilder.html#method.allocate_split
+#[derive(Debug)]
+pub struct SplitCounter {
+    user: Counter,
+    kernel: Counter,
+}
+
+impl SplitCounter {
+    pub(crate) fn new(user: Counter, kernel: Counter) -> Self {
+        Self { user, kernel }
+    }
+
+    /// Start counting in both modes.
+    ///
+    /// The counters stop when the returned [`RunningSplit`] handle is dropped.
+    ///
+    /// [`RunningSplit`]: struct.RunningSplit.html
+    #[must_use = "counter only runs until handle is dropped"]
+    pub fn start(&mut self) -> Result<RunningSplit<'_>, Error> {
+        let user = self.user.start()?;
+        let kernel = self.kernel.start()?;
+
+        Ok(RunningSplit { user, kernel })
+    }
+
+    /// Read the user and kernel mode counts.
+    pub fn read(&self) -> Result<SplitReading, Error> {
+        Ok(SplitReading {
+            user: self.user.read()?,
+            kernel: self.kernel.read()?,
+        })
+    }
+}
+
+/// A handle to a running [`SplitCounter`].
+///
+/// Dropping this handle causes both counters to stop recording events.
+///
+/// [`SplitCounter`]: struct.SplitCounter.html
+#[derive(Debug)]
+pub struct RunningSplit<'a> {
+    user: Running<'a>,
+    kernel: Running<'a>,
+}
+
+impl<'a> RunningSplit<'a> {
+    /// Read the current user and kernel mode counts.
+    pub fn read(&self) -> Result<SplitReading, Error> {
+        Ok(SplitReading {
+            user: self.user.read()?,
+            kernel: self.kernel.read()?,
+        })
+    }
+
+    /// Stop the counters from recording new events.
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+/// The user and kernel mode counts of a [`SplitCounter`].
+///
+/// [`SplitCounter`]: struct.SplitCounter.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitReading {
+    user: u64,
+    kernel: u64,
+}
+
+impl SplitReading {
+    /// Returns the number of events counted in user mode.
+    pub fn user(&self) -> u64 {
+        self.user
+    }
+
+    /// Returns the number of events counted in kernel mode.
+    pub fn kernel(&self) -> u64 {
+        self.kernel
+    }
+
+    /// Returns the sum of the user and kernel mode counts.
+    pub fn total(&self) -> u64 {
+        self.user.wrapping_add(self.kernel)
+    }
+}
+
+impl fmt::Display for SplitReading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user: {}, kernel: {}", self.user, self.kernel)
+    }
+}
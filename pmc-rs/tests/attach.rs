@@ -0,0 +1,88 @@
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_attach_detach() {
+    let sim = Simulated::default();
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![42])
+        .allocate("instructions")
+        .unwrap();
+    assert_eq!(counter.attached_pids(), vec![42]);
+
+    counter.attach(43).unwrap();
+    counter.attach(44).unwrap();
+    assert_eq!(counter.attached_pids(), vec![42, 43, 44]);
+
+    sim.clear_calls();
+    counter.detach(43).unwrap();
+    assert_eq!(sim.calls(), vec![Call::Detach(0, 43)]);
+    assert_eq!(counter.attached_pids(), vec![42, 44]);
+
+    // Detached PIDs are not detached again on release.
+    sim.clear_calls();
+    drop(counter);
+    assert_eq!(
+        sim.calls(),
+        vec![Call::Detach(0, 42), Call::Detach(0, 44), Call::Release(0)]
+    );
+}
+
+#[test]
+fn test_attach_errors() {
+    let sim = Simulated::default();
+
+    // System-wide counters cannot be attached to processes.
+    let mut system = sim.builder().allocate("instructions").unwrap();
+    assert!(system.attached_pids().is_empty());
+    assert_eq!(system.attach(42).unwrap_err().kind(), &ErrorKind::BadScope);
+    assert_eq!(system.detach(42).unwrap_err().kind(), &ErrorKind::BadTarget);
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![])
+        .allocate("instructions")
+        .unwrap();
+
+    counter.attach(42).unwrap();
+    assert_eq!(
+        counter.attach(42).unwrap_err().kind(),
+        &ErrorKind::AlreadyAttached
+    );
+
+    sim.fail_next(Op::Attach, libc::ESRCH);
+    assert_eq!(
+        counter.attach(43).unwrap_err().kind(),
+        &ErrorKind::BadTarget
+    );
+    assert_eq!(counter.attached_pids(), vec![42]);
+
+    assert_eq!(
+        counter.detach(43).unwrap_err().kind(),
+        &ErrorKind::BadTarget
+    );
+
+    // A failed detach leaves the PID attached.
+    sim.fail_next(Op::Detach, libc::EIO);
+    assert_eq!(counter.detach(42).unwrap_err().kind(), &ErrorKind::Unknown);
+    assert_eq!(counter.attached_pids(), vec![42]);
+}
+
+#[test]
+fn test_never_detach_pid_0() {
+    let sim = Simulated::default();
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![0])
+        .allocate("instructions")
+        .unwrap();
+
+    assert_eq!(counter.detach(0).unwrap_err().kind(), &ErrorKind::Forbidden);
+    assert_eq!(counter.attached_pids(), vec![0]);
+
+    drop(counter);
+    assert!(!sim.calls().iter().any(|c| c.op() == Op::Detach));
+}
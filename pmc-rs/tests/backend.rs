@@ -0,0 +1,61 @@
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_custom_backend() {
+    let sim = Simulated::default();
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![0, 1234])
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    assert_eq!(counter.set(42).unwrap(), 0);
+
+    let handle = counter.start().expect("failed to start counter");
+    assert_eq!(handle.read().unwrap(), 42);
+    handle.stop();
+
+    drop(counter);
+
+    let spec = &sim.allocations()[0];
+    assert_eq!(spec.event, "inst_retired.any");
+    assert_eq!(spec.mode, Mode::ProcessCounting);
+    assert_eq!(spec.cpu, CPU_ANY);
+
+    let calls = sim.calls();
+    assert_eq!(
+        &calls[2..],
+        &[
+            Call::Attach(0, 0),
+            Call::Attach(0, 1234),
+            Call::Write(0, 42),
+            Call::Start(0),
+            Call::Read(0),
+            Call::Stop(0),
+            // PID 0 is never detached
+            Call::Detach(0, 1234),
+            Call::Release(0),
+        ]
+    );
+}
+
+#[test]
+fn test_custom_backend_system_scope() {
+    let sim = Simulated::default();
+
+    let counter = sim
+        .builder()
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    drop(counter);
+
+    let spec = &sim.allocations()[0];
+    assert_eq!(spec.mode, Mode::SystemCounting);
+    assert_eq!(spec.cpu, 0);
+
+    let calls = sim.calls();
+    assert_eq!(&calls[2..], &[Call::Release(0)]);
+}
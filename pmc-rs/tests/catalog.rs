@@ -0,0 +1,126 @@
+use pmc::*;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sysfs");
+
+#[test]
+fn test_sysfs_events() {
+    let events = sysfs_events(FIXTURE).expect("failed to list events");
+
+    let names: Vec<&str> = events.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "cpu/cpu-cycles",
+            "cpu/instructions",
+            "cpu/int_misc.recovery_cycles",
+            "cpu/mem-loads",
+            "msr/smi",
+            "msr/tsc",
+        ]
+    );
+
+    let cycles = &events[0];
+    assert_eq!(cycles.pmu, "cpu");
+    assert_eq!(cycles.description, None);
+    assert_eq!(
+        cycles.qualifiers,
+        vec!["usr", "os", "edge", "inv", "anythread", "cmask", "umask"]
+    );
+
+    // The msr PMU has no format terms matching qualifiers.
+    assert_eq!(events[5].pmu, "msr");
+    assert_eq!(events[5].qualifiers, vec!["usr", "os"]);
+
+    // Every listed event is a valid event spec.
+    for name in names {
+        EventSpec::parse(name).expect("invalid event name");
+    }
+}
+
+#[test]
+fn test_sysfs_events_missing_dir() {
+    let err = sysfs_events("/does/not/exist").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::Unknown);
+}
+
+#[cfg(target_os = "linux")]
+mod perf {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let events = events().expect("failed to list events");
+
+        let task_clock = events
+            .iter()
+            .find(|e| e.name == "task-clock")
+            .expect("missing task-clock");
+        assert_eq!(task_clock.pmu, "software");
+        assert!(task_clock.description.is_some());
+
+        // Every generic event is allocatable by name.
+        CounterBuilder::default()
+            .attach_to(vec![0])
+            .allocate(task_clock.name.as_str())
+            .expect("failed to allocate listed event");
+    }
+
+    #[test]
+    fn test_allocate_sysfs_event() {
+        if !Path::new(SYSFS_EVENT_SOURCES)
+            .join("msr/events/tsc")
+            .exists()
+        {
+            return;
+        }
+
+        let mut counter = CounterBuilder::default()
+            .allocate("msr/tsc")
+            .expect("failed to allocate sysfs event");
+
+        let handle = counter.start().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        assert!(handle.read().unwrap() > 0);
+        handle.stop();
+
+        // Qualifiers without a matching format term are rejected.
+        let err = CounterBuilder::default()
+            .allocate(EventSpec::new("msr/tsc").umask(1))
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::AllocInit);
+
+        let err = CounterBuilder::default()
+            .allocate("msr/does-not-exist")
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::AllocInit);
+    }
+    #[test]
+    fn test_allocate_pmu_terms() {
+        // software/config=0/ is the cpu-clock event.
+        let mut counter = CounterBuilder::default()
+            .attach_to(vec![0])
+            .allocate("software/config=0/")
+            .expect("failed to allocate PMU terms event");
+
+        let handle = counter.start().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        assert!(handle.read().unwrap() > 0);
+        handle.stop();
+
+        // Unknown qualifiers are applied as format terms, which the software
+        // PMU and generic events do not have.
+        for spec in &[
+            "software/config=0/,ldlat=3",
+            "cpu-clock,ldlat=3",
+            "software/bogus=1/",
+        ] {
+            let err = CounterBuilder::default()
+                .attach_to(vec![0])
+                .allocate(*spec)
+                .unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::AllocInit, "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::process::Command;
+
+use pmc::backend::*;
+use pmc::*;
+
+fn sh(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+#[test]
+fn test_measure_command() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![100]);
+    sim.script_reads("cycles", vec![200]);
+
+    let (status, m) = sim
+        .builder()
+        .attach_to(vec![42])
+        .measure_command(sh("exit 3"), vec!["instructions", "cycles"])
+        .expect("failed to measure command");
+
+    assert_eq!(status.code(), Some(3));
+    assert_eq!(m.get("instructions"), Some(100));
+    assert_eq!(m.get("cycles"), Some(200));
+
+    // Both counters are attached to the child only.
+    let attached: Vec<i32> = sim
+        .calls()
+        .into_iter()
+        .filter_map(|c| match c {
+            Call::Attach(_, pid) => Some(pid),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(attached.len(), 2);
+    assert_eq!(attached[0], attached[1]);
+    assert!(attached[0] != 42 && attached[0] != 0);
+
+    // The counters are released once measured.
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_measure_command_descendants() {
+    let sim = Simulated::default();
+
+    sim.builder()
+        .follow_descendants(true)
+        .measure_command(sh("true"), vec!["instructions"])
+        .unwrap();
+
+    assert!(sim
+        .allocations()
+        .iter()
+        .any(|spec| spec.mode == Mode::ProcessCounting && spec.descendants));
+}
+
+#[test]
+fn test_measure_command_alloc_error() {
+    let dir = std::env::temp_dir().join(format!("pmc-command-{}", std::process::id()));
+    let _ = std::fs::remove_file(&dir);
+
+    let sim = Simulated::default();
+    sim.fail_next(Op::Allocate, libc::EINVAL);
+
+    let err = sim
+        .builder()
+        .measure_command(
+            sh(&format!("touch {}", dir.display())),
+            vec!["instructions"],
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+
+    // The command never ran.
+    assert!(!dir.exists());
+}
+
+#[test]
+fn test_measure_command_spawn_error() {
+    let sim = Simulated::default();
+
+    let err = sim
+        .builder()
+        .measure_command(Command::new("/does/not/exist"), vec!["instructions"])
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::Spawn);
+
+    let cause = err.cause().and_then(|c| c.downcast_ref::<std::io::Error>());
+    assert_eq!(cause.map(|e| e.kind()), Some(std::io::ErrorKind::NotFound));
+    assert_eq!(sim.allocated(), 0);
+}
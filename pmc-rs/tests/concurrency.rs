@@ -0,0 +1,225 @@
+use std::panic;
+use std::thread;
+
+use pmc::backend::*;
+use pmc::*;
+
+const THREADS: usize = 64;
+const ITERATIONS: usize = 100;
+
+fn count(calls: &[Call], op: Op) -> usize {
+    calls.iter().filter(|c| c.op() == op).count()
+}
+
+#[test]
+fn test_concurrent_counters() {
+    let sim = Simulated::default();
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let sim = sim.clone();
+            thread::spawn(move || {
+                let pid = 1000 + t as i32;
+                for i in 0..ITERATIONS {
+                    let mut counter = sim
+                        .builder()
+                        .attach_to(vec![pid])
+                        .allocate("instructions")
+                        .expect("failed to allocate counter");
+
+                    counter.set(i as u64).unwrap();
+                    let handle = counter.start().unwrap();
+                    assert_eq!(handle.read().unwrap(), i as u64);
+                    handle.stop();
+
+                    counter.attach(pid + 10_000).unwrap();
+                    assert_eq!(counter.attached_pids(), vec![pid, pid + 10_000]);
+                }
+            })
+        })
+        .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(sim.allocated(), 0);
+
+    let calls = sim.calls();
+    let total = THREADS * ITERATIONS;
+    assert_eq!(count(&calls, Op::Allocate), total);
+    assert_eq!(count(&calls, Op::Release), total);
+    assert_eq!(count(&calls, Op::Attach), 2 * total);
+    assert_eq!(count(&calls, Op::Detach), 2 * total);
+
+    // Every allocated counter was released exactly once, after its targets
+    // were detached.
+    let mut allocated = 0;
+    let mut ids = std::collections::HashSet::new();
+    for call in &calls {
+        match call {
+            Call::Allocate(_) => allocated += 1,
+            Call::Release(id) => {
+                assert!(ids.insert(*id), "counter {} released twice", id);
+                allocated -= 1;
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(allocated, 0);
+    assert_eq!(ids.len(), total);
+}
+
+#[test]
+fn test_concurrent_groups() {
+    let sim = Simulated::default();
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let sim = sim.clone();
+            thread::spawn(move || {
+                let builder = sim.builder().attach_to(vec![0]);
+
+                for _ in 0..ITERATIONS / 10 {
+                    let (_, m) = builder
+                        .measure_group(vec!["a", "b", "c"], || ())
+                        .expect("failed to measure group");
+                    assert_eq!(m.counts().len(), 3);
+                }
+            })
+        })
+        .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(sim.allocated(), 0);
+
+    // Every group allocated a leader and two members.
+    let calls = sim.calls();
+    assert_eq!(count(&calls, Op::Allocate), THREADS * ITERATIONS / 10 * 3);
+    assert!(sim.allocations().iter().any(|spec| spec.group.is_some()));
+}
+
+#[test]
+fn test_survives_panics() {
+    let sim = Simulated::default();
+    sim.panic_on(Op::Allocate, "panic");
+    sim.panic_on(Op::Release, "panic-release");
+
+    // Silence the expected panic messages.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| ()));
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let builder = sim.builder();
+            thread::spawn(move || {
+                for i in 0..ITERATIONS / 10 {
+                    let event = match (t + i) % 4 {
+                        0 => "panic",
+                        1 => "panic-release",
+                        _ => "instructions",
+                    };
+
+                    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                        builder.allocate(event).unwrap();
+                    }));
+                    assert_eq!(res.is_err(), event != "instructions", "{}", event);
+                }
+            })
+        })
+        .collect();
+
+    let results: Vec<_> = threads.into_iter().map(|t| t.join()).collect();
+    panic::set_hook(hook);
+
+    for r in results {
+        r.expect("allocation failed after a panic");
+    }
+
+    // Allocations still succeed once all the panics have happened.
+    sim.builder()
+        .allocate("instructions")
+        .expect("failed to allocate after panics");
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_concurrent_perf_counters() {
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..10 {
+                    let mut counter = match CounterBuilder::default()
+                        .attach_to(vec![0])
+                        .allocate(SoftwareEvent::TaskClock)
+                    {
+                        Ok(v) => v,
+                        // perf events are unavailable or forbidden.
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                ErrorKind::Forbidden | ErrorKind::Unsupported
+                            ) =>
+                        {
+                            return
+                        }
+                        Err(e) => panic!("failed to allocate PMC: {}", e),
+                    };
+
+                    let handle = counter.start().unwrap();
+                    let mut x = 0u64;
+                    for i in 0..10_000 {
+                        x = x.wrapping_add(i);
+                    }
+                    assert!(x > 0);
+                    handle.stop();
+                }
+            })
+        })
+        .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+// Allocations and releases through libpmc are serialised by the backend, so
+// concurrent counters neither fail nor corrupt its tables.
+#[cfg(target_os = "freebsd")]
+#[test]
+fn test_concurrent_libpmc_counters() {
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            thread::spawn(|| {
+                // hwpmc attaches one PMC per hardware counter to a process, so
+                // each thread counts its own child.
+                let mut child = std::process::Command::new("sleep")
+                    .arg("60")
+                    .spawn()
+                    .unwrap();
+                let pid = child.id() as i32;
+
+                for _ in 0..ITERATIONS {
+                    let mut counter = CounterBuilder::default()
+                        .attach_to(vec![pid])
+                        .allocate_generic(GenericEvent::Instructions)
+                        .expect("failed to allocate PMC");
+
+                    counter.start().unwrap().stop();
+                    counter.read().unwrap();
+                }
+
+                child.kill().unwrap();
+                child.wait().unwrap();
+            })
+        })
+        .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+}
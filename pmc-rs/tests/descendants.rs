@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_descendants_spec() {
+    let sim = Simulated::default();
+
+    let builder = sim.builder().attach_to(vec![42]);
+
+    builder.allocate("a").unwrap();
+    builder
+        .clone()
+        .follow_descendants(true)
+        .allocate("b")
+        .unwrap();
+    builder.track_child_exits(true).allocate("c").unwrap();
+
+    let specs: Vec<(bool, bool)> = sim
+        .allocations()
+        .iter()
+        .map(|spec| (spec.descendants, spec.track_exits))
+        .collect();
+
+    // Tracking exits implies following descendants.
+    assert_eq!(specs, vec![(false, false), (true, false), (true, true)]);
+}
+
+#[test]
+fn test_child_exits_unsupported() {
+    let counter = Simulated::default()
+        .builder()
+        .attach_to(vec![42])
+        .track_child_exits(true)
+        .allocate("a")
+        .unwrap();
+
+    let err = counter.child_exits().unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::Unsupported);
+}
+
+#[cfg(target_os = "linux")]
+mod perf {
+    use std::process::Command;
+
+    use super::*;
+
+    #[test]
+    fn test_follow_descendants() {
+        let builder = CounterBuilder::default()
+            .set_backend(Arc::new(Perf::default()))
+            .attach_to(vec![0]);
+
+        let mut own = builder
+            .allocate(SoftwareEvent::TaskClock)
+            .expect("failed to allocate PMC");
+        let mut all = builder
+            .follow_descendants(true)
+            .allocate(SoftwareEvent::TaskClock)
+            .expect("failed to allocate PMC");
+
+        let h1 = own.start().unwrap();
+        let h2 = all.start().unwrap();
+
+        // Burn some CPU in a child.
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        h2.stop();
+        h1.stop();
+
+        assert!(all.read().unwrap() > own.read().unwrap());
+    }
+
+    #[test]
+    fn test_child_exits() {
+        let mut counter = CounterBuilder::default()
+            .set_backend(Arc::new(Perf::default()))
+            .attach_to(vec![0])
+            .track_child_exits(true)
+            .allocate(SoftwareEvent::TaskClock)
+            .expect("failed to allocate PMC");
+
+        let handle = counter.start().unwrap();
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg("sleep 0.01; sleep 0.01")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        handle.stop();
+
+        // The kernel may not report every exit, so only check the exits that
+        // are reported.
+        let total = counter.read().unwrap();
+        let exits = counter.child_exits().unwrap();
+        for exit in &exits {
+            assert!(exit.pid > 0);
+            assert!(exit.value <= total);
+        }
+    }
+
+    #[test]
+    fn test_child_exits_not_tracked() {
+        let counter = CounterBuilder::default()
+            .set_backend(Arc::new(Perf::default()))
+            .attach_to(vec![0])
+            .allocate(SoftwareEvent::TaskClock)
+            .unwrap();
+
+        let err = counter.child_exits().unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::BadScope);
+    }
+}
@@ -0,0 +1,162 @@
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_exited_pids() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![100]);
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![42, 43, 44])
+        .allocate("instructions")
+        .unwrap();
+
+    assert!(counter.exited_pids().unwrap().is_empty());
+
+    let handle = counter.start().unwrap();
+    sim.exit(43);
+    sim.exit(44);
+    handle.stop();
+
+    assert_eq!(counter.exited_pids().unwrap(), vec![43, 44]);
+
+    // The final value is kept.
+    assert_eq!(counter.read().unwrap(), 100);
+
+    // Exited targets can be detached.
+    sim.clear_calls();
+    counter.detach(43).unwrap();
+    assert_eq!(sim.calls(), vec![Call::Detach(0, 43)]);
+    assert_eq!(counter.attached_pids(), vec![42, 44]);
+    assert_eq!(counter.exited_pids().unwrap(), vec![44]);
+    assert_eq!(counter.read().unwrap(), 100);
+
+    // Exited targets are detached once only.
+    sim.clear_calls();
+    drop(counter);
+    assert_eq!(
+        sim.calls(),
+        vec![Call::Detach(0, 42), Call::Detach(0, 44), Call::Release(0)]
+    );
+}
+
+#[test]
+fn test_exited_errors() {
+    let sim = Simulated::default();
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![42])
+        .allocate("instructions")
+        .unwrap();
+
+    sim.fail_next(Op::Read, libc::ESRCH);
+    assert_eq!(counter.read().unwrap_err().kind(), &ErrorKind::TargetExited);
+
+    sim.fail_next(Op::Start, libc::ESRCH);
+    assert_eq!(
+        counter.start().unwrap_err().kind(),
+        &ErrorKind::TargetExited
+    );
+
+    sim.fail_next(Op::Exited, libc::ENOSYS);
+    assert_eq!(
+        counter.exited_pids().unwrap_err().kind(),
+        &ErrorKind::Unsupported
+    );
+
+    // Exited processes cannot be attached to.
+    sim.exit(43);
+    assert_eq!(
+        counter.attach(43).unwrap_err().kind(),
+        &ErrorKind::BadTarget
+    );
+}
+
+#[test]
+fn test_exited_process() {
+    // The default implementation checks for the process itself.
+    let mut child = std::process::Command::new("sleep")
+        .arg("10")
+        .spawn()
+        .unwrap();
+    let pid = child.id() as i32;
+
+    let backend = Unsupported::default();
+    assert!(!backend.exited(0, pid).unwrap());
+    assert!(!backend.exited(0, 0).unwrap());
+
+    child.kill().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    // A zombie has exited.
+    #[cfg(target_os = "linux")]
+    assert!(backend.exited(0, pid).unwrap());
+
+    child.wait().unwrap();
+    assert!(backend.exited(0, pid).unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_perf_exited() {
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg("read line")
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let pid = child.id() as i32;
+
+    let mut counter = CounterBuilder::default()
+        .attach_to(vec![pid])
+        .allocate(SoftwareEvent::TaskClock)
+        .expect("failed to allocate PMC");
+    assert!(counter.exited_pids().unwrap().is_empty());
+
+    let handle = counter.start().unwrap();
+
+    // Let the child exit.
+    drop(child.stdin.take());
+    child.wait().unwrap();
+
+    handle.stop();
+    assert_eq!(counter.exited_pids().unwrap(), vec![pid]);
+
+    let value = counter.read().unwrap();
+    assert!(value > 0);
+
+    // The events counted are kept after detaching.
+    counter.detach(pid).unwrap();
+    assert_eq!(counter.read().unwrap(), value);
+}
+
+#[cfg(target_os = "freebsd")]
+#[test]
+fn test_libpmc_exited() {
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg("read line")
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let pid = child.id() as i32;
+
+    let mut counter = CounterBuilder::default()
+        .attach_to(vec![pid])
+        .allocate_generic(GenericEvent::Instructions)
+        .expect("failed to allocate PMC");
+    assert!(counter.exited_pids().unwrap().is_empty());
+
+    drop(child.stdin.take());
+    child.wait().unwrap();
+    assert_eq!(counter.exited_pids().unwrap(), vec![pid]);
+
+    // hwpmc detaches exited processes, and refuses to start a process-mode
+    // PMC once all its targets are gone.
+    assert_eq!(
+        counter.start().unwrap_err().kind(),
+        &ErrorKind::TargetExited
+    );
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use pmc::backend::*;
+use pmc::*;
+
+const ALL: &[GenericEvent] = &[
+    GenericEvent::Instructions,
+    GenericEvent::Cycles,
+    GenericEvent::Branches,
+    GenericEvent::BranchMisses,
+    GenericEvent::CacheReferences,
+    GenericEvent::CacheMisses,
+    GenericEvent::LlcLoads,
+    GenericEvent::LlcLoadMisses,
+    GenericEvent::DtlbLoadMisses,
+];
+
+#[test]
+fn test_resolve() {
+    let sim = Simulated::default();
+    let builder = sim.builder();
+
+    let spec = builder.resolve(GenericEvent::BranchMisses).unwrap();
+    assert_eq!(spec, EventSpec::new("branch-misses"));
+    assert_eq!(
+        sim.calls(),
+        vec![Call::GenericEvent(GenericEvent::BranchMisses)]
+    );
+}
+
+#[test]
+fn test_allocate_generic() {
+    let sim = Simulated::default();
+    sim.builder()
+        .attach_to(vec![42])
+        .allocate_generic(GenericEvent::Instructions)
+        .unwrap();
+
+    assert!(sim
+        .allocations()
+        .iter()
+        .any(|spec| spec.event == "instructions"));
+}
+
+#[test]
+fn test_resolve_errors() {
+    let sim = Simulated::default();
+    let builder = sim.builder();
+
+    sim.fail_next(Op::GenericEvent, libc::ENOENT);
+    let err = builder
+        .allocate_generic(GenericEvent::LlcLoads)
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnmappedEvent);
+
+    // Nothing is allocated for an unmapped event.
+    assert_eq!(sim.allocated(), 0);
+
+    sim.fail_next(Op::GenericEvent, libc::ENOSYS);
+    let err = builder.resolve(GenericEvent::Cycles).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_vendor_names() {
+    let intel = CpuId::new("GenuineIntel", 6, 0x55, 4);
+    let amd = CpuId::new("AuthenticAMD", 25, 0x21, 0);
+
+    assert_eq!(
+        GenericEvent::Instructions.vendor_name(&intel),
+        Some("inst_retired.any")
+    );
+    assert_eq!(
+        GenericEvent::Instructions.vendor_name(&amd),
+        Some("ex_ret_instr")
+    );
+    assert_eq!(
+        GenericEvent::LlcLoadMisses.vendor_name(&intel),
+        Some("mem_load_retired.l3_miss")
+    );
+    assert_eq!(GenericEvent::LlcLoads.vendor_name(&intel), None);
+    assert_eq!(GenericEvent::LlcLoads.vendor_name(&amd), None);
+
+    // Unknown vendors have no mappings at all.
+    let other = CpuId::new("HygonGenuine", 24, 0, 0);
+    for event in ALL {
+        assert_eq!(event.vendor_name(&other), None);
+    }
+
+    // Every mapped name is a valid event spec.
+    for event in ALL {
+        for cpu in &[&intel, &amd] {
+            if let Some(name) = event.vendor_name(cpu) {
+                EventSpec::parse(name).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod perf {
+    use super::*;
+
+    #[test]
+    fn test_perf_generic_events() {
+        let builder = CounterBuilder::default().set_backend(Arc::new(Perf::default()));
+
+        // Every generic event is known to the perf backend, even if the
+        // hardware cannot count it.
+        let listed: Vec<String> = Perf::default()
+            .events()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        for event in ALL {
+            let spec = builder.resolve(*event).unwrap();
+            assert_eq!(spec.name(), event.name());
+            assert!(listed.iter().any(|e| e == event.name()), "{}", event);
+        }
+    }
+
+    #[test]
+    fn test_perf_unmapped_event() {
+        let builder = CounterBuilder::default()
+            .set_backend(Arc::new(Perf::default()))
+            .attach_to(vec![0]);
+
+        // perf has no generic L2 cache events.
+        let err = builder.resolve(GenericEvent::L2Loads).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnmappedEvent);
+
+        let err = builder
+            .allocate_generic(GenericEvent::L2LoadMisses)
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnmappedEvent);
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+mod libpmc {
+    use super::*;
+
+    #[test]
+    fn test_libpmc_generic_events() {
+        let cpu = match CpuId::current() {
+            Some(v) => v,
+            None => return,
+        };
+        let backend = LibPmc::default();
+        let listed: Vec<String> = backend
+            .events()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name.to_lowercase())
+            .collect();
+        let builder = CounterBuilder::default().set_backend(Arc::new(backend));
+
+        // Events without a vendor name for the running CPU (such as
+        // CacheMisses on AMD), or whose vendor name the CPU model does not
+        // have, are unmapped.
+        for event in ALL {
+            match event.vendor_name(&cpu) {
+                Some(name) if listed.iter().any(|e| e == name) => {
+                    assert_eq!(builder.resolve(*event).unwrap().name(), name)
+                }
+                _ => {
+                    let err = builder.resolve(*event).unwrap_err();
+                    assert_eq!(err.kind(), &ErrorKind::UnmappedEvent, "{}", event);
+                }
+            }
+        }
+    }
+}
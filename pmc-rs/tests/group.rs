@@ -0,0 +1,137 @@
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_group_allocation() {
+    let sim = Simulated::default();
+
+    let group = sim
+        .builder()
+        .attach_to(vec![42])
+        .group(vec!["inst_retired.any", "cpu_clk_unhalted.thread"])
+        .expect("failed to allocate group");
+
+    assert_eq!(group.labels(), &["inst_retired.any", "cpu_clk_unhalted.thread"]);
+
+    let specs = sim.allocations();
+
+    // The first counter leads the group
+    assert_eq!(specs.len(), 2);
+    assert_eq!(specs[0].group, None);
+    assert_eq!(specs[1].group, Some(0));
+
+    sim.clear_calls();
+    drop(group);
+
+    // Members are released before the leader
+    assert_eq!(
+        sim.calls(),
+        vec![
+            Call::Detach(1, 42),
+            Call::Release(1),
+            Call::Detach(0, 42),
+            Call::Release(0),
+        ]
+    );
+}
+
+#[test]
+fn test_group_read() {
+    let sim = Simulated::default();
+    sim.script_reads("inst_retired.any", vec![100, 300]);
+    sim.script_reads("cpu_clk_unhalted.thread", vec![50, 100]);
+
+    let mut group = sim
+        .builder()
+        .attach_to(vec![0])
+        .group(vec!["inst_retired.any", "cpu_clk_unhalted.thread"])
+        .expect("failed to allocate group");
+
+    let handle = group.start().expect("failed to start group");
+
+    let r1 = handle.read().expect("failed to read group");
+    assert_eq!(r1.get("inst_retired.any"), Some(100));
+    assert_eq!(r1.get("cpu_clk_unhalted.thread"), Some(50));
+    assert_eq!(r1.get("missing"), None);
+    assert_eq!(r1.len(), 2);
+
+    handle.stop();
+
+    let r2 = group.read().expect("failed to read group");
+    assert_eq!(
+        r2.iter().collect::<Vec<_>>(),
+        vec![("inst_retired.any", 300), ("cpu_clk_unhalted.thread", 100)]
+    );
+    assert_eq!(
+        r2.to_string(),
+        "inst_retired.any: 300, cpu_clk_unhalted.thread: 100"
+    );
+
+    let calls = sim.calls();
+    let ops: Vec<Call> = calls
+        .into_iter()
+        .filter(|c| matches!(c.op(), Op::Start | Op::Stop))
+        .collect();
+    assert_eq!(
+        ops,
+        vec![
+            Call::Start(0),
+            Call::Start(1),
+            Call::Stop(0),
+            Call::Stop(1)
+        ]
+    );
+}
+
+#[test]
+fn test_empty_group() {
+    let sim = Simulated::default();
+
+    let err = sim
+        .builder()
+        .group(Vec::<String>::new())
+        .expect_err("expected empty group to fail");
+
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+}
+
+#[test]
+fn test_group_member_alloc_failure() {
+    let sim = Simulated::default();
+    let err = sim
+        .builder()
+        .group(vec!["inst_retired.any", "bad\0name"])
+        .expect_err("expected group to fail");
+
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+
+    // The leader was released
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_perf_group() {
+    let mut group = CounterBuilder::default()
+        .attach_to(vec![0])
+        .group(vec![SoftwareEvent::TaskClock, SoftwareEvent::ContextSwitches])
+        .expect("failed to allocate group");
+
+    let handle = group.start().expect("failed to start group");
+
+    // Sleeping yields the CPU
+    for _ in 0..5 {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+
+    let running = handle.read().expect("failed to read group");
+    assert!(running.get("task-clock").unwrap() > 0);
+    assert!(running.get("context-switches").unwrap() > 0);
+
+    handle.stop();
+
+    // A stopped group does not advance
+    let r1 = group.read().unwrap();
+    let r2 = group.read().unwrap();
+    assert_eq!(r1, r2);
+}
@@ -0,0 +1,103 @@
+use std::panic;
+use std::thread;
+use std::time::Duration;
+
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_measure() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![42]);
+
+    let (result, m) = sim
+        .builder()
+        .attach_to(vec![0])
+        .measure("instructions", || {
+            thread::sleep(Duration::from_millis(5));
+            "done"
+        })
+        .unwrap();
+
+    assert_eq!(result, "done");
+    assert_eq!(m.value(), 42);
+    assert_eq!(m.get("instructions"), Some(42));
+    assert!(m.elapsed() >= Duration::from_millis(5));
+
+    // The counter is stopped before it is read, and released afterwards.
+    let ops: Vec<Op> = sim
+        .calls()
+        .iter()
+        .map(Call::op)
+        .filter(|op| ![Op::Init, Op::Allocate, Op::Attach, Op::Detach].contains(op))
+        .collect();
+    assert_eq!(ops, vec![Op::Start, Op::Stop, Op::Read, Op::Release]);
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_measure_group() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![300]);
+    sim.script_reads("cycles", vec![100]);
+
+    let ((), m) = sim
+        .builder()
+        .attach_to(vec![0])
+        .measure_group(vec!["instructions", "cycles"], || ())
+        .unwrap();
+
+    assert_eq!(m.get("instructions"), Some(300));
+    assert_eq!(m.get("cycles"), Some(100));
+    assert_eq!(m.value(), 300);
+    assert!(m
+        .to_string()
+        .starts_with("instructions: 300, cycles: 100 in "));
+}
+
+#[test]
+fn test_measure_reused_group() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![10, 25]);
+
+    let mut group = sim.builder().group(vec!["instructions"]).unwrap();
+
+    let (_, first) = group.measure(|| ()).unwrap();
+    let (_, second) = group.measure(|| ()).unwrap();
+    assert_eq!((first.value(), second.value()), (10, 25));
+}
+
+#[test]
+fn test_measure_panic() {
+    let sim = Simulated::default();
+    let builder = sim.builder().attach_to(vec![0]);
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        builder.measure("instructions", || panic!("boom"))
+    }));
+    assert!(result.is_err());
+
+    // The counter was stopped and released during unwinding.
+    let calls = sim.calls();
+    assert!(calls.contains(&Call::Stop(0)));
+    assert_eq!(calls.last(), Some(&Call::Release(0)));
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_measure_errors() {
+    let sim = Simulated::default();
+    let builder = sim.builder();
+
+    // The closure is not run if the counter cannot be started.
+    sim.fail_next(Op::Start, libc::ENXIO);
+    let mut ran = false;
+    let err = builder.measure("instructions", || ran = true).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::BadScope);
+    assert!(!ran);
+
+    let err = builder
+        .measure_group(Vec::<String>::new(), || ())
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+}
@@ -0,0 +1,216 @@
+use pmc::backend::*;
+use pmc::*;
+
+fn eval(formula: &str, values: &[(&str, f64)]) -> Option<f64> {
+    Metric::new("test", formula)
+        .expect("failed to parse formula")
+        .evaluate_with(|event| values.iter().find(|(e, _)| *e == event).map(|&(_, v)| v))
+}
+
+#[test]
+fn test_formula_evaluation() {
+    assert_eq!(eval("1 + 2 * 3", &[]), Some(7.0));
+    assert_eq!(eval("(1 + 2) * 3", &[]), Some(9.0));
+    assert_eq!(eval("10 - 4 - 3", &[]), Some(3.0));
+    assert_eq!(eval("12 / 3 / 2", &[]), Some(2.0));
+    assert_eq!(eval("-2 * -(3)", &[]), Some(6.0));
+    assert_eq!(eval("0.5 * 4", &[]), Some(2.0));
+    assert_eq!(eval("a / b", &[("a", 10.0), ("b", 4.0)]), Some(2.5));
+
+    // Hyphenated event names, and subtraction
+    assert_eq!(
+        eval(
+            "branch-misses / branches",
+            &[("branch-misses", 1.0), ("branches", 4.0)]
+        ),
+        Some(0.25)
+    );
+    assert_eq!(eval("a - b", &[("a", 10.0), ("b", 4.0)]), Some(6.0));
+
+    // Missing events and division by zero
+    assert_eq!(eval("a / b", &[("a", 10.0)]), None);
+    assert_eq!(eval("a / b", &[("a", 10.0), ("b", 0.0)]), None);
+}
+
+#[test]
+fn test_formula_events() {
+    let m = Metric::new("l1", "l1_miss / (l1_hit + l1_miss) * 100").unwrap();
+    assert_eq!(m.events(), vec!["l1_miss", "l1_hit"]);
+    assert_eq!(m.name(), "l1");
+    assert_eq!(m.to_string(), "l1 = l1_miss / (l1_hit + l1_miss) * 100");
+}
+
+#[test]
+fn test_formula_parse_errors() {
+    let cases = vec![
+        ("a /", 3, ""),
+        ("a / (b + c", 10, ""),
+        ("a b", 2, "b"),
+        ("a $ b", 2, "$"),
+        ("a / )", 4, ")"),
+        ("1.2.3", 0, "1.2.3"),
+    ];
+
+    for (formula, position, token) in cases {
+        let err = Metric::new("test", formula).expect_err("expected parse error");
+        assert_eq!(err.kind(), &ErrorKind::InvalidFormula);
+
+        let cause = err
+            .cause()
+            .and_then(|c| c.downcast_ref::<ParseError>())
+            .expect("expected ParseError cause");
+
+        assert_eq!(cause.position(), position, "{}", formula);
+        assert_eq!(cause.token(), token, "{}", formula);
+    }
+}
+
+#[test]
+fn test_standard_metrics() {
+    let set = MetricSet::standard();
+    let names: Vec<&str> = set.metrics().iter().map(|m| m.name()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "ipc",
+            "cpi",
+            "branch_mispredict_rate",
+            "l1_miss_ratio",
+            "l2_miss_ratio",
+            "llc_miss_ratio"
+        ]
+    );
+
+    // Shared events are only allocated once, and every event is generic.
+    let events = set.events();
+    assert_eq!(&events[..2], &["instructions", "cycles"]);
+    assert_eq!(events.len(), 10);
+    assert!(events.iter().all(|e| GenericEvent::from_name(e).is_some()));
+}
+
+#[test]
+fn test_metric_set_evaluation() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![300]);
+    sim.script_reads("cycles", vec![100]);
+    sim.script_reads("branch-misses", vec![5]);
+    sim.script_reads("branches", vec![0]);
+
+    let set = MetricSet::default()
+        .with(Metric::ipc())
+        .with(Metric::cpi())
+        .with(Metric::branch_mispredict_rate());
+
+    let mut mux = set
+        .allocate(&sim.builder(), 4)
+        .expect("failed to allocate metrics");
+
+    assert_eq!(
+        mux.labels(),
+        &["instructions", "cycles", "branch-misses", "branches"]
+    );
+
+    let handle = mux.start().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    let values = set.evaluate(&handle.read().unwrap().scaled());
+
+    assert_eq!(values.get("ipc"), Some(3.0));
+    assert_eq!(values.get("cpi"), Some(1.0 / 3.0));
+    assert_eq!(values.get("branch_mispredict_rate"), None);
+    assert_eq!(
+        values.to_string(),
+        "ipc: 3.0000, cpi: 0.3333, branch_mispredict_rate: n/a"
+    );
+}
+
+#[test]
+fn test_metric_set_multiplexed() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![300]);
+    sim.script_reads("cycles", vec![100]);
+
+    // The built-in metrics need more events than the counters available, so
+    // are rotated - events that have not run yet are not evaluated.
+    let set = MetricSet::standard();
+    let mut mux = set
+        .allocate(&sim.builder().attach_to(vec![0]), 2)
+        .expect("failed to allocate metrics");
+    assert_eq!(mux.labels().len(), 10);
+
+    let handle = mux.start().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    let values = set.evaluate(&handle.read().unwrap().scaled());
+
+    assert_eq!(values.get("ipc"), Some(3.0));
+    assert_eq!(values.get("branch_mispredict_rate"), None);
+    assert_eq!(values.get("llc_miss_ratio"), None);
+}
+
+#[test]
+fn test_metric_set_unmapped_events() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![300]);
+    sim.script_reads("cycles", vec![100]);
+
+    // Metrics using events without a native equivalent are not counted.
+    let set = MetricSet::default()
+        .with(Metric::l2_miss_ratio())
+        .with(Metric::ipc());
+    sim.fail_next(Op::GenericEvent, libc::ENOENT);
+
+    let mut mux = set
+        .allocate(&sim.builder(), 4)
+        .expect("failed to allocate metrics");
+    assert_eq!(mux.labels(), &["L2-loads", "instructions", "cycles"]);
+
+    let handle = mux.start().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    let values = set.evaluate(&handle.read().unwrap().scaled());
+    assert_eq!(values.get("l2_miss_ratio"), None);
+    assert_eq!(values.get("ipc"), Some(3.0));
+    handle.stop();
+    drop(mux);
+
+    // Nor are those whose native event cannot be allocated.
+    sim.fail_next(Op::Allocate, libc::ENOENT);
+    let mux = set
+        .allocate(&sim.builder(), 4)
+        .expect("failed to allocate metrics");
+    assert_eq!(mux.labels(), &["L2-loads", "instructions", "cycles"]);
+    assert_eq!(sim.allocated(), 3);
+    drop(mux);
+
+    // Unless no event can be counted.
+    sim.fail_next(Op::GenericEvent, libc::ENOENT);
+    sim.fail_next(Op::GenericEvent, libc::ENOENT);
+    let err = MetricSet::default()
+        .with(Metric::l2_miss_ratio())
+        .allocate(&sim.builder(), 4)
+        .expect_err("expected unmapped events");
+    assert_eq!(err.kind(), &ErrorKind::UnmappedEvent);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_perf_metric_set() {
+    // Metrics over generic events resolve to events perf can count, apart
+    // from the L2 events perf has no name for.
+    let set = MetricSet::default()
+        .with(Metric::new("faults_per_ms", "page-faults / task-clock * 1000000").unwrap())
+        .with(Metric::l2_miss_ratio());
+
+    let mut mux = match set.allocate(&CounterBuilder::default().attach_to(vec![0]), 4) {
+        Ok(v) => v,
+        Err(e) if matches!(e.kind(), ErrorKind::Forbidden | ErrorKind::Unsupported) => return,
+        Err(e) => panic!("failed to allocate metrics: {}", e),
+    };
+    assert_eq!(mux.labels(), &["page-faults", "task-clock"]);
+
+    let handle = mux.start().unwrap();
+    let buf = vec![1u8; 4 * 1024 * 1024];
+    assert_eq!(buf.iter().map(|&v| v as usize).sum::<usize>(), buf.len());
+    let values = set.evaluate(&handle.read().unwrap().scaled());
+
+    assert!(values.get("faults_per_ms").is_some());
+    assert_eq!(values.get("l2_miss_ratio"), None);
+}
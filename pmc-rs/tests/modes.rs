@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_mode_filters() {
+    let sim = Simulated::default();
+
+    let builder = sim.builder().attach_to(vec![42]);
+
+    builder.allocate("a").unwrap();
+    builder.clone().count_kernel(false).allocate("b").unwrap();
+    builder
+        .clone()
+        .count_user(false)
+        .exclude_hypervisor(true)
+        .exclude_idle(true)
+        .allocate("c")
+        .unwrap();
+
+    let specs = sim.allocations();
+    assert_eq!(specs.len(), 3);
+
+    assert!(!specs[0].exclude_user && !specs[0].exclude_kernel);
+    assert!(!specs[0].exclude_hv && !specs[0].exclude_idle);
+
+    assert!(!specs[1].exclude_user && specs[1].exclude_kernel);
+
+    assert!(specs[2].exclude_user && !specs[2].exclude_kernel);
+    assert!(specs[2].exclude_hv && specs[2].exclude_idle);
+}
+
+#[test]
+fn test_mode_qualifier_conflicts() {
+    let sim = Simulated::default();
+
+    // Qualifiers asking to count an excluded mode are rejected.
+    let cases = vec![
+        (sim.builder().count_kernel(false), "ev,os", 3, "os"),
+        (sim.builder().count_user(false), "ev,usr", 3, "usr"),
+        (sim.builder().count_user(false), "ev,edge,usr,os", 8, "usr"),
+    ];
+    for (builder, spec, pos, token) in cases {
+        let err = builder.allocate(spec).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec, "{}", spec);
+
+        let cause = err
+            .cause()
+            .and_then(|c| c.downcast_ref::<ParseError>())
+            .expect("missing parse error");
+        assert_eq!(cause.position(), pos, "{}", spec);
+        assert_eq!(cause.token(), token, "{}", spec);
+    }
+    assert!(sim.allocations().is_empty());
+
+    // Excluding the mode the qualifier already excludes is allowed.
+    sim.builder().count_user(false).allocate("ev,os").unwrap();
+    sim.builder()
+        .count_kernel(false)
+        .allocate("ev,usr")
+        .unwrap();
+    assert_eq!(sim.allocations().len(), 2);
+}
+
+#[test]
+fn test_split_counter() {
+    let sim = Simulated::default();
+    sim.script_reads("inst_retired.any", vec![100, 20]);
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![42])
+        .count_user(false)
+        .allocate_split("inst_retired.any")
+        .expect("failed to allocate split counter");
+
+    let specs = sim.allocations();
+    assert_eq!(specs.len(), 2);
+    assert!(!specs[0].exclude_user && specs[0].exclude_kernel);
+    assert!(specs[1].exclude_user && !specs[1].exclude_kernel);
+
+    counter.start().expect("failed to start counter").stop();
+
+    let r = counter.read().expect("failed to read counter");
+    assert_eq!(r.user(), 100);
+    assert_eq!(r.kernel(), 20);
+    assert_eq!(r.total(), 120);
+    assert_eq!(r.to_string(), "user: 100, kernel: 20");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_split_perf() {
+    let mut counter = CounterBuilder::default()
+        .set_backend(Arc::new(Perf::default()))
+        .attach_to(vec![0])
+        .allocate_split(SoftwareEvent::PageFaults)
+        .expect("failed to allocate split counter");
+
+    let handle = counter.start().expect("failed to start counter");
+    let v = vec![1u8; 4 * 1024 * 1024];
+    drop(v);
+    handle.stop();
+
+    // Page faults are counted in kernel mode.
+    let r = counter.read().unwrap();
+    assert!(r.total() > 0);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_mode_qualifiers_perf() {
+    let builder = CounterBuilder::default().set_backend(Arc::new(Perf::default()));
+
+    let err = builder
+        .clone()
+        .count_kernel(false)
+        .allocate("cpu-clock,os")
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+
+    builder
+        .count_user(false)
+        .allocate("cpu-clock,os")
+        .expect("failed to allocate kernel-mode counter");
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use pmc::backend::*;
+use pmc::*;
+
+fn allocated_events(sim: &Simulated) -> Vec<String> {
+    sim.allocations().into_iter().map(|s| s.event).collect()
+}
+
+#[test]
+fn test_multiplex_userspace_rotation() {
+    let sim = Simulated::default();
+    sim.script_reads("a", vec![10]);
+    sim.script_reads("b", vec![20]);
+    sim.script_reads("c", vec![30]);
+
+    // Only two PMCs are available.
+    sim.set_max_counters(2);
+    let err = sim
+        .builder()
+        .attach_to(vec![0])
+        .group(vec!["a", "b", "c"])
+        .expect_err("expected to run out of PMCs");
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+
+    let mut mux = sim
+        .builder()
+        .attach_to(vec![0])
+        .multiplex(vec!["a", "b", "c"], 2)
+        .expect("failed to allocate multiplexer");
+
+    assert_eq!(mux.labels(), &["a", "b", "c"]);
+    assert!(!mux.is_kernel_multiplexed());
+    assert_eq!(sim.allocated(), 2);
+
+    // Rotate by hand only.
+    mux.set_interval(Duration::from_secs(3600));
+
+    let handle = mux.start().expect("failed to start multiplexer");
+    std::thread::sleep(Duration::from_millis(20));
+
+    // Each slice is allocated as it is rotated in, once the previous slice is
+    // released.
+    sim.clear_calls();
+    handle.rotate().expect("failed to rotate");
+    assert_eq!(allocated_events(&sim), vec!["c"]);
+    let calls = sim.calls();
+    assert_eq!(calls.iter().filter(|c| c.op() == Op::Release).count(), 2);
+    assert_eq!(sim.allocated(), 1);
+
+    std::thread::sleep(Duration::from_millis(20));
+
+    sim.clear_calls();
+    handle.rotate().expect("failed to rotate");
+    assert_eq!(allocated_events(&sim), vec!["a", "b"]);
+    assert_eq!(sim.allocated(), 2);
+
+    handle.stop();
+
+    let r = mux.read().expect("failed to read multiplexer");
+
+    // a and b ran for the first and last slice, c for the second.
+    let a = r.get("a").unwrap();
+    assert_eq!(a.raw(), 10);
+    assert!(a.time_running() >= Duration::from_millis(20));
+    assert!(a.time_running() < a.time_enabled());
+    assert!(a.confidence() > 0.0 && a.confidence() < 1.0);
+    assert!(a.scaled() >= 10);
+
+    let c = r.get("c").unwrap();
+    assert_eq!(c.raw(), 30);
+    assert!(c.time_running() >= Duration::from_millis(20));
+    assert_eq!(c.time_enabled(), a.time_enabled());
+    assert!(c.scaled() > 30);
+
+    assert!(r.get("missing").is_none());
+    assert_eq!(r.scaled().len(), 3);
+    assert_eq!(r.scaled().get("c"), Some(c.scaled()));
+
+    drop(mux);
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_multiplex_read_errors() {
+    let sim = Simulated::default();
+    sim.script_reads("a", vec![10]);
+    sim.script_reads("b", vec![20]);
+
+    let mut mux = sim
+        .builder()
+        .attach_to(vec![0])
+        .multiplex(vec!["a", "b", "c"], 2)
+        .expect("failed to allocate multiplexer");
+    mux.set_interval(Duration::from_secs(3600));
+
+    let handle = mux.start().expect("failed to start multiplexer");
+    std::thread::sleep(Duration::from_millis(20));
+
+    // The slice of a counter that cannot be read when rotated out is not
+    // counted as running.
+    sim.fail_next(Op::Read, libc::EINVAL);
+    handle.rotate().expect("failed to rotate");
+    handle.stop();
+
+    let r = mux.read().expect("failed to read multiplexer");
+    let a = r.get("a").unwrap();
+    assert_eq!(a.raw(), 0);
+    assert_eq!(a.time_running(), Duration::default());
+    assert_eq!(a.confidence(), 0.0);
+
+    let b = r.get("b").unwrap();
+    assert_eq!(b.raw(), 20);
+    assert!(b.time_running() >= Duration::from_millis(20));
+}
+
+#[test]
+fn test_multiplex_rotation_errors() {
+    let sim = Simulated::default();
+
+    let mut mux = sim
+        .builder()
+        .attach_to(vec![0])
+        .multiplex(vec!["a", "b", "c"], 2)
+        .expect("failed to allocate multiplexer");
+    mux.set_interval(Duration::from_millis(1));
+
+    // Errors rotating in the background are reported by the next read.
+    sim.fail_next(Op::Allocate, libc::EINVAL);
+    let handle = mux.start().expect("failed to start multiplexer");
+    std::thread::sleep(Duration::from_millis(50));
+
+    let err = handle.read().expect_err("expected a rotation error");
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+    handle.read().expect("failed to read multiplexer");
+}
+
+#[test]
+fn test_multiplex_invalid_slice() {
+    let sim = Simulated::default();
+
+    // Every slice is checked when allocating.
+    let err = sim
+        .builder()
+        .attach_to(vec![0])
+        .multiplex(vec!["a", "b", "bad\0name"], 2)
+        .expect_err("expected to fail allocating multiplexer");
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_multiplex_single_slice() {
+    let sim = Simulated::default();
+    sim.script_reads("a", vec![10]);
+
+    let mut mux = sim
+        .builder()
+        .attach_to(vec![0])
+        .multiplex(vec!["a", "b"], 4)
+        .expect("failed to allocate multiplexer");
+
+    mux.start().expect("failed to start multiplexer").stop();
+
+    // Everything fits at once, so every event counted all the time.
+    let r = mux.read().expect("failed to read multiplexer");
+    for v in r.iter() {
+        assert_eq!(v.confidence(), 1.0);
+        assert_eq!(v.scaled(), v.raw());
+    }
+    assert_eq!(r.get("a").unwrap().raw(), 10);
+}
+
+#[test]
+fn test_multiplex_empty() {
+    let err = Simulated::default()
+        .builder()
+        .multiplex(Vec::<String>::new(), 2)
+        .unwrap_err();
+
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_multiplex_perf() {
+    let mut mux = CounterBuilder::default()
+        .set_backend(Arc::new(Perf::default()))
+        .attach_to(vec![0])
+        .multiplex(
+            vec![SoftwareEvent::TaskClock, SoftwareEvent::ContextSwitches],
+            1,
+        )
+        .expect("failed to allocate multiplexer");
+
+    assert!(mux.is_kernel_multiplexed());
+
+    let handle = mux.start().expect("failed to start multiplexer");
+    std::thread::sleep(Duration::from_millis(10));
+    handle.stop();
+
+    // Software events are never multiplexed by the kernel.
+    let r = mux.read().expect("failed to read multiplexer");
+    let task_clock = r.get("task-clock").unwrap();
+    assert!(task_clock.raw() > 0);
+    assert!(task_clock.time_enabled() > Duration::from_secs(0));
+    assert_eq!(task_clock.confidence(), 1.0);
+}
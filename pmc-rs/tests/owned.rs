@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::thread;
+
+use pmc::backend::*;
+use pmc::*;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_send_sync() {
+    assert_send_sync::<Counter>();
+    assert_send_sync::<OwnedRunning>();
+}
+
+#[test]
+fn test_start_owned() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![10, 20, 30]);
+
+    let counter = sim
+        .builder()
+        .attach_to(vec![42])
+        .allocate("instructions")
+        .unwrap();
+
+    let running = Arc::new(counter.start_owned().unwrap());
+
+    // Read from another thread while running.
+    let value = {
+        let running = Arc::clone(&running);
+        thread::spawn(move || running.read().unwrap())
+            .join()
+            .unwrap()
+    };
+    assert_eq!(value, 10);
+    assert_eq!(running.read().unwrap(), 20);
+    assert_eq!(running.to_string(), "30");
+
+    // Stopping returns the still allocated counter.
+    let counter = Arc::try_unwrap(running).unwrap().stop();
+    assert_eq!(sim.calls().last(), Some(&Call::Stop(0)));
+    assert_eq!(sim.allocated(), 1);
+    assert_eq!(counter.read().unwrap(), 30);
+
+    drop(counter);
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_owned_moved_to_thread() {
+    let sim = Simulated::default();
+
+    let counter = sim.builder().allocate("instructions").unwrap();
+    let mut running = counter.start_owned().unwrap();
+    assert_eq!(running.set(5).unwrap(), 0);
+
+    let counter = thread::spawn(move || running.stop()).join().unwrap();
+    assert_eq!(counter.read().unwrap(), 5);
+}
+
+#[test]
+fn test_owned_drop() {
+    let sim = Simulated::default();
+
+    let running = sim
+        .builder()
+        .allocate("instructions")
+        .unwrap()
+        .start_owned()
+        .unwrap();
+    drop(running);
+
+    // Dropping the handle stops and releases the counter.
+    assert!(sim.calls().ends_with(&[Call::Stop(0), Call::Release(0)]));
+}
+
+#[test]
+fn test_start_owned_error() {
+    let sim = Simulated::default();
+    let counter = sim.builder().allocate("instructions").unwrap();
+
+    sim.fail_next(Op::Start, libc::ENXIO);
+    let err = counter.start_owned().unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::BadScope);
+
+    // The counter is released.
+    assert_eq!(sim.allocated(), 0);
+}
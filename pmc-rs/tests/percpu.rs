@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_per_cpu_allocation() {
+    let sim = Simulated::default();
+    sim.fail_next(Op::Allocate, libc::EPERM);
+
+    let counter = sim
+        .builder()
+        .per_cpu_on("inst_retired.any", vec![0, 1, 2])
+        .expect("failed to allocate per-CPU counter");
+
+    assert_eq!(counter.cpus(), vec![1, 2]);
+    assert_eq!(counter.failed().len(), 1);
+    assert_eq!(counter.failed()[0].0, 0);
+    assert_eq!(counter.failed()[0].1.kind(), &ErrorKind::Forbidden);
+
+    let specs = sim.allocations();
+
+    assert_eq!(specs.len(), 3);
+    for (spec, cpu) in specs.iter().zip(0..) {
+        assert_eq!(spec.cpu, cpu);
+        assert_eq!(spec.mode, Mode::SystemCounting);
+    }
+}
+
+#[test]
+fn test_per_cpu_read() {
+    let sim = Simulated::default();
+    sim.script_reads("inst_retired.any", vec![10, 20, 30, 11, 21, 31]);
+
+    let mut counter = sim
+        .builder()
+        .per_cpu_on("inst_retired.any", vec![0, 1, 3])
+        .expect("failed to allocate per-CPU counter");
+
+    sim.clear_calls();
+    let handle = counter.start().expect("failed to start counter");
+    assert_eq!(handle.read_total().unwrap(), 60);
+    handle.stop();
+
+    assert_eq!(
+        counter.read_per_cpu().unwrap(),
+        vec![(0, 11), (1, 21), (3, 31)]
+    );
+
+    assert!(sim.calls().contains(&Call::Start(2)));
+    assert!(sim.calls().contains(&Call::Stop(2)));
+}
+
+#[test]
+fn test_per_cpu_errors() {
+    // No CPU could be allocated
+    let sim = Simulated::default();
+    sim.fail_next(Op::Allocate, libc::EINVAL);
+    let err = sim
+        .builder()
+        .per_cpu_on("inst_retired.any", vec![0])
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+
+    // Per-CPU counters are system-scoped
+    let err = Simulated::default()
+        .builder()
+        .attach_to(vec![0])
+        .per_cpu("inst_retired.any")
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::BadScope);
+}
+
+#[test]
+fn test_default_cpu() {
+    let sim = Simulated::default();
+
+    // System-scoped counters default to CPU 0, process-scoped to any CPU.
+    let _system = sim.builder().allocate("inst_retired.any").unwrap();
+    let _process = sim
+        .builder()
+        .attach_to(vec![0])
+        .allocate("inst_retired.any")
+        .unwrap();
+
+    let cpus: Vec<(Mode, i32)> = sim
+        .allocations()
+        .iter()
+        .map(|spec| (spec.mode, spec.cpu))
+        .collect();
+
+    assert_eq!(
+        cpus,
+        vec![(Mode::SystemCounting, 0), (Mode::ProcessCounting, CPU_ANY)]
+    );
+}
+
+#[test]
+fn test_online_cpus() {
+    let cpus = online_cpus().expect("failed to list online CPUs");
+    assert!(!cpus.is_empty());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_per_cpu_perf() {
+    let mut counter = CounterBuilder::default()
+        .set_backend(Arc::new(Perf::default()))
+        .per_cpu(SoftwareEvent::CpuClock)
+        .expect("failed to allocate per-CPU counter");
+
+    assert_eq!(
+        counter.cpus().len() + counter.failed().len(),
+        online_cpus().unwrap().len()
+    );
+
+    let handle = counter.start().expect("failed to start counter");
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    handle.stop();
+
+    let total = counter.read_total().unwrap();
+    let per_cpu: u64 = counter.read_per_cpu().unwrap().iter().map(|(_, v)| v).sum();
+    assert!(total > 0);
+    assert_eq!(total, per_cpu);
+}
@@ -0,0 +1,52 @@
+// These tests exercise the perf_event_open(2) interface through the default
+// Linux backend.
+#![cfg(target_os = "linux")]
+
+use pmc::*;
+
+#[test]
+fn test_counter_bad_name() {
+    let err = CounterBuilder::default()
+        .attach_to(vec![0])
+        .allocate("does.not.exist")
+        .expect_err("expected to fail allocating PMC");
+
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+}
+
+#[test]
+fn test_system_counter_bad_name() {
+    let err = CounterBuilder::default()
+        .allocate("does.not.exist")
+        .expect_err("expected to fail allocating PMC");
+
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+}
+
+#[test]
+fn test_null_in_counter_name() {
+    let err = CounterBuilder::default()
+        .attach_to(vec![0])
+        .allocate("instru\0ctions")
+        .expect_err("expected to fail allocating PMC");
+
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+}
+
+#[test]
+fn test_mode_qualifiers() {
+    let mut counter = CounterBuilder::default()
+        .attach_to(vec![0])
+        .allocate(EventSpec::new("task-clock").usr())
+        .expect("failed to allocate PMC");
+
+    counter.start().expect("failed to start counter").stop();
+    counter.read().expect("failed to read counter");
+
+    let err = CounterBuilder::default()
+        .attach_to(vec![0])
+        .allocate(EventSpec::new("task-clock").cmask(1))
+        .expect_err("expected to fail allocating PMC");
+
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+}
@@ -0,0 +1,249 @@
+use pmc::backend::*;
+use pmc::*;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/perfmon");
+
+#[test]
+fn test_load_dir() {
+    let table = EventTable::load(format!("{}/skylake", FIXTURE)).expect("failed to load table");
+
+    // The uncore event is skipped.
+    let names: Vec<&str> = table.events().iter().map(|e| e.name()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "INST_RETIRED.ANY",
+            "INST_RETIRED.ANY_P",
+            "CPU_CLK_UNHALTED.THREAD_P",
+            "UOPS_ISSUED.STALL_CYCLES",
+            "CPU_CLK_UNHALTED.THREAD_P_ANY",
+            "MACHINE_CLEARS.COUNT",
+        ]
+    );
+
+    // The metric using perf-specific syntax is skipped.
+    assert_eq!(table.metrics().len(), 1);
+    assert_eq!(table.metrics()[0].name(), "IPC");
+    assert_eq!(
+        table.metrics()[0].events(),
+        vec!["INST_RETIRED.ANY", "CPU_CLK_UNHALTED.THREAD"]
+    );
+}
+
+#[test]
+fn test_encoding() {
+    let table = EventTable::load(format!("{}/skylake", FIXTURE)).unwrap();
+
+    let fixed = table.get("inst_retired.any").unwrap();
+    assert_eq!((fixed.code(), fixed.umask()), (0, 1));
+    assert_eq!(fixed.config(), 0x100);
+
+    let stalls = table.get("UOPS_ISSUED.STALL_CYCLES").unwrap();
+    assert_eq!(stalls.cmask(), 1);
+    assert!(stalls.inv());
+    assert!(!stalls.edge());
+    assert_eq!(stalls.config(), 0x0180_010e);
+
+    let any = table.get("CPU_CLK_UNHALTED.THREAD_P_ANY").unwrap();
+    assert!(any.any_thread());
+    assert_eq!(any.config(), 0x0020_003c);
+
+    let clears = table.get("MACHINE_CLEARS.COUNT").unwrap();
+    assert!(clears.edge());
+    assert_eq!(clears.config(), 0x0104_01c3);
+    assert_eq!(
+        clears.description(),
+        Some("Number of machine clears (nukes) of any type.")
+    );
+}
+
+#[test]
+fn test_header_layout() {
+    let table = EventTable::load(format!("{}/SKX/events/skylakex_core.json", FIXTURE)).unwrap();
+
+    // The offcore response event is programmed through an MSR, and skipped.
+    let names: Vec<&str> = table.events().iter().map(|e| e.name()).collect();
+    assert_eq!(names, vec!["BR_MISP_RETIRED.ALL_BRANCHES"]);
+
+    // The brief description is preferred.
+    assert_eq!(
+        table
+            .get("BR_MISP_RETIRED.ALL_BRANCHES")
+            .unwrap()
+            .description(),
+        Some("All mispredicted macro branch instructions retired.")
+    );
+}
+
+#[test]
+fn test_paired_event_code() {
+    // Events with a pair of codes use the first.
+    let table = EventTable::parse(
+        r#"[{ "EventName": "A", "EventCode": "0xB7, 0xBB", "UMask": "0x01", "MSRIndex": "0x00" }]"#,
+    )
+    .unwrap();
+    assert_eq!(table.get("A").unwrap().code(), 0xb7);
+}
+
+#[test]
+fn test_extended_event_code() {
+    let table = EventTable::load(format!("{}/amdzen2", FIXTURE)).unwrap();
+
+    let fused = table.get("ex_ret_fus_brnch_inst").unwrap();
+    assert_eq!(fused.code(), 0x1d0);
+    assert_eq!(fused.config(), 0x1_0000_00d0);
+
+    let fills = table.get("ls_sw_pf_dc_fills.mem_io_local").unwrap();
+    assert_eq!(fills.config(), 0x4059);
+}
+
+#[test]
+fn test_for_cpu_id() {
+    let skx = EventTable::for_cpu_id(FIXTURE, &CpuId::new("GenuineIntel", 6, 0x55, 4)).unwrap();
+    assert!(skx.get("BR_MISP_RETIRED.ALL_BRANCHES").is_some());
+
+    // Steppings outside the pattern do not match.
+    let err = EventTable::for_cpu_id(FIXTURE, &CpuId::new("GenuineIntel", 6, 0x55, 7)).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::Unsupported);
+
+    // Patterns without a stepping ignore it.
+    let skl = EventTable::for_cpu_id(FIXTURE, &CpuId::new("GenuineIntel", 6, 0x5e, 3)).unwrap();
+    assert!(skl.get("MACHINE_CLEARS.COUNT").is_some());
+
+    let zen2 = EventTable::for_cpu_id(FIXTURE, &CpuId::new("AuthenticAMD", 23, 0x31, 0)).unwrap();
+    assert!(zen2.get("ex_ret_instr").is_some());
+
+    let err = EventTable::for_cpu_id(FIXTURE, &CpuId::new("GenuineIntel", 6, 0x3c, 3)).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_mapfile_syntax() {
+    let dir = std::env::temp_dir().join(format!("pmc-mapfile-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("core.json"), "[]").unwrap();
+
+    let cpu = CpuId::new("GenuineIntel", 6, 0x55, 4);
+    let cases = [
+        ("GenuineIntel-6-55", true),
+        ("GenuineIntel-6-(4E|55)", true),
+        ("GenuineIntel-6-5[0-9A-F]", true),
+        ("GenuineIntel-6-[[:xdigit:]]+-4", true),
+        ("GenuineIntel-6-5", false),
+        ("GenuineIntel-6-55-[123]", false),
+        // Syntax not used by mapfiles never matches.
+        ("GenuineIntel-6-5.", false),
+        ("GenuineIntel-6-55?", false),
+        ("GenuineIntel-6-\\d+", false),
+        ("^GenuineIntel-6-55$", false),
+        ("GenuineIntel-6-[^0]+", false),
+    ];
+
+    for &(pattern, matches) in &cases {
+        let mapfile = format!("{},v1,core.json,core\n", pattern);
+        std::fs::write(dir.join("mapfile.csv"), mapfile).unwrap();
+        assert_eq!(
+            EventTable::for_cpu_id(&dir, &cpu).is_ok(),
+            matches,
+            "{}",
+            pattern
+        );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_spec() {
+    let table = EventTable::load(format!("{}/skylake", FIXTURE)).unwrap();
+
+    let spec = table.spec("INST_RETIRED.ANY_P").unwrap();
+    assert_eq!(spec.to_string(), "rc0");
+
+    let err = table.spec("bananas").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+}
+
+#[test]
+fn test_event_info() {
+    let table = EventTable::load(format!("{}/amdzen2", FIXTURE)).unwrap();
+
+    let info = EventInfo::from(&table.events()[0]);
+    assert_eq!(info.name, "ex_ret_instr");
+    assert_eq!(info.pmu, "cpu");
+    assert_eq!(info.description.as_deref(), Some("Retired Instructions."));
+}
+
+#[test]
+fn test_parse_errors() {
+    let cases = [
+        ("[{\"EventName\": \"A\",", "expected '\"'", 19, ""),
+        ("{\"Header\": {}}", "expected an array of events", 0, "{"),
+        (
+            "[{\"EventName\": \"A\", \"EventCode\": \"0xzz\"}]",
+            "invalid EventCode",
+            33,
+            "0xzz",
+        ),
+        (
+            "[{\"EventName\": \"A\", \"EventCode\": 1, \"UMask\": \"0x100\"}]",
+            "invalid UMask",
+            45,
+            "0x100",
+        ),
+        (
+            "[{\"EventName\": \"A\" \"EventCode\": 1}]",
+            "expected ',' or '}'",
+            19,
+            "\"",
+        ),
+        ("[] []", "unexpected trailing characters", 3, "["),
+    ];
+
+    // Deeply nested input is rejected rather than overflowing the stack.
+    let nested = "[".repeat(100_000);
+    let cases: Vec<_> = cases
+        .iter()
+        .cloned()
+        .chain(Some((nested.as_str(), "nesting too deep", 64, "[")))
+        .collect();
+
+    for &(input, message, position, token) in &cases {
+        let err = EventTable::parse(input).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidEventTable, "{}", input);
+
+        let cause = err
+            .cause()
+            .and_then(|c| c.downcast_ref::<ParseError>())
+            .expect("missing parse error");
+        assert_eq!(cause.message(), message, "{}", input);
+        assert_eq!(cause.position(), position, "{}", input);
+        assert_eq!(cause.token(), token, "{}", input);
+    }
+}
+
+#[test]
+fn test_load_missing() {
+    let err = EventTable::load("/does/not/exist.json").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::Unknown);
+}
+
+#[test]
+fn test_current_cpu() {
+    if let Some(cpu) = CpuId::current() {
+        assert!(!cpu.vendor().is_empty());
+        assert!(cpu.family() > 0);
+    }
+}
+
+#[test]
+fn test_allocate() {
+    let table = EventTable::load(format!("{}/skylake", FIXTURE)).unwrap();
+    let spec = table.spec("INST_RETIRED.ANY_P").unwrap();
+
+    let sim = Simulated::default();
+    sim.builder().allocate(&spec).unwrap();
+
+    let events: Vec<String> = sim.allocations().into_iter().map(|s| s.event).collect();
+    assert_eq!(events, vec![spec.to_string()]);
+}
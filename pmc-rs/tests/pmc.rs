@@ -1,10 +1,14 @@
+// These tests exercise the hwpmc kernel module through the default libpmc
+// backend.
+#![cfg(target_os = "freebsd")]
+
 use pmc::*;
 
 #[test]
 fn test_process_counter() {
     let mut counter = CounterBuilder::default()
         .attach_to(vec![0])
-        .allocate("ex_ret_instr")
+        .allocate_generic(GenericEvent::Instructions)
         .expect("failed to allocate PMC");
 
     read_counter(&mut counter);
@@ -13,7 +17,7 @@ fn test_process_counter() {
 #[test]
 fn test_system_counter() {
     let mut counter = CounterBuilder::default()
-        .allocate("ex_ret_instr")
+        .allocate_generic(GenericEvent::Instructions)
         .expect("failed to allocate PMC");
 
     read_counter(&mut counter);
@@ -23,7 +27,7 @@ fn test_system_counter() {
 fn test_set_counter() {
     let mut counter = CounterBuilder::default()
         .attach_to(vec![0])
-        .allocate("ex_ret_instr")
+        .allocate_generic(GenericEvent::Instructions)
         .expect("failed to allocate PMC");
 
     counter.set(42).expect("failed to set counter");
@@ -0,0 +1,163 @@
+use pmc::*;
+
+type Regs = (u32, u32, u32, u32);
+
+/// Split a 12 byte vendor string into the registers `CPUID` returns it in.
+fn vendor_regs(vendor: &str) -> [u32; 3] {
+    let b = vendor.as_bytes();
+    let reg = |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+    [reg(0), reg(4), reg(8)]
+}
+
+/// A fake `CPUID` returning `leaves`, and zeros for any other leaf.
+fn fake_cpuid(leaves: Vec<(u32, Regs)>) -> impl Fn(u32, u32) -> Regs {
+    move |leaf, _| {
+        leaves
+            .iter()
+            .find(|(l, _)| *l == leaf)
+            .map(|(_, r)| *r)
+            .unwrap_or_default()
+    }
+}
+
+fn leaf0(max_leaf: u32, vendor: &str) -> (u32, Regs) {
+    // The vendor string is split over EBX, EDX, ECX - in that order.
+    let [a, b, c] = vendor_regs(vendor);
+    (0, (max_leaf, a, c, b))
+}
+
+fn hypervisor_leaf(vendor: &str) -> (u32, Regs) {
+    let [a, b, c] = vendor_regs(vendor);
+    (0x4000_0000, (0x4000_0001, a, b, c))
+}
+
+#[test]
+fn test_intel() {
+    let info = PmuInfo::from_cpuid(fake_cpuid(vec![
+        leaf0(0x16, "GenuineIntel"),
+        // Family 6, model 0x55, stepping 4.
+        (1, (0x0005_0654, 0, 0, 0)),
+        // Version 4, 4 x 48-bit counters, 7 events with LLC misses (bit 4)
+        // unavailable, 3 x 48-bit fixed counters.
+        (0xa, (0x0730_0404, 0b1_0000, 0, 0x0603)),
+    ]))
+    .unwrap();
+
+    assert_eq!(info.cpu(), &CpuId::new("GenuineIntel", 6, 0x55, 4));
+    assert_eq!(info.version(), 4);
+    assert_eq!(info.gp_counters(), 4);
+    assert_eq!(info.gp_counter_width(), 48);
+    assert_eq!(info.fixed_counters(), 3);
+    assert_eq!(info.fixed_counter_width(), 48);
+
+    assert_eq!(
+        info.arch_events(),
+        &[
+            ArchEvent::CoreCycles,
+            ArchEvent::InstructionsRetired,
+            ArchEvent::ReferenceCycles,
+            ArchEvent::LlcReferences,
+            ArchEvent::BranchInstructionsRetired,
+            ArchEvent::BranchMissesRetired,
+        ]
+    );
+    assert!(!info.supports(ArchEvent::LlcMisses));
+    // Only 7 events are enumerated.
+    assert!(!info.supports(ArchEvent::TopdownSlots));
+
+    assert!(!info.is_virtualised());
+    assert_eq!(info.hypervisor_vendor(), None);
+    assert_eq!(
+        info.to_string(),
+        "GenuineIntel-6-55-4: 4 x 48-bit general-purpose, 3 x 48-bit fixed counters"
+    );
+}
+
+#[test]
+fn test_intel_without_leaf_0xa() {
+    let info = PmuInfo::from_cpuid(fake_cpuid(vec![
+        leaf0(0x9, "GenuineIntel"),
+        (1, (0x0005_0654, 0, 0, 0)),
+        (0xa, (0x0730_0404, 0, 0, 0x0603)),
+    ]))
+    .unwrap();
+
+    assert_eq!(info.version(), 0);
+    assert_eq!(info.gp_counters(), 0);
+    assert!(info.arch_events().is_empty());
+}
+
+#[test]
+fn test_hypervisor_without_pmu() {
+    let info = PmuInfo::from_cpuid(fake_cpuid(vec![
+        leaf0(0x16, "GenuineIntel"),
+        (1, (0x0005_0654, 0, 1 << 31, 0)),
+        hypervisor_leaf("KVMKVMKVM\0\0\0"),
+    ]))
+    .unwrap();
+
+    assert!(info.is_virtualised());
+    assert_eq!(info.hypervisor_vendor(), Some("KVMKVMKVM"));
+    assert_eq!(info.gp_counters(), 0);
+    assert!(info.to_string().ends_with("(hypervisor: KVMKVMKVM)"));
+}
+
+#[test]
+fn test_amd() {
+    let zen2 = |ext: Vec<(u32, Regs)>| {
+        let mut leaves = vec![
+            leaf0(0x10, "AuthenticAMD"),
+            // Family 0x17, model 0x31, stepping 0.
+            (1, (0x0083_0f10, 0, 0, 0)),
+        ];
+        leaves.extend(ext);
+        PmuInfo::from_cpuid(fake_cpuid(leaves)).unwrap()
+    };
+
+    // Legacy counters only.
+    let info = zen2(vec![]);
+    assert_eq!(info.cpu(), &CpuId::new("AuthenticAMD", 23, 0x31, 0));
+    assert_eq!(info.gp_counters(), 4);
+
+    // PerfCtrExtCore
+    let info = zen2(vec![
+        (0x8000_0000, (0x8000_0020, 0, 0, 0)),
+        (0x8000_0001, (0, 0, 1 << 23, 0)),
+    ]);
+    assert_eq!(info.version(), 1);
+    assert_eq!(info.gp_counters(), 6);
+    assert_eq!(info.gp_counter_width(), 48);
+    assert_eq!(info.fixed_counters(), 0);
+    assert!(info.arch_events().is_empty());
+
+    // PerfMonV2
+    let info = zen2(vec![
+        (0x8000_0000, (0x8000_0022, 0, 0, 0)),
+        (0x8000_0001, (0, 0, 1 << 23, 0)),
+        (0x8000_0022, (1, 5, 0, 0)),
+    ]);
+    assert_eq!(info.version(), 2);
+    assert_eq!(info.gp_counters(), 5);
+}
+
+#[test]
+fn test_no_leaf_1() {
+    assert!(PmuInfo::from_cpuid(fake_cpuid(vec![leaf0(0, "GenuineIntel")])).is_none());
+}
+
+#[test]
+fn test_arch_event_generic() {
+    assert_eq!(
+        ArchEvent::InstructionsRetired.generic(),
+        Some(GenericEvent::Instructions)
+    );
+    assert_eq!(ArchEvent::TopdownSlots.generic(), None);
+}
+
+#[test]
+fn test_current() {
+    if let Some(info) = PmuInfo::current() {
+        assert_eq!(Some(info.cpu()), CpuId::current().as_ref());
+        assert!(info.gp_counter_width() <= 64);
+    }
+}
@@ -0,0 +1,150 @@
+use std::thread;
+use std::time::Duration;
+
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_poll_counter() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![5, 10, 25, 45, 70, 100]);
+
+    let counter = sim
+        .builder()
+        .attach_to(vec![42])
+        .allocate("instructions")
+        .unwrap();
+
+    let mut poller = Poller::from(counter);
+    poller.set_interval(Duration::from_millis(1));
+    assert_eq!(poller.labels(), &["value"]);
+
+    let handle = poller.start().expect("failed to start poller");
+    assert!(sim.calls().contains(&Call::Start(0)));
+
+    // Wait for the scripted values to be consumed.
+    while handle.samples().len() < 5 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    handle.stop();
+    assert!(sim.calls().contains(&Call::Stop(0)));
+
+    let samples = poller.drain();
+    let deltas: Vec<u64> = samples.iter().take(5).map(|s| s.value()).collect();
+    assert_eq!(deltas, vec![5, 15, 20, 25, 30]);
+
+    // The deltas add up to the total counted since starting.
+    assert_eq!(samples.iter().map(|s| s.value()).sum::<u64>(), 95);
+
+    // Samples are ordered, and their intervals cover the polling time.
+    for pair in samples.windows(2) {
+        assert!(pair[0].at() <= pair[1].at());
+        assert_eq!(pair[1].at() - pair[0].at(), pair[1].interval());
+    }
+    assert!(samples[0].interval() <= samples[0].at());
+
+    assert!(poller.samples().is_empty());
+    assert_eq!(poller.dropped(), 0);
+}
+
+#[test]
+fn test_poll_group() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![0, 100]);
+    sim.script_reads("cycles", vec![0, 50]);
+
+    let group = sim
+        .builder()
+        .attach_to(vec![42])
+        .group(vec!["instructions", "cycles"])
+        .unwrap();
+
+    let mut poller = Poller::from(group);
+    poller.set_interval(Duration::from_secs(3600));
+
+    sim.clear_calls();
+    poller.start().unwrap().stop();
+    assert_eq!(
+        sim.calls(),
+        vec![
+            Call::Read(0),
+            Call::Read(1),
+            Call::Start(0),
+            Call::Start(1),
+            Call::Stop(0),
+            Call::Stop(1),
+            Call::Read(0),
+            Call::Read(1),
+        ]
+    );
+
+    // Only the final sample is recorded.
+    let samples = poller.samples();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].get("instructions"), Some(100));
+    assert_eq!(samples[0].get("cycles"), Some(50));
+    assert!(samples[0]
+        .to_string()
+        .ends_with(": instructions: 100, cycles: 50"));
+}
+
+#[test]
+fn test_poll_capacity() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", 0..100);
+
+    let counter = sim.builder().allocate("instructions").unwrap();
+
+    let mut poller = Poller::from(counter);
+    poller.set_interval(Duration::from_micros(100));
+    poller.set_capacity(3);
+
+    let handle = poller.start().unwrap();
+    while handle.dropped() < 5 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    // Draining while running makes room for new samples.
+    assert_eq!(handle.drain().len(), 3);
+    handle.stop();
+
+    assert!(poller.samples().len() <= 3);
+
+    poller.set_capacity(1);
+    assert_eq!(poller.samples().len(), 1);
+}
+
+#[test]
+fn test_poll_read_errors_skipped() {
+    let sim = Simulated::default();
+    sim.script_reads("instructions", vec![0, 10, 20]);
+
+    let counter = sim.builder().allocate("instructions").unwrap();
+
+    let mut poller = Poller::from(counter);
+    poller.set_interval(Duration::from_secs(3600));
+
+    let handle = poller.start().unwrap();
+    sim.fail_next(Op::Read, libc::EIO);
+    handle.stop();
+
+    // The failed final read records no sample.
+    assert!(poller.samples().is_empty());
+
+    // Restarting records the delta since the restart.
+    poller.start().unwrap().stop();
+    let samples = poller.drain();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].value(), 10);
+}
+
+#[test]
+fn test_poll_start_error() {
+    let sim = Simulated::default();
+    let counter = sim.builder().allocate("instructions").unwrap();
+
+    let mut poller = Poller::from(counter);
+    sim.fail_next(Op::Start, libc::ENXIO);
+    let err = poller.start().unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::BadScope);
+}
@@ -0,0 +1,111 @@
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_config() {
+    let raw = RawEvent::new(0xc3, 0x01).cmask(1).edge();
+    assert_eq!(raw.config(), 0x0104_01c3);
+    assert_eq!(raw.to_string(), "r10401c3");
+
+    let raw = RawEvent::new(0x3c, 0).any_thread();
+    assert_eq!(raw.config(), 0x0020_003c);
+
+    // The upper bits of AMD event codes are placed above the counter mask.
+    let raw = RawEvent::new(0x1d0, 0x02);
+    assert_eq!(raw.config(), 0x1_0000_02d0);
+
+    // Event codes are at most 12 bits.
+    assert_eq!(RawEvent::new(0xf0c0, 0).event_select(), 0xc0);
+}
+
+#[test]
+fn test_from_config() {
+    let cases = vec![
+        RawEvent::new(0xc0, 0),
+        RawEvent::new(0x0e, 0x01).cmask(1).inv(),
+        RawEvent::new(0xc3, 0x01).cmask(1).edge(),
+        RawEvent::new(0x3c, 0).any_thread(),
+        RawEvent::new(0x1d0, 0xff)
+            .cmask(0xff)
+            .inv()
+            .edge()
+            .any_thread(),
+    ];
+
+    for raw in cases {
+        assert_eq!(RawEvent::from_config(raw.config()), raw);
+    }
+
+    let raw = RawEvent::from_config(0x0180_010e);
+    assert_eq!(raw.event_select(), 0x0e);
+    assert_eq!(raw.unit_mask(), 0x01);
+    assert_eq!(raw.counter_mask(), 1);
+    assert!(raw.is_inverted());
+    assert!(!raw.is_edge());
+    assert!(!raw.is_any_thread());
+}
+
+#[test]
+fn test_spec() {
+    let spec = EventSpec::from(RawEvent::new(0xc0, 0)).os();
+    assert_eq!(spec.to_string(), "rc0,os");
+    assert_eq!(EventSpec::parse("rc0,os").unwrap(), spec);
+}
+
+#[test]
+fn test_from_spec() {
+    let raw = RawEvent::new(0x1d0, 0x02).cmask(3).edge();
+    let spec = EventSpec::from(raw).os();
+    assert_eq!(RawEvent::from_spec(&spec), Some(raw));
+
+    // Leading zeros and upper case digits are accepted.
+    assert_eq!(
+        RawEvent::from_spec(&EventSpec::new("r00C0")),
+        Some(RawEvent::new(0xc0, 0))
+    );
+
+    for name in &["r", "rfo", "r-1", "r10000000000000000", "r4000c0", "c0"] {
+        assert_eq!(
+            RawEvent::from_spec(&EventSpec::new(*name)),
+            None,
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_allocate() {
+    let sim = Simulated::default();
+    let builder = sim.builder();
+
+    builder.allocate(RawEvent::new(0xc0, 0)).unwrap();
+    builder
+        .group(vec![RawEvent::new(0xc0, 0), RawEvent::new(0x3c, 0)])
+        .unwrap();
+
+    let events: Vec<String> = sim.allocations().into_iter().map(|s| s.event).collect();
+    assert_eq!(events, vec!["rc0", "rc0", "r3c"]);
+}
+
+#[test]
+#[cfg(target_os = "freebsd")]
+fn test_allocate_libpmc() {
+    let builder = CounterBuilder::default().attach_to(vec![0]);
+
+    // Retired instructions on both Intel and AMD.
+    let mut counter = builder
+        .allocate(EventSpec::from(RawEvent::new(0xc0, 0)).usr())
+        .expect("failed to allocate raw event");
+
+    let handle = counter.start().expect("failed to start counter");
+    let v: u64 = (0..1000).sum();
+    assert_eq!(v, 499500);
+    handle.stop();
+    assert!(counter.read().unwrap() > 0);
+
+    // Configs setting bits a RawEvent does not encode are passed to libpmc
+    // unchanged, which has no such event.
+    let err = builder.allocate("r4000c0").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::AllocInit);
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+use pmc::backend::*;
+use pmc::*;
+
+fn log_file(name: &str) -> File {
+    let path = std::env::temp_dir().join(format!("pmc-rs-{}-{}.log", name, std::process::id()));
+    File::create(path).expect("failed to create log file")
+}
+
+#[test]
+fn test_process_sampler() {
+    let sim = Simulated::default();
+
+    let mut sampler = sim
+        .builder()
+        .attach_to(vec![42])
+        .set_sample_rate(1000)
+        .sampler("inst_retired.any", log_file("process"))
+        .expect("failed to allocate sampler");
+
+    sampler.start().expect("failed to start sampler").stop();
+    sampler.flush().expect("failed to flush log");
+
+    let spec = sim.allocations()[0].clone();
+    assert_eq!(spec.mode, Mode::ProcessSampling);
+    assert_eq!(spec.cpu, CPU_ANY);
+    assert_eq!(spec.sample_rate, 1000);
+
+    sim.clear_calls();
+    drop(sampler);
+
+    // The log is flushed, and closed after the PMC is released.
+    assert_eq!(
+        sim.calls(),
+        vec![
+            Call::FlushLog,
+            Call::Detach(0, 42),
+            Call::Release(0),
+            Call::CloseLog
+        ]
+    );
+}
+
+#[test]
+fn test_system_sampler() {
+    let sim = Simulated::default();
+
+    let sampler = sim
+        .builder()
+        .sampler("inst_retired.any", log_file("system"))
+        .expect("failed to allocate sampler");
+
+    let spec = sim.allocations()[0].clone();
+    assert_eq!(spec.mode, Mode::SystemSampling);
+    assert_eq!(spec.cpu, 0);
+    assert_eq!(spec.sample_rate, DEFAULT_SAMPLE_RATE);
+
+    assert!(sim
+        .calls()
+        .contains(&Call::ConfigureLog(sampler.log().as_raw_fd())));
+}
+
+#[test]
+fn test_counter_has_no_sample_rate() {
+    let sim = Simulated::default();
+
+    sim.builder()
+        .set_sample_rate(1000)
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    let spec = sim.allocations()[0].clone();
+    assert_eq!(spec.mode, Mode::SystemCounting);
+    assert_eq!(spec.sample_rate, 0);
+}
+
+#[test]
+fn test_configure_log_error() {
+    let sim = Simulated::default();
+    sim.fail_next(Op::ConfigureLog, libc::EPERM);
+
+    let err = sim
+        .builder()
+        .sampler("inst_retired.any", log_file("error"))
+        .expect_err("expected to fail allocating sampler");
+
+    assert_eq!(err.kind(), &ErrorKind::Forbidden);
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_start_without_log() {
+    let sim = Simulated::default();
+
+    let mut sampler = sim
+        .builder()
+        .sampler("inst_retired.any", log_file("closed"))
+        .expect("failed to allocate sampler");
+
+    // Closing the log out from under the sampler causes start to fail.
+    sim.close_log().unwrap();
+
+    let err = sampler.start().expect_err("expected to fail starting sampler");
+    assert_eq!(err.kind(), &ErrorKind::LogFileRequired);
+}
+
+#[test]
+fn test_samplers_share_log() {
+    let sim = Simulated::default();
+    let log = log_file("shared");
+
+    let first = sim
+        .builder()
+        .sampler("inst_retired.any", log.try_clone().unwrap())
+        .expect("failed to allocate sampler");
+
+    // A second sampler cannot redirect the samples to another file.
+    let err = sim
+        .builder()
+        .sampler("inst_retired.any", log_file("other"))
+        .expect_err("expected to fail allocating sampler");
+    assert_eq!(err.kind(), &ErrorKind::LogFileInUse);
+    assert_eq!(sim.allocated(), 1);
+
+    // But can share the same file.
+    let mut second = sim
+        .builder()
+        .sampler("inst_retired.any", log)
+        .expect("failed to allocate sampler");
+
+    // Dropping the first sampler leaves the log open for the second.
+    drop(first);
+    second.start().expect("failed to start sampler").stop();
+    second.flush().expect("failed to flush log");
+
+    drop(second);
+    assert!(sim.flush_log().is_err());
+
+    // Once closed, another file can be used.
+    sim.builder()
+        .sampler("inst_retired.any", log_file("other"))
+        .expect("failed to allocate sampler");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_perf_sampler_unsupported() {
+    let err = CounterBuilder::default()
+        .attach_to(vec![0])
+        .sampler(SoftwareEvent::TaskClock, log_file("perf"))
+        .expect_err("expected to fail allocating sampler");
+
+    assert_eq!(err.kind(), &ErrorKind::Unsupported);
+}
@@ -0,0 +1,233 @@
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_scripted_reads() {
+    let sim = Simulated::default();
+    sim.script_reads("inst_retired.any", vec![1, 10, 100]);
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![0])
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    let handle = counter.start().expect("failed to start counter");
+    assert_eq!(handle.read().unwrap(), 1);
+    assert_eq!(handle.read().unwrap(), 10);
+    assert_eq!(handle.read().unwrap(), 100);
+
+    // Once exhausted, the last value is returned
+    assert_eq!(handle.read().unwrap(), 100);
+    handle.stop();
+
+    // Counters for other events are not affected
+    let other = sim
+        .builder()
+        .attach_to(vec![0])
+        .allocate("ex_ret_instr")
+        .expect("failed to allocate PMC");
+    assert_eq!(other.read().unwrap(), 0);
+}
+
+#[test]
+fn test_set_counter() {
+    let sim = Simulated::default();
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![0])
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    counter.set(42).expect("failed to set counter");
+    assert_eq!(counter.read().unwrap(), 42);
+    assert_eq!(counter.set(4242).unwrap(), 42);
+}
+
+#[test]
+fn test_call_sequence() {
+    let sim = Simulated::default();
+
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![0, 42])
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    counter.start().expect("failed to start counter").stop();
+    drop(counter);
+
+    let spec = sim.allocations()[0].clone();
+    assert_eq!(spec.event, "inst_retired.any");
+    assert_eq!(spec.mode, Mode::ProcessCounting);
+    assert_eq!(spec.cpu, CPU_ANY);
+
+    assert_eq!(
+        sim.calls(),
+        vec![
+            Call::Init,
+            Call::Allocate(spec),
+            Call::Attach(0, 0),
+            Call::Attach(0, 42),
+            Call::Start(0),
+            Call::Stop(0),
+            // PID 0 is never detached
+            Call::Detach(0, 42),
+            Call::Release(0),
+        ]
+    );
+    assert_eq!(sim.allocated(), 0);
+}
+
+#[test]
+fn test_system_scope_defaults_to_cpu_0() {
+    let sim = Simulated::default();
+
+    sim.builder()
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    let spec = &sim.allocations()[0];
+    assert_eq!(spec.mode, Mode::SystemCounting);
+    assert_eq!(spec.cpu, 0);
+}
+
+#[test]
+fn test_init_errors() {
+    let cases = vec![
+        (libc::ENOENT, ErrorKind::Init),
+        (libc::ENXIO, ErrorKind::Unsupported),
+        (libc::ENOSYS, ErrorKind::Unsupported),
+        (libc::EPERM, ErrorKind::Forbidden),
+        (EPROGMISMATCH, ErrorKind::VersionMismatch),
+        (libc::EIO, ErrorKind::Unknown),
+    ];
+
+    for (errno, kind) in cases {
+        let sim = Simulated::default();
+        sim.fail_next(Op::Init, errno);
+
+        let err = sim
+            .builder()
+            .allocate("inst_retired.any")
+            .expect_err("expected to fail allocating PMC");
+
+        assert_eq!(err.kind(), &kind);
+        assert_eq!(sim.calls(), vec![Call::Init]);
+    }
+}
+
+#[test]
+fn test_allocate_errors() {
+    let cases = vec![
+        (libc::EINVAL, ErrorKind::AllocInit),
+        (libc::EPERM, ErrorKind::Forbidden),
+        (libc::EIO, ErrorKind::Unknown),
+    ];
+
+    for (errno, kind) in cases {
+        let sim = Simulated::default();
+        sim.fail_next(Op::Allocate, errno);
+
+        let err = sim
+            .builder()
+            .allocate("inst_retired.any")
+            .expect_err("expected to fail allocating PMC");
+
+        assert_eq!(err.kind(), &kind);
+        assert_eq!(sim.allocated(), 0);
+    }
+}
+
+#[test]
+fn test_attach_errors() {
+    let cases = vec![
+        (libc::EEXIST, ErrorKind::AlreadyAttached),
+        (libc::EPERM, ErrorKind::Forbidden),
+        (libc::EINVAL, ErrorKind::BadTarget),
+        (libc::ESRCH, ErrorKind::BadTarget),
+        (libc::EIO, ErrorKind::Unknown),
+    ];
+
+    for (errno, kind) in cases {
+        let sim = Simulated::default();
+        sim.fail_next(Op::Attach, errno);
+
+        let err = sim
+            .builder()
+            .attach_to(vec![42])
+            .allocate("inst_retired.any")
+            .expect_err("expected to fail allocating PMC");
+
+        assert_eq!(err.kind(), &kind);
+
+        // The failed counter must have been released.
+        assert_eq!(sim.allocated(), 0);
+    }
+}
+
+#[test]
+fn test_attach_failure_detaches() {
+    let sim = Simulated::default();
+
+    // The second attach to the same PID fails
+    let err = sim
+        .builder()
+        .attach_to(vec![42, 42])
+        .allocate("inst_retired.any")
+        .expect_err("expected to fail allocating PMC");
+
+    assert_eq!(err.kind(), &ErrorKind::AlreadyAttached);
+    assert_eq!(
+        &sim.calls()[2..],
+        &[
+            Call::Attach(0, 42),
+            Call::Attach(0, 42),
+            Call::Detach(0, 42),
+            Call::Release(0),
+        ]
+    );
+}
+
+#[test]
+fn test_start_errors() {
+    let cases = vec![
+        (EDOOFUS, ErrorKind::LogFileRequired),
+        (libc::ENXIO, ErrorKind::BadScope),
+        (libc::EIO, ErrorKind::Unknown),
+    ];
+
+    for (errno, kind) in cases {
+        let sim = Simulated::default();
+        sim.fail_next(Op::Start, errno);
+
+        let mut counter = sim
+            .builder()
+            .attach_to(vec![42])
+            .allocate("inst_retired.any")
+            .expect("failed to allocate PMC");
+
+        let err = counter.start().expect_err("expected to fail starting PMC");
+        assert_eq!(err.kind(), &kind);
+
+        // A failed start is not stopped
+        assert!(!sim.calls().iter().any(|c| c.op() == Op::Stop));
+    }
+}
+
+#[test]
+fn test_read_error() {
+    let sim = Simulated::default();
+    sim.fail_next(Op::Read, libc::EIO);
+
+    let counter = sim
+        .builder()
+        .attach_to(vec![42])
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    let err = counter.read().expect_err("expected to fail reading PMC");
+    assert_eq!(err.kind(), &ErrorKind::Unknown);
+    assert_eq!(counter.read().unwrap(), 0);
+}
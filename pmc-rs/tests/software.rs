@@ -0,0 +1,145 @@
+use std::thread;
+use std::time::Duration;
+
+use pmc::*;
+
+// Returns true if the test should be skipped, as perf events are unavailable
+// or forbidden by perf_event_paranoid.
+fn unavailable(err: &Error) -> bool {
+    matches!(err.kind(), ErrorKind::Forbidden | ErrorKind::Unsupported)
+}
+
+// Allocate `event`, or return None if perf events are unavailable.
+fn allocate(builder: CounterBuilder, event: impl Into<String>) -> Option<Counter> {
+    match builder.allocate(event) {
+        Ok(counter) => Some(counter),
+        Err(e) if unavailable(&e) => None,
+        Err(e) => panic!("failed to allocate PMC: {}", e),
+    }
+}
+
+#[test]
+fn test_page_faults() {
+    let mut counter = match allocate(
+        CounterBuilder::default().attach_to(vec![0]),
+        SoftwareEvent::PageFaults,
+    ) {
+        Some(c) => c,
+        None => return,
+    };
+
+    let handle = counter.start().expect("failed to start counter");
+
+    // Touch a freshly allocated buffer to generate page faults.
+    let mut buf = vec![0u8; 16 * 1024 * 1024];
+    for i in (0..buf.len()).step_by(4096) {
+        buf[i] = 1;
+    }
+    assert_eq!(buf.iter().filter(|&&v| v == 1).count(), buf.len() / 4096);
+
+    handle.stop();
+
+    assert!(counter.read().unwrap() > 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_task_clock() {
+    let mut counter = match allocate(
+        CounterBuilder::default().attach_to(vec![0]),
+        SoftwareEvent::TaskClock,
+    ) {
+        Some(c) => c,
+        None => return,
+    };
+
+    let handle = counter.start().expect("failed to start counter");
+
+    let mut last: u64 = 0;
+    for _ in 1..100 {
+        let now = handle.read().expect("unable to read counter");
+        if now < last {
+            panic!("counter decremented")
+        }
+        last = now;
+    }
+
+    handle.stop();
+
+    // A stopped counter does not advance
+    let r1 = counter.read().unwrap();
+    let r2 = counter.read().unwrap();
+    assert!(r1 > 0);
+    assert_eq!(r1, r2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_context_switches() {
+    let mut counter = match allocate(
+        CounterBuilder::default().attach_to(vec![0]),
+        SoftwareEvent::ContextSwitches,
+    ) {
+        Some(c) => c,
+        None => return,
+    };
+
+    let handle = counter.start().expect("failed to start counter");
+
+    // Sleeping yields the CPU
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert!(handle.read().unwrap() >= 5);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_set_counter() {
+    let mut counter = match allocate(
+        CounterBuilder::default().attach_to(vec![0]),
+        SoftwareEvent::MajorPageFaults,
+    ) {
+        Some(c) => c,
+        None => return,
+    };
+
+    counter.set(42).expect("failed to set counter");
+    assert_eq!(counter.read().unwrap(), 42);
+    assert_eq!(counter.set(4242).unwrap(), 42);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_system_counter() {
+    let mut counter = match allocate(
+        CounterBuilder::default().set_cpu(CPU_ANY),
+        SoftwareEvent::CpuClock,
+    ) {
+        Some(c) => c,
+        None => return,
+    };
+
+    let handle = counter.start().expect("failed to start counter");
+    thread::sleep(Duration::from_millis(10));
+
+    assert!(handle.read().unwrap() > 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_measure_command_task_clock() {
+    let mut command = std::process::Command::new("sh");
+    command.arg("-c").arg("i=0; while [ $i -lt 1000 ]; do i=$((i+1)); done");
+
+    let (status, m) =
+        match CounterBuilder::default().measure_command(command, vec![SoftwareEvent::TaskClock]) {
+            Ok(v) => v,
+            Err(e) if unavailable(&e) => return,
+            Err(e) => panic!("failed to measure command: {}", e),
+        };
+
+    assert!(status.success());
+    assert!(m.value() > 0);
+}
@@ -0,0 +1,186 @@
+use pmc::backend::*;
+use pmc::*;
+
+fn parse_error(spec: &str) -> ParseError {
+    let err = EventSpec::parse(spec).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+
+    err.cause()
+        .and_then(|c| c.downcast_ref::<ParseError>())
+        .expect("missing parse error")
+        .clone()
+}
+
+#[test]
+fn test_parse() {
+    let spec = EventSpec::parse(
+        "uops_issued.any,usr,os,edge,inv,anythread,cmask=2,umask=0x41,threshold=10",
+    )
+    .expect("failed to parse spec");
+
+    assert_eq!(spec.name(), "uops_issued.any");
+    assert_eq!(
+        spec.qualifiers(),
+        &[
+            Qualifier::Usr,
+            Qualifier::Os,
+            Qualifier::Edge,
+            Qualifier::Inv,
+            Qualifier::AnyThread,
+            Qualifier::Cmask(2),
+            Qualifier::Umask(0x41),
+            Qualifier::Threshold(10),
+        ]
+    );
+
+    // Display round trips through the parser.
+    assert_eq!(
+        spec.to_string(),
+        "uops_issued.any,usr,os,edge,inv,anythread,cmask=2,umask=0x41,threshold=10"
+    );
+    assert_eq!(EventSpec::parse(&spec.to_string()).unwrap(), spec);
+
+    // Names without qualifiers
+    for name in &["branch-misses", "r01c2", "PAGE_FAULT.ALL", "a"] {
+        let spec = EventSpec::parse(name).unwrap();
+        assert_eq!(spec.name(), *name);
+        assert!(spec.qualifiers().is_empty());
+    }
+}
+
+#[test]
+fn test_parse_other_qualifiers() {
+    let spec = EventSpec::parse(
+        "mem_trans_retired.load_latency,usr,ldlat=3,offcore_rsp=0x10003c0091,pebs",
+    )
+    .expect("failed to parse spec");
+
+    assert_eq!(
+        spec.qualifiers(),
+        &[
+            Qualifier::Usr,
+            Qualifier::Other("ldlat=3".to_string()),
+            Qualifier::Other("offcore_rsp=0x10003c0091".to_string()),
+            Qualifier::Other("pebs".to_string()),
+        ]
+    );
+    assert_eq!(EventSpec::parse(&spec.to_string()).unwrap(), spec);
+}
+
+#[test]
+fn test_parse_pmu_terms() {
+    // Commas between the slashes are part of the name.
+    let spec = EventSpec::parse("cpu/event=0x3c,umask=0x0/,usr").expect("failed to parse spec");
+    assert_eq!(spec.name(), "cpu/event=0x3c,umask=0x0/");
+    assert_eq!(spec.qualifiers(), &[Qualifier::Usr]);
+
+    let spec = EventSpec::parse("cpu/event=0xcd,umask=0x1,ldlat=3/").unwrap();
+    assert_eq!(spec.name(), "cpu/event=0xcd,umask=0x1,ldlat=3/");
+    assert!(spec.qualifiers().is_empty());
+}
+
+#[test]
+fn test_builder() {
+    let spec = EventSpec::new("inst_retired.any")
+        .usr()
+        .umask(1)
+        .umask(3)
+        .edge();
+
+    // Setting a qualifier again replaces it.
+    assert_eq!(
+        spec.qualifiers(),
+        &[Qualifier::Usr, Qualifier::Umask(3), Qualifier::Edge]
+    );
+    assert_eq!(spec.to_string(), "inst_retired.any,usr,umask=0x3,edge");
+    assert_eq!(String::from(&spec), spec.to_string());
+}
+
+#[test]
+fn test_parse_errors() {
+    let cases = vec![
+        ("", "expected event name", 0, ""),
+        (",usr", "expected event name", 0, ","),
+        ("inst retired", "invalid character in event name", 4, " "),
+        ("inst\0", "invalid character in event name", 4, "\0"),
+        ("inst,", "expected qualifier", 5, ""),
+        ("inst,,usr", "expected qualifier", 5, ","),
+        ("inst,ban@nas", "invalid qualifier", 5, "ban@nas"),
+        ("inst,=3", "invalid qualifier", 5, "=3"),
+        ("inst,ldlat=", "expected qualifier value", 5, "ldlat="),
+        ("inst,ldlat=3,ldlat=4", "duplicate qualifier", 13, "ldlat=4"),
+        ("cpu/event=0x3c/,a b", "invalid qualifier", 16, "a b"),
+        ("cpu/umask=0 1/", "invalid character in event name", 11, " "),
+        ("inst,usr=1", "qualifier does not take a value", 5, "usr=1"),
+        ("inst,cmask", "expected qualifier value", 5, "cmask"),
+        ("inst,cmask=", "expected qualifier value", 5, "cmask="),
+        ("inst,umask=0xzz", "invalid number", 11, "0xzz"),
+        ("inst,cmask=256", "value out of range", 11, "256"),
+        ("inst,umask=0x1ff", "value out of range", 11, "0x1ff"),
+        ("inst,threshold=300", "value out of range", 15, "300"),
+        ("inst,usr,usr", "duplicate qualifier", 9, "usr"),
+    ];
+
+    for (spec, message, position, token) in cases {
+        let err = parse_error(spec);
+        assert_eq!(err.message(), message, "spec {:?}", spec);
+        assert_eq!(err.position(), position, "spec {:?}", spec);
+        assert_eq!(err.token(), token, "spec {:?}", spec);
+    }
+
+    assert_eq!(
+        parse_error("inst,ban@nas").to_string(),
+        "invalid qualifier at position 5 ('ban@nas')"
+    );
+
+    // The parse error is shown with the error it caused.
+    let err = EventSpec::parse("inst,ban@nas").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid event specification: invalid qualifier at position 5 ('ban@nas')"
+    );
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn test_allocate_validates_spec() {
+    let sim = Simulated::default();
+
+    let err = sim
+        .builder()
+        .allocate("inst_retired.any,ban@nas")
+        .unwrap_err();
+
+    // Rejected before calling into the backend.
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+    assert!(sim.calls().is_empty());
+
+    // As are built specs with out of range values.
+    let err = sim
+        .builder()
+        .allocate(EventSpec::new("inst_retired.any").cmask(256))
+        .unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidEventSpec);
+    assert!(sim.calls().is_empty());
+
+    sim.builder()
+        .allocate(EventSpec::new("inst_retired.any").os())
+        .expect("failed to allocate");
+
+    assert_eq!(sim.allocations()[0].event, "inst_retired.any,os");
+}
+
+#[test]
+fn test_allocate_other_qualifier() {
+    let sim = Simulated::default();
+
+    sim.builder()
+        .allocate("mem_trans_retired.load_latency,ldlat=3")
+        .expect("failed to allocate");
+
+    // Passed to the backend unchanged.
+    assert_eq!(
+        sim.allocations()[0].event,
+        "mem_trans_retired.load_latency,ldlat=3"
+    );
+}
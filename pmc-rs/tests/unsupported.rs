@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use pmc::backend::*;
+use pmc::*;
+
+#[test]
+fn test_unsupported_backend() {
+    let err = CounterBuilder::default()
+        .set_backend(Arc::new(Unsupported::default()))
+        .attach_to(vec![0])
+        .allocate("inst_retired.any")
+        .expect_err("expected to fail allocating PMC");
+
+    assert_eq!(err.kind(), &ErrorKind::Unsupported);
+    assert!(err
+        .to_string()
+        .starts_with("unsupported by the PMC backend: "));
+}
+
+#[test]
+fn test_unsupported_after_allocation() {
+    // A backend that becomes unavailable after allocating a counter reports
+    // Unsupported from every entry point.
+    let sim = Simulated::default();
+    let mut counter = sim
+        .builder()
+        .attach_to(vec![0])
+        .allocate("inst_retired.any")
+        .expect("failed to allocate PMC");
+
+    sim.fail_next(Op::Read, libc::ENOSYS);
+    sim.fail_next(Op::Write, libc::ENOSYS);
+    sim.fail_next(Op::Start, libc::ENOSYS);
+
+    assert_eq!(counter.read().unwrap_err().kind(), &ErrorKind::Unsupported);
+    assert_eq!(counter.set(42).unwrap_err().kind(), &ErrorKind::Unsupported);
+    assert_eq!(counter.start().unwrap_err().kind(), &ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_is_available() {
+    // The result depends on the host, but must not panic.
+    if !is_available() {
+        let err = CounterBuilder::default()
+            .allocate(SoftwareEvent::TaskClock)
+            .expect_err("expected to fail allocating PMC");
+        assert_ne!(err.kind(), &ErrorKind::AllocInit);
+    }
+}